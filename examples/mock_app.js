@@ -0,0 +1,21 @@
+const assert = require("assert");
+const { WriteConflictError } = require("../dist/error");
+const tikv = require("../dist/asynchronous");
+
+(async () => {
+  const raw = await new tikv.RawClient("mock://example");
+  await raw.put("k1", "v1", "default");
+  assert.equal(await raw.get("k1", "default"), "v1");
+  assert.equal(await raw.get("k1", "write"), undefined);
+
+  const client = await new tikv.TransactionClient("mock://example");
+  const txn1 = await client.begin(false);
+  const txn2 = await client.begin(false);
+  await txn1.put("k1", "v1");
+  await txn2.put("k1", "v2");
+  await txn1.commit();
+  await assert.rejects(txn2.commit(), WriteConflictError);
+
+  const snapshot = await client.snapshot(await client.current_timestamp(), false);
+  assert.equal(await snapshot.get("k1"), "v1");
+})();
@@ -0,0 +1,537 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::{
+    ops::{Bound, Range},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use tikv_client::{
+    BoundRange, ColumnFamily, Config, Error, Key, KvPair, Result, Timestamp, TransactionOptions,
+    Value,
+};
+
+use crate::{
+    locks::{range_keys, LockInfo, TikvLocks},
+    logger, mock,
+    region::{PdPlacement, PlacementBackend},
+    replica::ReplicaSnapshot,
+    store::Stores,
+};
+
+/// PD endpoints starting with this scheme are served by the in-process mock store.
+/// Clients connecting to the same `mock://<name>` share their data.
+pub const MOCK_SCHEME: &str = "mock://";
+
+#[async_trait]
+pub trait RawBackend: Send + Sync {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend>;
+    fn placement(&self) -> Arc<dyn PlacementBackend>;
+    async fn get(&self, key: Key) -> Result<Option<Value>>;
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>>;
+    async fn put(&self, key: Key, value: Value) -> Result<()>;
+    /// Put a value which TiKV deletes once `ttl_secs` have passed, or never if 0.
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()>;
+    /// The seconds left before the value of `key` expires, 0 if it never does, or `None` if
+    /// there is no value.
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>>;
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()>;
+    async fn delete(&self, key: Key) -> Result<()>;
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()>;
+    async fn delete_range(&self, range: BoundRange) -> Result<()>;
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>>;
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>>;
+    /// Scan several ranges at once, returning the results of each range.
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>>;
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>>;
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum>;
+    /// Send `request` to the raw coprocessor plugin `name` of every region covering `ranges`.
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>>;
+}
+
+/// The checksum of the pairs in a range, as computed by TiKV: the XOR of the CRC64 of each key
+/// followed by its value.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Checksum {
+    pub crc64_xor: u64,
+    pub total_kvs: u64,
+    pub total_bytes: u64,
+}
+
+/// The response of the coprocessor of a region, and the parts of the requested ranges in it.
+#[derive(Clone, Debug)]
+pub struct CoprocessorResponse {
+    pub data: Vec<u8>,
+    pub ranges: Vec<Range<Key>>,
+}
+
+/// A write a transaction will send at commit.
+#[derive(Clone, Debug)]
+pub enum PendingMutation {
+    Put(Key, Value),
+    Insert(Key, Value),
+    Delete(Key),
+    Lock(Key),
+}
+
+impl PendingMutation {
+    pub fn key(&self) -> &Key {
+        match self {
+            PendingMutation::Put(key, _)
+            | PendingMutation::Insert(key, _)
+            | PendingMutation::Delete(key)
+            | PendingMutation::Lock(key) => key,
+        }
+    }
+
+    /// The size of the key and value written.
+    pub fn size(&self) -> usize {
+        match self {
+            PendingMutation::Put(_, value) | PendingMutation::Insert(_, value) => {
+                <&[u8]>::from(self.key()).len() + value.len()
+            }
+            PendingMutation::Delete(_) | PendingMutation::Lock(_) => {
+                <&[u8]>::from(self.key()).len()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TxnOptions {
+    pub pessimistic: bool,
+    pub read_only: bool,
+    /// Whether snapshots read from followers rather than leaders.
+    pub replica_read: bool,
+}
+
+impl TxnOptions {
+    pub fn to_tikv(self) -> TransactionOptions {
+        let options = if self.pessimistic {
+            TransactionOptions::new_pessimistic()
+        } else {
+            TransactionOptions::new_optimistic()
+        };
+        if self.read_only {
+            options.read_only()
+        } else {
+            options
+        }
+    }
+}
+
+#[async_trait]
+pub trait TransactionBackend: Send + Sync {
+    fn placement(&self) -> Arc<dyn PlacementBackend>;
+    async fn begin(&self, options: TxnOptions) -> Result<Box<dyn TransactionHandle>>;
+    fn snapshot(&self, timestamp: Timestamp, options: TxnOptions) -> Box<dyn SnapshotHandle>;
+    async fn current_timestamp(&self) -> Result<Timestamp>;
+    async fn gc(&self, safepoint: Timestamp) -> Result<bool>;
+    /// The locks in `range` of transactions started at or before `max_ts`, at most `limit`.
+    async fn scan_locks(
+        &self,
+        range: BoundRange,
+        max_ts: Timestamp,
+        limit: u32,
+    ) -> Result<Vec<LockInfo>>;
+    /// Resolve the expired locks in `range` of transactions started at or before `safepoint`,
+    /// returning those resolved. A dry run resolves none, returning those it would.
+    async fn cleanup_locks(
+        &self,
+        range: BoundRange,
+        safepoint: Timestamp,
+        dry_run: bool,
+    ) -> Result<Vec<LockInfo>>;
+}
+
+#[async_trait]
+pub trait TransactionHandle: Send {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>>;
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>>;
+    async fn key_exists(&mut self, key: Key) -> Result<bool>;
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>>;
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>>;
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>>;
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>>;
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()>;
+    async fn put(&mut self, key: Key, value: Value) -> Result<()>;
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()>;
+    async fn delete(&mut self, key: Key) -> Result<()>;
+    async fn commit(&mut self) -> Result<Option<Timestamp>>;
+
+    /// The timestamp the transaction reads at, known to the transactions of the backends, which
+    /// `gc::begin` registers once they have begun.
+    fn start_timestamp(&self) -> Result<Timestamp> {
+        Err(Error::Unimplemented)
+    }
+
+    /// Savepoints and pending mutations are kept by `buffer::WriteBuffer`, which wraps every
+    /// transaction begun.
+    async fn savepoint(&mut self, _name: String) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    async fn rollback_to(&mut self, _name: String) -> Result<()> {
+        Err(Error::Unimplemented)
+    }
+
+    async fn pending_mutations(&mut self) -> Result<Vec<PendingMutation>> {
+        Err(Error::Unimplemented)
+    }
+}
+
+#[async_trait]
+pub trait SnapshotHandle: Send {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>>;
+    async fn key_exists(&mut self, key: Key) -> Result<bool>;
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>>;
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>>;
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>>;
+}
+
+pub async fn connect_raw(pd_endpoint: String) -> Result<Arc<dyn RawBackend>> {
+    if let Some(name) = pd_endpoint.strip_prefix(MOCK_SCHEME) {
+        return Ok(Arc::new(mock::MockRawClient::new(mock::store(name))));
+    }
+    let config = Config::default();
+    let client = tikv_client::RawClient::new_with_config(
+        vec![pd_endpoint.clone()],
+        config.clone(),
+        Some(logger::slog_logger()),
+    )
+    .await?;
+    Ok(Arc::new(TikvClient {
+        client,
+        placement: Arc::new(PdPlacement::new(vec![pd_endpoint], config)),
+        locks: (),
+    }))
+}
+
+pub async fn connect_transaction(pd_endpoint: String) -> Result<Arc<dyn TransactionBackend>> {
+    if let Some(name) = pd_endpoint.strip_prefix(MOCK_SCHEME) {
+        return Ok(Arc::new(mock::MockTransactionClient::new(mock::store(
+            name,
+        ))));
+    }
+    let config = Config::default();
+    let client = tikv_client::TransactionClient::new_with_config(
+        vec![pd_endpoint.clone()],
+        config.clone(),
+        Some(logger::slog_logger()),
+    )
+    .await?;
+    let placement = Arc::new(PdPlacement::new(vec![pd_endpoint], config));
+    Ok(Arc::new(TikvClient {
+        client,
+        locks: Arc::new(TikvLocks::new(Arc::new(Stores::new(placement.clone())?))),
+        placement,
+    }))
+}
+
+/// A client of the Rust TiKV client, with a PD client of its own to locate keys, and for
+/// transactions, the connections to clean up locks with.
+pub struct TikvClient<C, L = ()> {
+    client: C,
+    placement: Arc<PdPlacement>,
+    locks: L,
+}
+
+#[async_trait]
+impl RawBackend for TikvClient<tikv_client::RawClient> {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(TikvClient {
+            client: self.client.with_cf(cf),
+            placement: self.placement.clone(),
+            locks: (),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.placement.clone()
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        self.client.get(key).await
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.client.batch_get(keys).await
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        self.client.put(key, value).await
+    }
+
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        self.client.put_with_ttl(key, value, ttl_secs).await
+    }
+
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        self.client.get_key_ttl_secs(key).await
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        self.client.batch_put(pairs).await
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.client.delete(key).await
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        self.client.batch_delete(keys).await
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        self.client.delete_range(range).await
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.client.scan(range, limit).await
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.client.scan_keys(range, limit).await
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        let pairs = self.client.batch_scan(ranges.clone(), each_limit).await?;
+        Ok(group_by_range(&ranges, pairs, each_limit, |pair| &pair.0))
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        let keys = self
+            .client
+            .batch_scan_keys(ranges.clone(), each_limit)
+            .await?;
+        Ok(group_by_range(&ranges, keys, each_limit, |key| key))
+    }
+
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        let checksum = self.client.checksum(range).await?;
+        Ok(Checksum {
+            crc64_xor: checksum.crc64_xor,
+            total_kvs: checksum.total_kvs,
+            total_bytes: checksum.total_bytes,
+        })
+    }
+
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        let responses = self
+            .client
+            .coprocessor(name, version, ranges, move |_region, _ranges| {
+                request.clone()
+            })
+            .await?;
+        Ok(responses
+            .into_iter()
+            .map(|(data, ranges)| CoprocessorResponse { data, ranges })
+            .collect())
+    }
+}
+
+/// TiKV returns the results of a batch scan as a single list, so give each range the results
+/// it contains, in order.
+fn group_by_range<T: Clone>(
+    ranges: &[BoundRange],
+    results: Vec<T>,
+    each_limit: u32,
+    key: impl Fn(&T) -> &Key,
+) -> Vec<Vec<T>> {
+    ranges
+        .iter()
+        .map(|range| {
+            let mut group: Vec<T> = results
+                .iter()
+                .filter(|result| contains(range, key(result)))
+                .cloned()
+                .collect();
+            group.sort_by(|a, b| key(a).cmp(key(b)));
+            group.truncate(each_limit as usize);
+            group
+        })
+        .collect()
+}
+
+pub fn contains(range: &BoundRange, key: &Key) -> bool {
+    let after_start = match &range.from {
+        Bound::Included(start) => key >= start,
+        Bound::Excluded(start) => key > start,
+        Bound::Unbounded => true,
+    };
+    // An empty end key means the end of the key space.
+    let before_end = match &range.to {
+        Bound::Included(end) => key <= end || <&[u8]>::from(end).is_empty(),
+        Bound::Excluded(end) => key < end || <&[u8]>::from(end).is_empty(),
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+#[async_trait]
+impl TransactionBackend for TikvClient<tikv_client::TransactionClient, Arc<TikvLocks>> {
+    async fn begin(&self, options: TxnOptions) -> Result<Box<dyn TransactionHandle>> {
+        let txn = self.client.begin_with_options(options.to_tikv()).await?;
+        Ok(Box::new(txn))
+    }
+
+    fn snapshot(&self, timestamp: Timestamp, options: TxnOptions) -> Box<dyn SnapshotHandle> {
+        if options.replica_read {
+            return Box::new(ReplicaSnapshot::new(self.locks.clone(), timestamp));
+        }
+        Box::new(self.client.snapshot(timestamp, options.to_tikv()))
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.placement.clone()
+    }
+
+    async fn current_timestamp(&self) -> Result<Timestamp> {
+        self.client.current_timestamp().await
+    }
+
+    async fn gc(&self, safepoint: Timestamp) -> Result<bool> {
+        self.client.gc(safepoint).await
+    }
+
+    async fn scan_locks(
+        &self,
+        range: BoundRange,
+        max_ts: Timestamp,
+        limit: u32,
+    ) -> Result<Vec<LockInfo>> {
+        let (start, end) = range_keys(range);
+        self.locks.scan(start, end, max_ts, limit).await
+    }
+
+    async fn cleanup_locks(
+        &self,
+        range: BoundRange,
+        safepoint: Timestamp,
+        dry_run: bool,
+    ) -> Result<Vec<LockInfo>> {
+        let (start, end) = range_keys(range);
+        let now = self.client.current_timestamp().await?;
+        self.locks
+            .cleanup(start, end, safepoint, now, dry_run)
+            .await
+    }
+}
+
+#[async_trait]
+impl TransactionHandle for tikv_client::Transaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        tikv_client::Transaction::get(self, key).await
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        tikv_client::Transaction::get_for_update(self, key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        tikv_client::Transaction::key_exists(self, key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        tikv_client::Transaction::batch_get(self, keys)
+            .await
+            .map(|kvpairs| kvpairs.collect())
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        tikv_client::Transaction::batch_get_for_update(self, keys).await
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        tikv_client::Transaction::scan(self, range, limit)
+            .await
+            .map(|kvpairs| kvpairs.collect())
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        tikv_client::Transaction::scan_keys(self, range, limit)
+            .await
+            .map(|keys| keys.collect())
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        tikv_client::Transaction::lock_keys(self, keys).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        tikv_client::Transaction::put(self, key, value).await
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        tikv_client::Transaction::insert(self, key, value).await
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        tikv_client::Transaction::delete(self, key).await
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        tikv_client::Transaction::commit(self).await
+    }
+
+    fn start_timestamp(&self) -> Result<Timestamp> {
+        Ok(tikv_client::Transaction::start_timestamp(self))
+    }
+}
+
+#[async_trait]
+impl SnapshotHandle for tikv_client::Snapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        tikv_client::Snapshot::get(self, key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        tikv_client::Snapshot::key_exists(self, key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        tikv_client::Snapshot::batch_get(self, keys)
+            .await
+            .map(|kvpairs| kvpairs.collect())
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        tikv_client::Snapshot::scan(self, range, limit)
+            .await
+            .map(|kvpairs| kvpairs.collect())
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        tikv_client::Snapshot::scan_keys(self, range, limit)
+            .await
+            .map(|keys| keys.collect())
+    }
+}
@@ -0,0 +1,500 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! The writes of a transaction as the binding sees them, since the Rust TiKV client keeps its
+//! buffer of mutations to itself.
+//!
+//! Writes sent to the transaction are remembered by key and kind only, so the pending mutations
+//! can be listed before commit, reading their values back from the buffer of the transaction.
+//! Savepoints undo the writes of a transaction back to a point without aborting it. The Rust
+//! TiKV client cannot take back a buffered mutation, so once a savepoint is set the writes after
+//! it are only kept in a log, and reads see them on top of the transaction. Rolling back to a
+//! savepoint truncates the log, and committing sends it to the transaction first. Writes of
+//! pessimistic transactions after a savepoint therefore take their locks at commit, and if one
+//! fails the transaction can't be committed any more, since only some of its writes were sent.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+};
+
+use async_trait::async_trait;
+use tikv_client::{BoundRange, Error, Key, KvPair, Result, Timestamp, Value};
+
+use crate::backend::{contains, PendingMutation, TransactionHandle};
+
+enum Mutation {
+    Put(Key, Value),
+    Insert(Key, Value),
+    Delete(Key),
+    Lock(Vec<Key>),
+}
+
+/// The kind of the last write sent for a key, its value being kept by the transaction.
+#[derive(Clone, Copy)]
+enum Sent {
+    Put,
+    Insert,
+    Delete,
+    Lock,
+}
+
+pub struct WriteBuffer {
+    inner: Box<dyn TransactionHandle>,
+    /// The writes since the first savepoint, in order, not yet sent to the transaction.
+    log: Vec<Mutation>,
+    /// The keys written by the writes sent to the transaction.
+    sent: BTreeMap<Key, Sent>,
+    /// Keys locked by reading them for update.
+    locked: BTreeSet<Key>,
+    /// The names of the savepoints, and the length of the log when each was set.
+    savepoints: Vec<(String, usize)>,
+    /// A write failed to be sent at commit, leaving the transaction with only some of them.
+    poisoned: bool,
+}
+
+impl WriteBuffer {
+    pub fn new(inner: Box<dyn TransactionHandle>) -> WriteBuffer {
+        WriteBuffer {
+            inner,
+            log: vec![],
+            sent: BTreeMap::new(),
+            locked: BTreeSet::new(),
+            savepoints: vec![],
+            poisoned: false,
+        }
+    }
+
+    /// The value last written to `key` in the log, `Some(None)` if it was deleted.
+    fn written(&self, key: &Key) -> Option<Option<Value>> {
+        self.log.iter().rev().find_map(|mutation| match mutation {
+            Mutation::Put(k, value) | Mutation::Insert(k, value) if k == key => {
+                Some(Some(value.clone()))
+            }
+            Mutation::Delete(k) if k == key => Some(None),
+            _ => None,
+        })
+    }
+
+    /// The values last written to the keys of `range` in the log.
+    fn written_in(&self, range: &BoundRange) -> BTreeMap<Key, Option<Value>> {
+        let mut written = BTreeMap::new();
+        for mutation in &self.log {
+            match mutation {
+                Mutation::Put(key, value) | Mutation::Insert(key, value)
+                    if contains(range, key) =>
+                {
+                    written.insert(key.clone(), Some(value.clone()));
+                }
+                Mutation::Delete(key) if contains(range, key) => {
+                    written.insert(key.clone(), None);
+                }
+                _ => {}
+            }
+        }
+        written
+    }
+
+    fn overlay(&self, pairs: Vec<KvPair>, keys: &[Key]) -> Vec<KvPair> {
+        let mut pairs: BTreeMap<Key, Value> = pairs
+            .into_iter()
+            .map(|KvPair(key, value)| (key, value))
+            .collect();
+        for key in keys {
+            match self.written(key) {
+                Some(Some(value)) => {
+                    pairs.insert(key.clone(), value);
+                }
+                Some(None) => {
+                    pairs.remove(key);
+                }
+                None => {}
+            }
+        }
+        pairs
+            .into_iter()
+            .map(|(key, value)| KvPair(key, value))
+            .collect()
+    }
+
+    fn check_poisoned(&self) -> Result<()> {
+        if self.poisoned {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "A write failed at commit, so the transaction can only be rolled back",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Send a write to the transaction, or log it if a savepoint may undo it.
+    async fn write(&mut self, mutation: Mutation) -> Result<()> {
+        self.check_poisoned()?;
+        if self.savepoints.is_empty() {
+            send(&mut *self.inner, &mutation).await?;
+            self.record_sent(&mutation);
+        } else {
+            self.log.push(mutation);
+        }
+        Ok(())
+    }
+
+    /// Locking a key already written leaves its kind.
+    fn record_sent(&mut self, mutation: &Mutation) {
+        let (key, sent) = match mutation {
+            Mutation::Put(key, _) => (key, Sent::Put),
+            Mutation::Insert(key, _) => (key, Sent::Insert),
+            Mutation::Delete(key) => (key, Sent::Delete),
+            Mutation::Lock(keys) => {
+                for key in keys {
+                    self.sent.entry(key.clone()).or_insert(Sent::Lock);
+                }
+                return;
+            }
+        };
+        self.sent.insert(key.clone(), sent);
+    }
+
+    /// The value the transaction buffered for `key`, which it reads without a request.
+    async fn sent_value(&mut self, key: &Key) -> Result<Value> {
+        Ok(self.inner.get(key.clone()).await?.unwrap_or_default())
+    }
+}
+
+async fn send(inner: &mut dyn TransactionHandle, mutation: &Mutation) -> Result<()> {
+    match mutation {
+        Mutation::Put(key, value) => inner.put(key.clone(), value.clone()).await,
+        Mutation::Insert(key, value) => inner.insert(key.clone(), value.clone()).await,
+        Mutation::Delete(key) => inner.delete(key.clone()).await,
+        Mutation::Lock(keys) => inner.lock_keys(keys.clone()).await,
+    }
+}
+
+#[async_trait]
+impl TransactionHandle for WriteBuffer {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        match self.written(&key) {
+            Some(value) => Ok(value),
+            None => self.inner.get(key).await,
+        }
+    }
+
+    /// The key is locked even if it was written after a savepoint.
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        let value = self.inner.get_for_update(key.clone()).await?;
+        self.locked.insert(key.clone());
+        Ok(self.written(&key).unwrap_or(value))
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        match self.written(&key) {
+            Some(value) => Ok(value.is_some()),
+            None => self.inner.key_exists(key).await,
+        }
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let unwritten = keys
+            .iter()
+            .filter(|key| self.written(key).is_none())
+            .cloned()
+            .collect();
+        let pairs = self.inner.batch_get(unwritten).await?;
+        Ok(self.overlay(pairs, &keys))
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get_for_update(keys.clone()).await?;
+        self.locked.extend(keys.iter().cloned());
+        Ok(self.overlay(pairs, &keys))
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let written = self.written_in(&range);
+        if written.is_empty() {
+            return self.inner.scan(range, limit).await;
+        }
+        // Each written key hides at most one pair of the transaction.
+        let limit_with_written = limit.saturating_add(written.len() as u32);
+        let mut pairs: BTreeMap<Key, Value> = self
+            .inner
+            .scan(range, limit_with_written)
+            .await?
+            .into_iter()
+            .map(|KvPair(key, value)| (key, value))
+            .collect();
+        for (key, value) in written {
+            match value {
+                Some(value) => pairs.insert(key, value),
+                None => pairs.remove(&key),
+            };
+        }
+        Ok(pairs
+            .into_iter()
+            .take(limit as usize)
+            .map(|(key, value)| KvPair(key, value))
+            .collect())
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        let written = self.written_in(&range);
+        if written.is_empty() {
+            return self.inner.scan_keys(range, limit).await;
+        }
+        let limit_with_written = limit.saturating_add(written.len() as u32);
+        let mut keys: BTreeSet<Key> = self
+            .inner
+            .scan_keys(range, limit_with_written)
+            .await?
+            .into_iter()
+            .collect();
+        for (key, value) in written {
+            match value {
+                Some(_) => keys.insert(key),
+                None => keys.remove(&key),
+            };
+        }
+        Ok(keys.into_iter().take(limit as usize).collect())
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        self.write(Mutation::Lock(keys)).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        self.write(Mutation::Put(key, value)).await
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        self.write(Mutation::Insert(key, value)).await
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        self.write(Mutation::Delete(key)).await
+    }
+
+    /// The writes of the log not sent are kept, so they are still listed if one fails.
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.check_poisoned()?;
+        let mut sent = 0;
+        let mut result = Ok(());
+        for mutation in &self.log {
+            result = send(&mut *self.inner, mutation).await;
+            if result.is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        let log: Vec<Mutation> = self.log.drain(..sent).collect();
+        for mutation in &log {
+            self.record_sent(mutation);
+        }
+        if let Err(err) = result {
+            self.poisoned = true;
+            return Err(err);
+        }
+        self.savepoints.clear();
+        self.inner.commit().await
+    }
+
+    /// The last write to each key wins, and keys only locked are listed as locks.
+    async fn pending_mutations(&mut self) -> Result<Vec<PendingMutation>> {
+        let mut sent = self.sent.clone();
+        for key in &self.locked {
+            sent.entry(key.clone()).or_insert(Sent::Lock);
+        }
+        let mut pending = BTreeMap::new();
+        for (key, sent) in sent {
+            let mutation = match sent {
+                Sent::Put => PendingMutation::Put(key.clone(), self.sent_value(&key).await?),
+                Sent::Insert => PendingMutation::Insert(key.clone(), self.sent_value(&key).await?),
+                Sent::Delete => PendingMutation::Delete(key.clone()),
+                Sent::Lock => PendingMutation::Lock(key.clone()),
+            };
+            pending.insert(key, mutation);
+        }
+        for mutation in &self.log {
+            match mutation {
+                Mutation::Put(key, value) => {
+                    pending.insert(
+                        key.clone(),
+                        PendingMutation::Put(key.clone(), value.clone()),
+                    );
+                }
+                Mutation::Insert(key, value) => {
+                    let insert = PendingMutation::Insert(key.clone(), value.clone());
+                    pending.insert(key.clone(), insert);
+                }
+                Mutation::Delete(key) => {
+                    pending.insert(key.clone(), PendingMutation::Delete(key.clone()));
+                }
+                Mutation::Lock(keys) => {
+                    for key in keys {
+                        pending
+                            .entry(key.clone())
+                            .or_insert_with(|| PendingMutation::Lock(key.clone()));
+                    }
+                }
+            }
+        }
+        Ok(pending.into_iter().map(|(_, mutation)| mutation).collect())
+    }
+
+    /// Setting a savepoint again moves it.
+    async fn savepoint(&mut self, name: String) -> Result<()> {
+        self.check_poisoned()?;
+        self.savepoints.retain(|(existing, _)| *existing != name);
+        self.savepoints.push((name, self.log.len()));
+        Ok(())
+    }
+
+    /// Discard the writes since the savepoint, and the savepoints set after it.
+    async fn rollback_to(&mut self, name: String) -> Result<()> {
+        self.check_poisoned()?;
+        let index = match self
+            .savepoints
+            .iter()
+            .position(|(existing, _)| *existing == name)
+        {
+            Some(index) => index,
+            None => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No savepoint named {}", name),
+                )))
+            }
+        };
+        self.log.truncate(self.savepoints[index].1);
+        self.savepoints.truncate(index + 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        backend::{TransactionBackend, TxnOptions},
+        mock::{MockStore, MockTransactionClient},
+        utils::RUNTIME,
+    };
+
+    fn client() -> MockTransactionClient {
+        MockTransactionClient::new(Arc::new(MockStore::default()))
+    }
+
+    async fn begin(client: &MockTransactionClient, pessimistic: bool) -> WriteBuffer {
+        let options = TxnOptions {
+            pessimistic,
+            read_only: false,
+            replica_read: false,
+        };
+        WriteBuffer::new(client.begin(options).await.unwrap())
+    }
+
+    fn key(key: &str) -> Key {
+        Key::from(key.to_owned())
+    }
+
+    #[test]
+    fn rollback_to_discards_later_writes() {
+        RUNTIME.block_on(async {
+            let client = client();
+            let mut txn = begin(&client, false).await;
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.savepoint("s".to_owned()).await.unwrap();
+            txn.put(key("a"), b"2".to_vec()).await.unwrap();
+            txn.put(key("b"), b"2".to_vec()).await.unwrap();
+            assert_eq!(txn.get(key("a")).await.unwrap(), Some(b"2".to_vec()));
+
+            txn.rollback_to("s".to_owned()).await.unwrap();
+            assert_eq!(txn.get(key("a")).await.unwrap(), Some(b"1".to_vec()));
+            assert_eq!(txn.get(key("b")).await.unwrap(), None);
+            txn.commit().await.unwrap();
+
+            let mut txn = begin(&client, false).await;
+            let pairs = txn.scan((key("")..).into(), 10).await.unwrap();
+            assert_eq!(pairs, vec![KvPair(key("a"), b"1".to_vec())]);
+        });
+    }
+
+    #[test]
+    fn rollback_to_drops_later_savepoints() {
+        RUNTIME.block_on(async {
+            let mut txn = begin(&client(), false).await;
+            txn.savepoint("outer".to_owned()).await.unwrap();
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.savepoint("inner".to_owned()).await.unwrap();
+            txn.delete(key("a")).await.unwrap();
+
+            txn.rollback_to("outer".to_owned()).await.unwrap();
+            assert!(txn.rollback_to("inner".to_owned()).await.is_err());
+            assert!(txn.pending_mutations().await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn pending_mutations_read_back_sent_writes() {
+        RUNTIME.block_on(async {
+            let mut txn = begin(&client(), false).await;
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.insert(key("b"), b"1".to_vec()).await.unwrap();
+            txn.lock_keys(vec![key("a"), key("c")]).await.unwrap();
+            assert!(txn.log.is_empty());
+
+            txn.savepoint("s".to_owned()).await.unwrap();
+            txn.delete(key("b")).await.unwrap();
+            let pending = txn.pending_mutations().await.unwrap();
+            assert!(
+                matches!(&pending[0], PendingMutation::Put(k, v) if *k == key("a") && v == b"1")
+            );
+            assert!(matches!(&pending[1], PendingMutation::Delete(k) if *k == key("b")));
+            assert!(matches!(&pending[2], PendingMutation::Lock(k) if *k == key("c")));
+            assert_eq!(pending.len(), 3);
+        });
+    }
+
+    #[test]
+    fn scan_overlays_unsent_writes() {
+        RUNTIME.block_on(async {
+            let client = client();
+            let mut txn = begin(&client, false).await;
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.put(key("b"), b"1".to_vec()).await.unwrap();
+            txn.commit().await.unwrap();
+
+            let mut txn = begin(&client, false).await;
+            txn.savepoint("s".to_owned()).await.unwrap();
+            txn.delete(key("a")).await.unwrap();
+            txn.put(key("c"), b"3".to_vec()).await.unwrap();
+            let keys = txn.scan_keys((key("")..).into(), 10).await.unwrap();
+            assert_eq!(keys, vec![key("b"), key("c")]);
+            let pairs = txn.scan((key("")..).into(), 1).await.unwrap();
+            assert_eq!(pairs, vec![KvPair(key("b"), b"1".to_vec())]);
+        });
+    }
+
+    #[test]
+    fn failed_commit_keeps_unsent_writes_and_poisons() {
+        RUNTIME.block_on(async {
+            let client = client();
+            let mut txn = begin(&client, true).await;
+            txn.savepoint("s".to_owned()).await.unwrap();
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.put(key("b"), b"1".to_vec()).await.unwrap();
+
+            let mut other = begin(&client, true).await;
+            other.get_for_update(key("b")).await.unwrap();
+            assert!(txn.commit().await.is_err());
+            assert_eq!(txn.pending_mutations().await.unwrap().len(), 2);
+
+            drop(other);
+            assert!(txn.commit().await.is_err());
+            assert!(txn.put(key("c"), b"1".to_vec()).await.is_err());
+
+            let mut txn = begin(&client, false).await;
+            assert_eq!(txn.get(key("a")).await.unwrap(), None);
+        });
+    }
+}
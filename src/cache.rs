@@ -0,0 +1,359 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Local caches of values read from TiKV.
+//!
+//! A raw client cache is a bounded LRU of values by column family and key. Its entries expire
+//! after a TTL, since other clients may write the keys, and writes through the caching client
+//! invalidate them at once. A read racing such a write may fetch the value from before it, so
+//! values are only cached if nothing was invalidated since their read began. A snapshot cache
+//! needs neither: the values of a snapshot never change.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lru::LruCache;
+use neon::prelude::*;
+use tikv_client::{BoundRange, ColumnFamily, Key, KvPair, Result, Value};
+
+use crate::{
+    backend::{contains, Checksum, CoprocessorResponse, RawBackend, SnapshotHandle},
+    region::PlacementBackend,
+    utils::js_millis_to_duration,
+};
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+pub struct Cache {
+    ttl: Option<Duration>,
+    /// Missing keys are cached as `None`.
+    entries: Mutex<LruCache<(String, Key), (Option<Value>, Instant)>>,
+    /// Counts invalidations, bumped while `entries` is locked.
+    epoch: AtomicU64,
+}
+
+impl Cache {
+    pub fn from_js<'a, C: Context<'a>>(cx: &mut C, options: Handle<JsObject>) -> NeonResult<Cache> {
+        let capacity = match options.get(cx, "capacity")?.downcast::<JsNumber, _>(cx) {
+            Ok(capacity) => capacity.value(cx) as usize,
+            Err(_) => DEFAULT_CAPACITY,
+        };
+        let ttl = match options.get(cx, "ttl_ms")?.downcast::<JsNumber, _>(cx) {
+            Ok(ttl_ms) => {
+                let ttl_ms = ttl_ms.value(cx).max(0.0);
+                Some(js_millis_to_duration(cx, "cache TTL", ttl_ms)?)
+            }
+            Err(_) => None,
+        };
+        Ok(Cache {
+            ttl,
+            entries: Mutex::new(LruCache::new(capacity.max(1))),
+            epoch: AtomicU64::new(0),
+        })
+    }
+
+    fn get(&self, cf: &str, key: &Key) -> Option<Option<Value>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = (cf.to_owned(), key.clone());
+        let expired = match entries.get(&entry) {
+            Some((value, cached_at)) if !self.is_expired(*cached_at) => return Some(value.clone()),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.pop(&entry);
+        }
+        None
+    }
+
+    /// To be taken before reading values to cache.
+    fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Cache a value read since `epoch`, unless a write may have made it stale meanwhile.
+    fn put(&self, epoch: u64, cf: &str, key: Key, value: Option<Value>) {
+        let mut entries = self.entries.lock().unwrap();
+        if self.epoch() == epoch {
+            entries.put((cf.to_owned(), key), (value, Instant::now()));
+        }
+    }
+
+    fn remove(&self, cf: &str, keys: &[Key]) {
+        let mut entries = self.entries.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        for key in keys {
+            entries.pop(&(cf.to_owned(), key.clone()));
+        }
+    }
+
+    fn remove_range(&self, cf: &str, range: &BoundRange) {
+        let mut entries = self.entries.lock().unwrap();
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let stale: Vec<_> = entries
+            .iter()
+            .map(|(entry, _)| entry)
+            .filter(|(entry_cf, key)| entry_cf == cf && contains(range, key))
+            .cloned()
+            .collect();
+        for entry in stale {
+            entries.pop(&entry);
+        }
+    }
+
+    fn is_expired(&self, cached_at: Instant) -> bool {
+        matches!(self.ttl, Some(ttl) if cached_at.elapsed() >= ttl)
+    }
+}
+
+pub struct CachedRaw {
+    pub inner: Arc<dyn RawBackend>,
+    pub cache: Arc<Cache>,
+    /// Keys of different column families are cached apart.
+    pub cf: String,
+}
+
+#[async_trait]
+impl RawBackend for CachedRaw {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(CachedRaw {
+            cf: format!("{:?}", cf),
+            inner: self.inner.with_cf(cf),
+            cache: self.cache.clone(),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.inner.placement()
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        if let Some(value) = self.cache.get(&self.cf, &key) {
+            return Ok(value);
+        }
+        let epoch = self.cache.epoch();
+        let value = self.inner.get(key.clone()).await?;
+        self.cache.put(epoch, &self.cf, key, value.clone());
+        Ok(value)
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let mut pairs = vec![];
+        let mut missing = vec![];
+        for key in keys {
+            match self.cache.get(&self.cf, &key) {
+                Some(Some(value)) => pairs.push(KvPair(key, value)),
+                Some(None) => {}
+                None => missing.push(key),
+            }
+        }
+        if !missing.is_empty() {
+            let epoch = self.cache.epoch();
+            let fetched = by_key(self.inner.batch_get(missing.clone()).await?);
+            for key in missing {
+                let value = fetched.get(&key).cloned();
+                self.cache.put(epoch, &self.cf, key.clone(), value.clone());
+                pairs.extend(value.map(|value| KvPair(key, value)));
+            }
+        }
+        Ok(pairs)
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        let result = self.inner.put(key.clone(), value).await;
+        self.cache.remove(&self.cf, &[key]);
+        result
+    }
+
+    /// The value isn't cached past the TTL of the cache, which may be longer than its own.
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        let result = self.inner.put_with_ttl(key.clone(), value, ttl_secs).await;
+        self.cache.remove(&self.cf, &[key]);
+        result
+    }
+
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        self.inner.get_key_ttl(key).await
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        let keys: Vec<Key> = pairs.iter().map(|KvPair(key, _)| key.clone()).collect();
+        let result = self.inner.batch_put(pairs).await;
+        self.cache.remove(&self.cf, &keys);
+        result
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        let result = self.inner.delete(key.clone()).await;
+        self.cache.remove(&self.cf, &[key]);
+        result
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        let result = self.inner.batch_delete(keys.clone()).await;
+        self.cache.remove(&self.cf, &keys);
+        result
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        let result = self.inner.delete_range(range.clone()).await;
+        self.cache.remove_range(&self.cf, &range);
+        result
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.scan(range, limit).await
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        self.inner.batch_scan(ranges, each_limit).await
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        self.inner.batch_scan_keys(ranges, each_limit).await
+    }
+
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        self.inner.checksum(range).await
+    }
+
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        self.inner.coprocessor(name, version, ranges, request).await
+    }
+}
+
+/// Shares the snapshot of the `Snapshot` it was created from, remembering the values it read.
+pub struct CachedSnapshot {
+    pub inner: Arc<tokio::sync::Mutex<Box<dyn SnapshotHandle>>>,
+    pub values: LruCache<Key, Option<Value>>,
+}
+
+#[async_trait]
+impl SnapshotHandle for CachedSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        if let Some(value) = self.values.get(&key) {
+            return Ok(value.clone());
+        }
+        let value = self.inner.lock().await.get(key.clone()).await?;
+        self.values.put(key, value.clone());
+        Ok(value)
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        if let Some(value) = self.values.get(&key) {
+            return Ok(value.is_some());
+        }
+        self.inner.lock().await.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let mut pairs = vec![];
+        let mut missing = vec![];
+        for key in keys {
+            match self.values.get(&key) {
+                Some(Some(value)) => pairs.push(KvPair(key, value.clone())),
+                Some(None) => {}
+                None => missing.push(key),
+            }
+        }
+        if !missing.is_empty() {
+            let fetched = by_key(self.inner.lock().await.batch_get(missing.clone()).await?);
+            for key in missing {
+                let value = fetched.get(&key).cloned();
+                self.values.put(key.clone(), value.clone());
+                pairs.extend(value.map(|value| KvPair(key, value)));
+            }
+        }
+        Ok(pairs)
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.lock().await.scan(range, limit).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.lock().await.scan_keys(range, limit).await
+    }
+}
+
+/// Keys missing from the result of a batch get are absent, and cached as such.
+fn by_key(pairs: Vec<KvPair>) -> HashMap<Key, Value> {
+    pairs
+        .into_iter()
+        .map(|KvPair(key, value)| (key, value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(ttl: Option<Duration>) -> Cache {
+        Cache {
+            ttl,
+            entries: Mutex::new(LruCache::new(2)),
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    fn key(key: &str) -> Key {
+        Key::from(key.to_owned())
+    }
+
+    #[test]
+    fn reads_racing_writes_are_not_cached() {
+        let cache = cache(None);
+        let epoch = cache.epoch();
+        cache.remove("Default", &[key("a")]);
+        cache.put(epoch, "Default", key("a"), Some(b"stale".to_vec()));
+        assert_eq!(cache.get("Default", &key("a")), None);
+
+        let epoch = cache.epoch();
+        cache.put(epoch, "Default", key("a"), Some(b"fresh".to_vec()));
+        assert_eq!(
+            cache.get("Default", &key("a")),
+            Some(Some(b"fresh".to_vec()))
+        );
+    }
+
+    #[test]
+    fn entries_are_kept_per_column_family() {
+        let cache = cache(None);
+        cache.put(cache.epoch(), "Default", key("a"), None);
+        assert_eq!(cache.get("Default", &key("a")), Some(None));
+        assert_eq!(cache.get("Write", &key("a")), None);
+        cache.remove_range("Default", &(key("a")..).into());
+        assert_eq!(cache.get("Default", &key("a")), None);
+    }
+
+    #[test]
+    fn entries_expire() {
+        let cache = cache(Some(Duration::from_secs(0)));
+        cache.put(cache.epoch(), "Default", key("a"), Some(vec![]));
+        assert_eq!(cache.get("Default", &key("a")), None);
+    }
+}
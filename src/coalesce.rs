@@ -0,0 +1,388 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Coalescing of gets issued close together into a single batch get.
+//!
+//! The first get of a batch waits for the window, then sends a batch get of every distinct key
+//! requested meanwhile and hands each caller the value of its key. Gets arriving after a batch
+//! is full, or after it was sent, start the next one. If the first get is dropped before sending
+//! the batch, the others fail.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use neon::prelude::*;
+use tikv_client::{BoundRange, ColumnFamily, Error, Key, KvPair, Result, Value};
+use tokio::sync::oneshot;
+
+use crate::{
+    backend::{Checksum, CoprocessorResponse, RawBackend},
+    region::PlacementBackend,
+    utils::js_millis_to_duration,
+};
+
+const DEFAULT_WINDOW: Duration = Duration::from_millis(1);
+const DEFAULT_MAX_BATCH: usize = 1024;
+
+#[derive(Clone, Copy)]
+pub struct Coalescing {
+    window: Duration,
+    max_batch: usize,
+}
+
+impl Coalescing {
+    pub fn from_js<'a, C: Context<'a>>(
+        cx: &mut C,
+        options: Handle<JsObject>,
+    ) -> NeonResult<Coalescing> {
+        let window = match options.get(cx, "window_ms")?.downcast::<JsNumber, _>(cx) {
+            Ok(window_ms) => {
+                let window_ms = window_ms.value(cx).max(0.0);
+                js_millis_to_duration(cx, "coalescing window", window_ms)?
+            }
+            Err(_) => DEFAULT_WINDOW,
+        };
+        let max_batch = match options.get(cx, "max_batch")?.downcast::<JsNumber, _>(cx) {
+            Ok(max_batch) => (max_batch.value(cx) as usize).max(1),
+            Err(_) => DEFAULT_MAX_BATCH,
+        };
+        Ok(Coalescing { window, max_batch })
+    }
+}
+
+/// The callers waiting for each key of a batch.
+type Waiters = HashMap<Key, Vec<oneshot::Sender<Result<Option<Value>>>>>;
+
+pub struct Coalescer {
+    coalescing: Coalescing,
+    /// The batch still open to new gets, by name.
+    batches: Mutex<HashMap<String, Arc<Mutex<Waiters>>>>,
+}
+
+impl Coalescer {
+    pub fn new(coalescing: Coalescing) -> Coalescer {
+        Coalescer {
+            coalescing,
+            batches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn coalescing(&self) -> Coalescing {
+        self.coalescing
+    }
+
+    /// Get `key` in the open batch named `batch`, sent with `batch_get` if this get opens it.
+    pub async fn get<F, Fut>(&self, batch: &str, key: Key, batch_get: F) -> Result<Option<Value>>
+    where
+        F: FnOnce(Vec<Key>) -> Fut,
+        Fut: Future<Output = Result<Vec<KvPair>>>,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let opened = {
+            let mut batches = self.batches.lock().unwrap();
+            let (waiters, opened) = match batches.get(batch) {
+                Some(waiters) => (waiters.clone(), None),
+                None => {
+                    let waiters = Arc::new(Mutex::new(Waiters::new()));
+                    batches.insert(batch.to_owned(), waiters.clone());
+                    (waiters.clone(), Some(waiters))
+                }
+            };
+            let mut waiters = waiters.lock().unwrap();
+            waiters.entry(key).or_default().push(sender);
+            if waiters.len() >= self.coalescing.max_batch {
+                batches.remove(batch);
+            }
+            opened
+        };
+
+        if let Some(waiters) = opened {
+            let open = OpenBatch {
+                batches: &self.batches,
+                name: batch,
+                waiters,
+            };
+            tokio::time::sleep(self.coalescing.window).await;
+            let waiters = open.close();
+            let keys = waiters.keys().cloned().collect();
+            match batch_get(keys).await {
+                Ok(pairs) => {
+                    let mut values: HashMap<Key, Value> = pairs
+                        .into_iter()
+                        .map(|KvPair(key, value)| (key, value))
+                        .collect();
+                    for (key, senders) in waiters {
+                        let value = values.remove(&key);
+                        for sender in senders {
+                            let _ = sender.send(Ok(value.clone()));
+                        }
+                    }
+                }
+                Err(err) => {
+                    for sender in waiters.into_iter().flat_map(|(_, senders)| senders) {
+                        let _ = sender.send(Err(copy_error(&err)));
+                    }
+                }
+            }
+        }
+        receiver.await.unwrap_or_else(|_| {
+            Err(Error::Io(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "The get which opened the batch was cancelled before sending it",
+            )))
+        })
+    }
+}
+
+/// A batch opened by a get, closed once the get sends it or is dropped. The gets of a batch
+/// closed without being sent fail.
+struct OpenBatch<'a> {
+    batches: &'a Mutex<HashMap<String, Arc<Mutex<Waiters>>>>,
+    name: &'a str,
+    waiters: Arc<Mutex<Waiters>>,
+}
+
+impl OpenBatch<'_> {
+    /// Take the gets of the batch, no longer open to new ones.
+    fn close(&self) -> Waiters {
+        let mut batches = self.batches.lock().unwrap();
+        if matches!(batches.get(self.name), Some(open) if Arc::ptr_eq(open, &self.waiters)) {
+            batches.remove(self.name);
+        }
+        std::mem::take(&mut *self.waiters.lock().unwrap())
+    }
+}
+
+impl Drop for OpenBatch<'_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Errors can't be cloned, so callers sharing a failed batch get copies of its error, keeping
+/// the kinds JS distinguishes.
+fn copy_error(err: &Error) -> Error {
+    match err {
+        Error::KeyError(e) => Error::KeyError(e.clone()),
+        Error::OperationAfterCommitError => Error::OperationAfterCommitError,
+        err => Error::Io(io::Error::new(io::ErrorKind::Other, err.to_string())),
+    }
+}
+
+pub struct CoalescedRaw {
+    pub inner: Arc<dyn RawBackend>,
+    pub coalescer: Arc<Coalescer>,
+    /// Gets of different column families go in different batches.
+    pub cf: String,
+}
+
+#[async_trait]
+impl RawBackend for CoalescedRaw {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(CoalescedRaw {
+            cf: format!("{:?}", cf),
+            inner: self.inner.with_cf(cf),
+            coalescer: self.coalescer.clone(),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.inner.placement()
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        let inner = self.inner.clone();
+        self.coalescer
+            .get(&self.cf, key, move |keys| async move {
+                inner.batch_get(keys).await
+            })
+            .await
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get(keys).await
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        self.inner.put(key, value).await
+    }
+
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        self.inner.put_with_ttl(key, value, ttl_secs).await
+    }
+
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        self.inner.get_key_ttl(key).await
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        self.inner.batch_put(pairs).await
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        self.inner.batch_delete(keys).await
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        self.inner.delete_range(range).await
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.scan(range, limit).await
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        self.inner.batch_scan(ranges, each_limit).await
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        self.inner.batch_scan_keys(ranges, each_limit).await
+    }
+
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        self.inner.checksum(range).await
+    }
+
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        self.inner.coprocessor(name, version, ranges, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::{self, Ready};
+    use tikv_client_proto::kvrpcpb;
+
+    fn coalescer(max_batch: usize) -> Coalescer {
+        Coalescer::new(Coalescing {
+            window: Duration::from_millis(10),
+            max_batch,
+        })
+    }
+
+    fn key(key: &str) -> Key {
+        Key::from(key.to_owned())
+    }
+
+    /// Records the keys of each batch get and finds every key but "missing", valued as itself.
+    fn batch_get(
+        calls: &Mutex<Vec<Vec<Key>>>,
+    ) -> impl FnOnce(Vec<Key>) -> Ready<Result<Vec<KvPair>>> + '_ {
+        move |mut keys| {
+            keys.sort();
+            calls.lock().unwrap().push(keys.clone());
+            let pairs = keys
+                .into_iter()
+                .filter(|k| *k != key("missing"))
+                .map(|k| KvPair(k.clone(), Vec::from(k)))
+                .collect();
+            future::ready(Ok(pairs))
+        }
+    }
+
+    #[test]
+    fn gets_in_a_window_share_one_batch_get() {
+        let coalescer = coalescer(DEFAULT_MAX_BATCH);
+        let calls = Mutex::new(vec![]);
+        let (a, b, again, missing) = crate::utils::RUNTIME.block_on(async {
+            tokio::join!(
+                coalescer.get("Default", key("a"), batch_get(&calls)),
+                coalescer.get("Default", key("b"), batch_get(&calls)),
+                coalescer.get("Default", key("a"), batch_get(&calls)),
+                coalescer.get("Default", key("missing"), batch_get(&calls)),
+            )
+        });
+        assert_eq!(a.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(b.unwrap(), Some(b"b".to_vec()));
+        assert_eq!(again.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(missing.unwrap(), None);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![vec![key("a"), key("b"), key("missing")]]
+        );
+    }
+
+    #[test]
+    fn full_batches_and_other_batch_names_are_sent_apart() {
+        let coalescer = coalescer(2);
+        let calls = Mutex::new(vec![]);
+        let (a, b, c, other) = crate::utils::RUNTIME.block_on(async {
+            tokio::join!(
+                coalescer.get("Default", key("a"), batch_get(&calls)),
+                coalescer.get("Default", key("b"), batch_get(&calls)),
+                coalescer.get("Default", key("c"), batch_get(&calls)),
+                coalescer.get("Write", key("a"), batch_get(&calls)),
+            )
+        });
+        assert_eq!(a.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(b.unwrap(), Some(b"b".to_vec()));
+        assert_eq!(c.unwrap(), Some(b"c".to_vec()));
+        assert_eq!(other.unwrap(), Some(b"a".to_vec()));
+        let mut calls = calls.into_inner().unwrap();
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec![vec![key("a")], vec![key("a"), key("b")], vec![key("c")]]
+        );
+    }
+
+    #[test]
+    fn every_get_of_a_failed_batch_fails() {
+        let coalescer = coalescer(DEFAULT_MAX_BATCH);
+        let fail = |_: Vec<Key>| async { Err(Error::KeyError(kvrpcpb::KeyError::default())) };
+        let (a, b) = crate::utils::RUNTIME.block_on(async {
+            tokio::join!(
+                coalescer.get("Default", key("a"), fail),
+                coalescer.get("Default", key("b"), fail),
+            )
+        });
+        assert!(matches!(a, Err(Error::KeyError(_))));
+        assert!(matches!(b, Err(Error::KeyError(_))));
+    }
+
+    #[test]
+    fn gets_of_a_batch_whose_opener_is_dropped_fail() {
+        let coalescer = coalescer(DEFAULT_MAX_BATCH);
+        let calls = Mutex::new(vec![]);
+        let (opener, joined) = crate::utils::RUNTIME.block_on(async {
+            tokio::join!(
+                tokio::time::timeout(
+                    Duration::from_millis(1),
+                    coalescer.get("Default", key("a"), batch_get(&calls)),
+                ),
+                coalescer.get("Default", key("b"), batch_get(&calls)),
+            )
+        });
+        assert!(opener.is_err());
+        assert!(joined.is_err());
+        assert!(calls.lock().unwrap().is_empty());
+    }
+}
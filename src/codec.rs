@@ -0,0 +1,505 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Conversion of values between JS and the bytes stored in TiKV.
+//!
+//! Values are encoded on the JS thread while reading the arguments of a request, and decoded
+//! there when its result is sent back, so custom codecs can be JS functions. Keys read are
+//! returned as the type chosen with the codec, strings by default, whatever their content.
+
+use std::sync::Arc;
+
+use neon::prelude::*;
+use rmpv::Value as MsgPack;
+use tikv_client::{Key, KvPair, Value};
+
+use crate::backend::{CoprocessorResponse, PendingMutation};
+use crate::utils::{
+    bytes_to_js_buffer, bytes_to_js_value, js_value_to_bytes, rust_keys_to_js_array,
+    rust_pairs_to_js_array, ToJS,
+};
+
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+/// How deep values can nest, as deep as `serde_json` decodes. Circular values reach it too.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone)]
+pub enum ValueCodec {
+    /// Values are strings stored as UTF-8.
+    String,
+    /// Values are Buffers stored as they are.
+    Buffer,
+    Json,
+    MessagePack,
+    /// `encode` turns a value into a Buffer or string, and `decode` turns a Buffer back.
+    Custom {
+        encode: Arc<Root<JsFunction>>,
+        decode: Arc<Root<JsFunction>>,
+    },
+}
+
+impl Default for ValueCodec {
+    fn default() -> ValueCodec {
+        ValueCodec::String
+    }
+}
+
+/// The type keys read are returned as.
+#[derive(Clone, Copy)]
+pub enum KeyType {
+    /// Keys which are not valid UTF-8 fail to be returned.
+    String,
+    Buffer,
+}
+
+impl Default for KeyType {
+    fn default() -> KeyType {
+        KeyType::String
+    }
+}
+
+impl KeyType {
+    pub fn from_js<'a, C: Context<'a>>(cx: &mut C, name: Handle<JsString>) -> NeonResult<KeyType> {
+        match name.value(cx).as_str() {
+            "string" => Ok(KeyType::String),
+            "buffer" => Ok(KeyType::Buffer),
+            name => cx.throw_error(format!("Unknown key type: {}", name)),
+        }
+    }
+}
+
+/// How a client converts the values it writes and reads, and the keys it reads.
+#[derive(Clone, Default)]
+pub struct Codec {
+    values: ValueCodec,
+    keys: KeyType,
+}
+
+impl Codec {
+    /// This codec with the values codec given by name, or as an object with `encode` and
+    /// `decode` functions.
+    pub fn with_values<'a, C: Context<'a>>(
+        &self,
+        cx: &mut C,
+        codec: Handle<JsValue>,
+    ) -> NeonResult<Codec> {
+        Ok(Codec {
+            values: ValueCodec::from_js(cx, codec)?,
+            keys: self.keys,
+        })
+    }
+
+    pub fn with_keys(&self, keys: KeyType) -> Codec {
+        Codec {
+            values: self.values.clone(),
+            keys,
+        }
+    }
+
+    pub fn encode<'a, C: Context<'a>>(
+        &self,
+        cx: &mut C,
+        value: Handle<JsValue>,
+    ) -> NeonResult<Vec<u8>> {
+        self.values.encode(cx, value)
+    }
+
+    pub fn decode<'a, C: Context<'a>>(&self, cx: &mut C, bytes: Vec<u8>) -> JsResult<'a, JsValue> {
+        self.values.decode(cx, bytes)
+    }
+
+    pub fn equal(&self, a: &[u8], b: &[u8]) -> bool {
+        self.values.equal(a, b)
+    }
+
+    pub fn key<'a, C: Context<'a>>(&self, cx: &mut C, key: Vec<u8>) -> JsResult<'a, JsValue> {
+        match self.keys {
+            KeyType::String => match String::from_utf8(key) {
+                Ok(key) => Ok(cx.string(key).upcast()),
+                Err(_) => cx.throw_type_error(
+                    "A key read is not valid UTF-8, read it with the \"buffer\" key type",
+                ),
+            },
+            KeyType::Buffer => Ok(bytes_to_js_buffer(cx, &key)?.upcast()),
+        }
+    }
+
+    pub fn decoded<T>(&self, values: T) -> Decoded<T> {
+        Decoded {
+            codec: self.clone(),
+            values,
+        }
+    }
+}
+
+impl ValueCodec {
+    /// Read a codec given by name, or as an object with `encode` and `decode` functions.
+    fn from_js<'a, C: Context<'a>>(cx: &mut C, codec: Handle<JsValue>) -> NeonResult<ValueCodec> {
+        if let Ok(name) = codec.downcast::<JsString, _>(cx) {
+            return match name.value(cx).as_str() {
+                "string" => Ok(ValueCodec::String),
+                "buffer" => Ok(ValueCodec::Buffer),
+                "json" => Ok(ValueCodec::Json),
+                "msgpack" => Ok(ValueCodec::MessagePack),
+                name => cx.throw_error(format!("Unknown codec: {}", name)),
+            };
+        }
+        let hooks = codec.downcast_or_throw::<JsObject, _>(cx)?;
+        let encode = hooks
+            .get(cx, "encode")?
+            .downcast_or_throw::<JsFunction, _>(cx)?
+            .root(cx);
+        let decode = hooks
+            .get(cx, "decode")?
+            .downcast_or_throw::<JsFunction, _>(cx)?
+            .root(cx);
+        Ok(ValueCodec::Custom {
+            encode: Arc::new(encode),
+            decode: Arc::new(decode),
+        })
+    }
+
+    fn encode<'a, C: Context<'a>>(
+        &self,
+        cx: &mut C,
+        value: Handle<JsValue>,
+    ) -> NeonResult<Vec<u8>> {
+        match self {
+            ValueCodec::String => Ok(value
+                .downcast_or_throw::<JsString, _>(cx)?
+                .value(cx)
+                .into_bytes()),
+            ValueCodec::Buffer => js_value_to_bytes(cx, value),
+            ValueCodec::Json => {
+                let value = js_to_msgpack(cx, value, 0)?;
+                match serde_json::to_vec(&value) {
+                    Ok(bytes) => Ok(bytes),
+                    Err(err) => cx.throw_error(format!("Cannot encode value as JSON: {}", err)),
+                }
+            }
+            ValueCodec::MessagePack => {
+                let value = js_to_msgpack(cx, value, 0)?;
+                let mut bytes = vec![];
+                match rmpv::encode::write_value(&mut bytes, &value) {
+                    Ok(()) => Ok(bytes),
+                    Err(err) => {
+                        cx.throw_error(format!("Cannot encode value as MessagePack: {}", err))
+                    }
+                }
+            }
+            ValueCodec::Custom { encode, .. } => {
+                let encode = encode.to_inner(cx);
+                let this = cx.undefined();
+                let encoded = encode.call(cx, this, vec![value])?;
+                js_value_to_bytes(cx, encoded)
+            }
+        }
+    }
+
+    fn decode<'a, C: Context<'a>>(&self, cx: &mut C, bytes: Vec<u8>) -> JsResult<'a, JsValue> {
+        match self {
+            ValueCodec::String => bytes_to_js_value(cx, bytes),
+            ValueCodec::Buffer => Ok(bytes_to_js_buffer(cx, &bytes)?.upcast()),
+            ValueCodec::Json => match serde_json::from_slice::<MsgPack>(&bytes) {
+                Ok(value) => msgpack_to_js(cx, value),
+                Err(err) => cx.throw_error(format!("Cannot decode JSON value: {}", err)),
+            },
+            ValueCodec::MessagePack => match rmpv::decode::read_value(&mut bytes.as_slice()) {
+                Ok(value) => msgpack_to_js(cx, value),
+                Err(err) => cx.throw_error(format!("Cannot decode MessagePack value: {}", err)),
+            },
+            ValueCodec::Custom { decode, .. } => {
+                let buffer = bytes_to_js_buffer(cx, &bytes)?;
+                let decode = decode.to_inner(cx);
+                let this = cx.undefined();
+                decode.call(cx, this, vec![buffer])
+            }
+        }
+    }
+
+    /// Whether two encoded values decode to equal values, compared off the JS thread. JSON and
+    /// MessagePack values are compared as decoded: maps whatever the order of their fields, and
+    /// numbers by value. Values of the other codecs, custom ones included, are compared byte by
+    /// byte, as are values which fail to decode.
+    fn equal(&self, a: &[u8], b: &[u8]) -> bool {
+        let decoded = match self {
+            ValueCodec::Json => (
+                serde_json::from_slice::<MsgPack>(a).ok(),
+                serde_json::from_slice::<MsgPack>(b).ok(),
+            ),
+            ValueCodec::MessagePack => (
+                rmpv::decode::read_value(&mut &*a).ok(),
+                rmpv::decode::read_value(&mut &*b).ok(),
+            ),
+            _ => (None, None),
+        };
+        match decoded {
+            (Some(a), Some(b)) => msgpack_equal(&a, &b),
+            _ => a == b,
+        }
+    }
+}
+
+/// Values read through a client, to be decoded with its codec.
+pub struct Decoded<T> {
+    pub codec: Codec,
+    pub values: T,
+}
+
+impl ToJS for Decoded<Option<Value>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        match self.values {
+            Some(value) => self.codec.decode(cx, value),
+            None => Ok(cx.undefined().upcast()),
+        }
+    }
+}
+
+impl ToJS for Decoded<Vec<KvPair>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(rust_pairs_to_js_array(cx, self.values, &self.codec)?.upcast())
+    }
+}
+
+impl ToJS for Decoded<Vec<Vec<KvPair>>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let groups = JsArray::new(cx, self.values.len() as u32);
+        for (i, pairs) in self.values.into_iter().enumerate() {
+            let pairs = rust_pairs_to_js_array(cx, pairs, &self.codec)?;
+            groups.set(cx, i as u32, pairs)?;
+        }
+        Ok(groups.upcast())
+    }
+}
+
+impl ToJS for Decoded<Vec<Key>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(rust_keys_to_js_array(cx, self.values, &self.codec)?.upcast())
+    }
+}
+
+impl ToJS for Decoded<Vec<Vec<Key>>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let groups = JsArray::new(cx, self.values.len() as u32);
+        for (i, keys) in self.values.into_iter().enumerate() {
+            let keys = rust_keys_to_js_array(cx, keys, &self.codec)?;
+            groups.set(cx, i as u32, keys)?;
+        }
+        Ok(groups.upcast())
+    }
+}
+
+impl ToJS for Decoded<Vec<CoprocessorResponse>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let responses = JsArray::new(cx, self.values.len() as u32);
+        for (i, response) in self.values.into_iter().enumerate() {
+            let object = cx.empty_object();
+            let data = bytes_to_js_buffer(cx, &response.data)?;
+            object.set(cx, "data", data)?;
+            let ranges = JsArray::new(cx, response.ranges.len() as u32);
+            for (j, range) in response.ranges.into_iter().enumerate() {
+                let bounds = JsArray::new(cx, 2);
+                let start = self.codec.key(cx, range.start.into())?;
+                bounds.set(cx, 0, start)?;
+                let end = self.codec.key(cx, range.end.into())?;
+                bounds.set(cx, 1, end)?;
+                ranges.set(cx, j as u32, bounds)?;
+            }
+            object.set(cx, "ranges", ranges)?;
+            responses.set(cx, i as u32, object)?;
+        }
+        Ok(responses.upcast())
+    }
+}
+
+/// Mutations as `{ type, key, value }` objects, `value` only for puts and inserts.
+impl ToJS for Decoded<Vec<PendingMutation>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let mutations = JsArray::new(cx, self.values.len() as u32);
+        for (i, mutation) in self.values.into_iter().enumerate() {
+            let object = cx.empty_object();
+            let (kind, key, value) = match mutation {
+                PendingMutation::Put(key, value) => ("put", key, Some(value)),
+                PendingMutation::Insert(key, value) => ("insert", key, Some(value)),
+                PendingMutation::Delete(key) => ("delete", key, None),
+                PendingMutation::Lock(key) => ("lock", key, None),
+            };
+            let kind = cx.string(kind);
+            object.set(cx, "type", kind)?;
+            let key = self.codec.key(cx, key.into())?;
+            object.set(cx, "key", key)?;
+            if let Some(value) = value {
+                let value = self.codec.decode(cx, value)?;
+                object.set(cx, "value", value)?;
+            }
+            mutations.set(cx, i as u32, object)?;
+        }
+        Ok(mutations.upcast())
+    }
+}
+
+/// Both built-in codecs go through MessagePack values, which also represent Buffers. Objects
+/// with a `toJSON` method, such as Dates, are encoded as what it returns, like `JSON.stringify`
+/// does.
+fn js_to_msgpack<'a, C: Context<'a>>(
+    cx: &mut C,
+    value: Handle<JsValue>,
+    depth: usize,
+) -> NeonResult<MsgPack> {
+    if depth > MAX_DEPTH {
+        return cx.throw_type_error(format!(
+            "Values nested deeper than {} levels, or circular, cannot be encoded",
+            MAX_DEPTH
+        ));
+    }
+    if value.is_a::<JsNull, _>(cx) || value.is_a::<JsUndefined, _>(cx) {
+        return Ok(MsgPack::Nil);
+    }
+    if let Ok(value) = value.downcast::<JsBoolean, _>(cx) {
+        return Ok(MsgPack::Boolean(value.value(cx)));
+    }
+    if let Ok(value) = value.downcast::<JsNumber, _>(cx) {
+        let value = value.value(cx);
+        if value.fract() == 0.0 && value.abs() <= MAX_SAFE_INTEGER {
+            return Ok(MsgPack::from(value as i64));
+        }
+        return Ok(MsgPack::F64(value));
+    }
+    if let Ok(value) = value.downcast::<JsString, _>(cx) {
+        return Ok(MsgPack::from(value.value(cx)));
+    }
+    if value.is_a::<JsBuffer, _>(cx) {
+        return Ok(MsgPack::Binary(js_value_to_bytes(cx, value)?));
+    }
+    if let Ok(value) = value.downcast::<JsArray, _>(cx) {
+        let mut values = vec![];
+        for value in value.to_vec(cx)? {
+            values.push(js_to_msgpack(cx, value, depth + 1)?);
+        }
+        return Ok(MsgPack::Array(values));
+    }
+    if value.is_a::<JsFunction, _>(cx) {
+        return cx.throw_type_error("Functions cannot be encoded");
+    }
+    let object = value.downcast_or_throw::<JsObject, _>(cx)?;
+    let to_json = object.get(cx, "toJSON")?;
+    if let Ok(to_json) = to_json.downcast::<JsFunction, _>(cx) {
+        let args: Vec<Handle<JsValue>> = vec![];
+        let value = to_json.call(cx, object, args)?;
+        return js_to_msgpack(cx, value, depth + 1);
+    }
+    let mut entries = vec![];
+    for name in object.get_own_property_names(cx)?.to_vec(cx)? {
+        let key = name.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        let value = object.get(cx, name)?;
+        entries.push((MsgPack::from(key), js_to_msgpack(cx, value, depth + 1)?));
+    }
+    Ok(MsgPack::Map(entries))
+}
+
+/// Whether two values decode to equal JS values, fields of maps in any order.
+fn msgpack_equal(a: &MsgPack, b: &MsgPack) -> bool {
+    match (a, b) {
+        (MsgPack::Array(a), MsgPack::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| msgpack_equal(a, b))
+        }
+        (MsgPack::Map(a), MsgPack::Map(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, value)| {
+                    b.iter()
+                        .any(|(other_key, other)| key == other_key && msgpack_equal(value, other))
+                })
+        }
+        (MsgPack::Binary(a) | MsgPack::Ext(_, a), MsgPack::Binary(b) | MsgPack::Ext(_, b)) => {
+            a == b
+        }
+        (a, b) => match (msgpack_number(a), msgpack_number(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+fn msgpack_number(value: &MsgPack) -> Option<f64> {
+    match value {
+        MsgPack::Integer(value) => value.as_f64(),
+        MsgPack::F32(value) => Some(*value as f64),
+        MsgPack::F64(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn msgpack_to_js<'a, C: Context<'a>>(cx: &mut C, value: MsgPack) -> JsResult<'a, JsValue> {
+    Ok(match value {
+        MsgPack::Nil => cx.null().upcast(),
+        MsgPack::Boolean(value) => cx.boolean(value).upcast(),
+        MsgPack::Integer(value) => cx.number(value.as_f64().unwrap_or(f64::NAN)).upcast(),
+        MsgPack::F32(value) => cx.number(value).upcast(),
+        MsgPack::F64(value) => cx.number(value).upcast(),
+        MsgPack::String(value) => bytes_to_js_value(cx, value.into_bytes())?,
+        MsgPack::Binary(value) | MsgPack::Ext(_, value) => bytes_to_js_buffer(cx, &value)?.upcast(),
+        MsgPack::Array(values) => {
+            let array = JsArray::new(cx, values.len() as u32);
+            for (i, value) in values.into_iter().enumerate() {
+                let value = msgpack_to_js(cx, value)?;
+                array.set(cx, i as u32, value)?;
+            }
+            array.upcast()
+        }
+        MsgPack::Map(entries) => {
+            let object = cx.empty_object();
+            for (key, value) in entries {
+                let key = match key {
+                    MsgPack::String(key) => String::from_utf8_lossy(key.as_bytes()).into_owned(),
+                    key => key.to_string(),
+                };
+                let value = msgpack_to_js(cx, value)?;
+                object.set(cx, key.as_str(), value)?;
+            }
+            object.upcast()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msgpack(value: &MsgPack) -> Vec<u8> {
+        let mut bytes = vec![];
+        rmpv::encode::write_value(&mut bytes, value).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn json_values_are_compared_as_decoded() {
+        let codec = ValueCodec::Json;
+        assert!(codec.equal(br#"{"a":1,"b":[2,"c"]}"#, br#"{ "b": [2.0, "c"], "a": 1 }"#));
+        assert!(!codec.equal(br#"{"a":1}"#, br#"{"a":1,"b":null}"#));
+        assert!(!codec.equal(br#"{"a":1}"#, br#"{"a":"1"}"#));
+        assert!(!codec.equal(b"[1,2]", b"[2,1]"));
+        assert!(!codec.equal(b"not json", b"1"));
+        assert!(codec.equal(b"not json", b"not json"));
+    }
+
+    #[test]
+    fn msgpack_values_are_compared_as_decoded() {
+        let codec = ValueCodec::MessagePack;
+        let a = MsgPack::Map(vec![
+            (MsgPack::from("a"), MsgPack::from(1)),
+            (MsgPack::from("b"), MsgPack::Binary(vec![1])),
+        ]);
+        let b = MsgPack::Map(vec![
+            (MsgPack::from("b"), MsgPack::Binary(vec![1])),
+            (MsgPack::from("a"), MsgPack::F64(1.0)),
+        ]);
+        assert!(codec.equal(&msgpack(&a), &msgpack(&b)));
+        assert!(!codec.equal(&msgpack(&a), &msgpack(&MsgPack::Nil)));
+    }
+
+    #[test]
+    fn other_values_are_compared_byte_by_byte() {
+        assert!(ValueCodec::String.equal(b"abc", b"abc"));
+        assert!(!ValueCodec::String.equal(b"abc", b"abd"));
+        assert!(!ValueCodec::Buffer.equal(b"1.0", b"1"));
+    }
+}
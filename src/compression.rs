@@ -0,0 +1,577 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Backends compressing values before they are written and decompressing them when read.
+//!
+//! A compressed value starts with `HEADER`, a byte which is never valid UTF-8 nor MessagePack,
+//! followed by a byte naming the algorithm. Values shorter than the minimum size are written as
+//! they are, so compressed and uncompressed values can live side by side, and values written
+//! before compression was turned on are still read back. Other tags after `HEADER` belong to
+//! other layers, such as encryption, and are left alone.
+
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use neon::prelude::*;
+use tikv_client::{BoundRange, ColumnFamily, Error, Key, KvPair, Result, Timestamp, Value};
+
+use crate::{
+    backend::{
+        Checksum, CoprocessorResponse, RawBackend, SnapshotHandle, TransactionBackend,
+        TransactionHandle, TxnOptions,
+    },
+    locks::LockInfo,
+    region::PlacementBackend,
+    utils::{bytes_to_js_buffer, js_value_to_bytes, ToJS},
+};
+
+pub const HEADER: u8 = 0xc1;
+
+const UNCOMPRESSED: u8 = 0;
+const ZSTD: u8 = 1;
+const LZ4: u8 = 2;
+const SNAPPY: u8 = 3;
+
+const DEFAULT_MIN_SIZE: usize = 64;
+/// TiKV's default `raft-entry-max-size`, which no value written can exceed, so a value
+/// decompressing past it is corrupt and fails to be read rather than filling the memory.
+const MAX_VALUE_SIZE: usize = 8 * 1024 * 1024;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy)]
+pub enum Algorithm {
+    Zstd,
+    Lz4,
+    Snappy,
+}
+
+pub struct Compression {
+    algorithm: Algorithm,
+    level: i32,
+    min_size: usize,
+    /// Used to compress and decompress zstd values.
+    dictionary: Option<Vec<u8>>,
+}
+
+impl Compression {
+    /// Read `{ algorithm, level, min_size, dictionary }`, where only the algorithm is required.
+    pub fn from_js<'a, C: Context<'a>>(
+        cx: &mut C,
+        options: Handle<JsObject>,
+    ) -> NeonResult<Compression> {
+        let algorithm = options.get(cx, "algorithm")?;
+        let algorithm = algorithm.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        let algorithm = match algorithm.as_str() {
+            "zstd" => Algorithm::Zstd,
+            "lz4" => Algorithm::Lz4,
+            "snappy" => Algorithm::Snappy,
+            algorithm => {
+                return cx.throw_error(format!("Unknown compression algorithm: {}", algorithm))
+            }
+        };
+        let level = match options.get(cx, "level")?.downcast::<JsNumber, _>(cx) {
+            Ok(level) => level.value(cx) as i32,
+            Err(_) => DEFAULT_ZSTD_LEVEL,
+        };
+        let min_size = match options.get(cx, "min_size")?.downcast::<JsNumber, _>(cx) {
+            Ok(min_size) => min_size.value(cx) as usize,
+            Err(_) => DEFAULT_MIN_SIZE,
+        };
+        let dictionary = options.get(cx, "dictionary")?;
+        let dictionary = if dictionary.is_a::<JsBuffer, _>(cx) {
+            Some(js_value_to_bytes(cx, dictionary)?)
+        } else {
+            None
+        };
+        Ok(Compression {
+            algorithm,
+            level,
+            min_size,
+            dictionary,
+        })
+    }
+
+    fn compress(&self, value: Value) -> Result<Value> {
+        if value.len() < self.min_size {
+            if value.first() == Some(&HEADER) {
+                // Escape values which would be mistaken for compressed ones.
+                return Ok(with_header(UNCOMPRESSED, value));
+            }
+            return Ok(value);
+        }
+        let compressed = match self.algorithm {
+            Algorithm::Zstd => with_header(ZSTD, self.zstd_compress(&value)?),
+            Algorithm::Lz4 => with_header(LZ4, lz4_flex::compress_prepend_size(&value)),
+            Algorithm::Snappy => {
+                let compressed = snap::raw::Encoder::new()
+                    .compress_vec(&value)
+                    .map_err(invalid_data)?;
+                with_header(SNAPPY, compressed)
+            }
+        };
+        if compressed.len() >= value.len() && value.first() != Some(&HEADER) {
+            return Ok(value);
+        }
+        Ok(compressed)
+    }
+
+    fn decompress(&self, value: Value) -> Result<Value> {
+        if value.len() < 2 || value[0] != HEADER {
+            return Ok(value);
+        }
+        let data = &value[2..];
+        match value[1] {
+            UNCOMPRESSED => Ok(data.to_vec()),
+            ZSTD => self.zstd_decompress(data),
+            LZ4 => lz4_decompress(data),
+            SNAPPY => {
+                check_size(snap::raw::decompress_len(data).map_err(invalid_data)?)?;
+                snap::raw::Decoder::new()
+                    .decompress_vec(data)
+                    .map_err(invalid_data)
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn zstd_compress(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let dictionary = self.dictionary.as_deref().unwrap_or_default();
+        let mut encoder =
+            zstd::stream::write::Encoder::with_dictionary(vec![], self.level, dictionary)?;
+        encoder.write_all(value)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn zstd_decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let dictionary = self.dictionary.as_deref().unwrap_or_default();
+        let decoder = zstd::stream::read::Decoder::with_dictionary(data, dictionary)?;
+        let mut value = vec![];
+        decoder
+            .take(MAX_VALUE_SIZE as u64 + 1)
+            .read_to_end(&mut value)?;
+        check_size(value.len())?;
+        Ok(value)
+    }
+
+    fn decompress_pairs(&self, pairs: Vec<KvPair>) -> Result<Vec<KvPair>> {
+        pairs
+            .into_iter()
+            .map(|KvPair(key, value)| Ok(KvPair(key, self.decompress(value)?)))
+            .collect()
+    }
+}
+
+fn with_header(algorithm: u8, data: Vec<u8>) -> Vec<u8> {
+    let mut value = Vec::with_capacity(data.len() + 2);
+    value.push(HEADER);
+    value.push(algorithm);
+    value.extend_from_slice(&data);
+    value
+}
+
+/// Decompress a block prepended with its size, as `lz4_flex::compress_prepend_size` writes it,
+/// checking the size before allocating it.
+fn lz4_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 4 {
+        return Err(invalid_data("Compressed value is truncated"));
+    }
+    let (size, block) = data.split_at(4);
+    let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+    check_size(size)?;
+    lz4_flex::decompress(block, size).map_err(invalid_data)
+}
+
+fn check_size(size: usize) -> Result<()> {
+    if size > MAX_VALUE_SIZE {
+        return Err(invalid_data(format!(
+            "Compressed value decompresses to more than {} bytes",
+            MAX_VALUE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn invalid_data(err: impl ToString) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+/// A zstd dictionary trained from sample values.
+pub struct Dictionary(pub Vec<u8>);
+
+impl Dictionary {
+    pub fn train(samples: &[KvPair], max_size: usize) -> Result<Dictionary> {
+        let samples: Vec<&[u8]> = samples.iter().map(|pair| pair.1.as_slice()).collect();
+        Ok(Dictionary(zstd::dict::from_samples(&samples, max_size)?))
+    }
+}
+
+impl ToJS for Dictionary {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(bytes_to_js_buffer(cx, &self.0)?.upcast())
+    }
+}
+
+pub struct CompressedRaw {
+    pub inner: Arc<dyn RawBackend>,
+    pub compression: Arc<Compression>,
+}
+
+#[async_trait]
+impl RawBackend for CompressedRaw {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(CompressedRaw {
+            inner: self.inner.with_cf(cf),
+            compression: self.compression.clone(),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.inner.placement()
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        match self.inner.get(key).await? {
+            Some(value) => Ok(Some(self.compression.decompress(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(keys).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        let value = self.compression.compress(value)?;
+        self.inner.put(key, value).await
+    }
+
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        let value = self.compression.compress(value)?;
+        self.inner.put_with_ttl(key, value, ttl_secs).await
+    }
+
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        self.inner.get_key_ttl(key).await
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        let pairs = pairs
+            .into_iter()
+            .map(|KvPair(key, value)| Ok(KvPair(key, self.compression.compress(value)?)))
+            .collect::<Result<_>>()?;
+        self.inner.batch_put(pairs).await
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        self.inner.batch_delete(keys).await
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        self.inner.delete_range(range).await
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(range, limit).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        let results = self.inner.batch_scan(ranges, each_limit).await?;
+        results
+            .into_iter()
+            .map(|pairs| self.compression.decompress_pairs(pairs))
+            .collect()
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        self.inner.batch_scan_keys(ranges, each_limit).await
+    }
+
+    /// The checksum of the values as they are stored.
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        self.inner.checksum(range).await
+    }
+
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        self.inner.coprocessor(name, version, ranges, request).await
+    }
+}
+
+pub struct CompressedTransactionBackend {
+    pub inner: Arc<dyn TransactionBackend>,
+    pub compression: Arc<Compression>,
+}
+
+#[async_trait]
+impl TransactionBackend for CompressedTransactionBackend {
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.inner.placement()
+    }
+
+    async fn begin(&self, options: TxnOptions) -> Result<Box<dyn TransactionHandle>> {
+        Ok(Box::new(CompressedTransaction {
+            inner: self.inner.begin(options).await?,
+            compression: self.compression.clone(),
+        }))
+    }
+
+    fn snapshot(&self, timestamp: Timestamp, options: TxnOptions) -> Box<dyn SnapshotHandle> {
+        Box::new(CompressedSnapshot {
+            inner: self.inner.snapshot(timestamp, options),
+            compression: self.compression.clone(),
+        })
+    }
+
+    async fn current_timestamp(&self) -> Result<Timestamp> {
+        self.inner.current_timestamp().await
+    }
+
+    async fn gc(&self, safepoint: Timestamp) -> Result<bool> {
+        self.inner.gc(safepoint).await
+    }
+
+    async fn scan_locks(
+        &self,
+        range: BoundRange,
+        max_ts: Timestamp,
+        limit: u32,
+    ) -> Result<Vec<LockInfo>> {
+        self.inner.scan_locks(range, max_ts, limit).await
+    }
+
+    async fn cleanup_locks(
+        &self,
+        range: BoundRange,
+        safepoint: Timestamp,
+        dry_run: bool,
+    ) -> Result<Vec<LockInfo>> {
+        self.inner.cleanup_locks(range, safepoint, dry_run).await
+    }
+}
+
+struct CompressedTransaction {
+    inner: Box<dyn TransactionHandle>,
+    compression: Arc<Compression>,
+}
+
+#[async_trait]
+impl TransactionHandle for CompressedTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        match self.inner.get(key).await? {
+            Some(value) => Ok(Some(self.compression.decompress(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        match self.inner.get_for_update(key).await? {
+            Some(value) => Ok(Some(self.compression.decompress(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(keys).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get_for_update(keys).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(range, limit).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        self.inner.lock_keys(keys).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        let value = self.compression.compress(value)?;
+        self.inner.put(key, value).await
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        let value = self.compression.compress(value)?;
+        self.inner.insert(key, value).await
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.inner.commit().await
+    }
+
+    fn start_timestamp(&self) -> Result<Timestamp> {
+        self.inner.start_timestamp()
+    }
+}
+
+struct CompressedSnapshot {
+    inner: Box<dyn SnapshotHandle>,
+    compression: Arc<Compression>,
+}
+
+#[async_trait]
+impl SnapshotHandle for CompressedSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        match self.inner.get(key).await? {
+            Some(value) => Ok(Some(self.compression.decompress(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(keys).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(range, limit).await?;
+        self.compression.decompress_pairs(pairs)
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compression(algorithm: Algorithm) -> Compression {
+        Compression {
+            algorithm,
+            level: DEFAULT_ZSTD_LEVEL,
+            min_size: DEFAULT_MIN_SIZE,
+            dictionary: None,
+        }
+    }
+
+    fn round_trip(compression: &Compression, value: &[u8]) -> Vec<u8> {
+        let compressed = compression.compress(value.to_vec()).unwrap();
+        assert_eq!(compression.decompress(compressed.clone()).unwrap(), value);
+        compressed
+    }
+
+    /// Bytes that don't compress, from a xorshift generator.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compressible_values_round_trip() {
+        let value = b"abcdefgh".repeat(100);
+        for algorithm in &[Algorithm::Zstd, Algorithm::Lz4, Algorithm::Snappy] {
+            let compressed = round_trip(&compression(*algorithm), &value);
+            assert_eq!(compressed[0], HEADER);
+            assert!(compressed.len() < value.len());
+        }
+    }
+
+    #[test]
+    fn small_and_incompressible_values_are_kept() {
+        let compression = compression(Algorithm::Zstd);
+        assert_eq!(round_trip(&compression, b"small"), b"small");
+        assert_eq!(round_trip(&compression, b""), b"");
+        let noise = noise(1000);
+        assert_eq!(round_trip(&compression, &noise), noise);
+    }
+
+    #[test]
+    fn values_starting_with_the_header_are_escaped() {
+        for algorithm in &[Algorithm::Zstd, Algorithm::Lz4, Algorithm::Snappy] {
+            let compression = compression(*algorithm);
+            for value in &[
+                vec![HEADER],
+                vec![HEADER, ZSTD, 1, 2, 3],
+                [&[HEADER, LZ4][..], &noise(1000)].concat(),
+            ] {
+                let compressed = round_trip(&compression, value);
+                assert_eq!(compressed[0], HEADER);
+                assert_ne!(&compressed, value);
+            }
+        }
+    }
+
+    #[test]
+    fn other_tags_are_left_alone() {
+        let value = vec![HEADER, 0x80, 1, 2, 3];
+        let compression = compression(Algorithm::Zstd);
+        assert_eq!(compression.decompress(value.clone()).unwrap(), value);
+    }
+
+    #[test]
+    fn corrupt_values_fail_to_decompress() {
+        let compression = compression(Algorithm::Lz4);
+        let mut compressed = round_trip(&compression, &b"abcdefgh".repeat(100));
+        compressed.truncate(compressed.len() / 2);
+        assert!(compression.decompress(compressed).is_err());
+    }
+
+    #[test]
+    fn values_decompressing_past_the_maximum_size_fail() {
+        let value = vec![0; MAX_VALUE_SIZE + 1];
+        for algorithm in &[Algorithm::Zstd, Algorithm::Lz4, Algorithm::Snappy] {
+            let compression = compression(*algorithm);
+            let compressed = compression.compress(value.clone()).unwrap();
+            assert!(compression.decompress(compressed).is_err());
+        }
+
+        let compression = compression(Algorithm::Lz4);
+        let mut forged = vec![HEADER, LZ4];
+        forged.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(compression.decompress(forged).is_err());
+    }
+}
@@ -0,0 +1,591 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Backends encrypting values with AES-256-GCM before they are written and decrypting them
+//! when read. Keys are left as they are, so ranges and scans still work.
+//!
+//! Values are envelope encrypted: each one with a random data key of its own, which is stored
+//! with it, wrapped by the key named `key_id`, a key encryption key from a JS function, called
+//! once per key ID. An encrypted value is `HEADER`, `ENCRYPTED`, the length of the key ID, the
+//! key ID, the nonce and wrapped data key, then the nonce and ciphertext of the value. Values are
+//! read with the key named in their header, so keys can be rotated without rewriting data. The
+//! KV key is authenticated with the value, so a value copied under another key fails to decrypt.
+//! So do values without the header, unless plaintext is allowed while migrating to encryption.
+//!
+//! The KV key authenticated is the one this layer sees, without the prefix of the subspaces
+//! wrapped by it, so values are read back through layers stacked in the order they were written.
+//! Ciphers are cached under a lock held while the JS function is called, so it is called once
+//! per key ID even when requests miss the cache together.
+
+use std::{collections::HashMap, io, sync::Arc};
+
+use aes_gcm::{
+    aead::{Aead, NewAead, Payload},
+    Aes256Gcm, Nonce,
+};
+use async_trait::async_trait;
+use neon::prelude::*;
+use tikv_client::{BoundRange, ColumnFamily, Error, Key, KvPair, Result, Timestamp, Value};
+use tokio::sync::oneshot;
+
+use crate::{
+    backend::{
+        Checksum, CoprocessorResponse, RawBackend, SnapshotHandle, TransactionBackend,
+        TransactionHandle, TxnOptions,
+    },
+    compression::HEADER,
+    locks::LockInfo,
+    region::PlacementBackend,
+    utils::js_value_to_bytes,
+};
+
+const ENCRYPTED: u8 = 0x80;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+
+pub struct Encryption {
+    key_id: String,
+    /// Values without the header are read as they are, instead of failing.
+    allow_plaintext: bool,
+    queue: EventQueue,
+    key_provider: Arc<Root<JsFunction>>,
+    ciphers: tokio::sync::Mutex<HashMap<String, Arc<Aes256Gcm>>>,
+}
+
+impl Encryption {
+    /// Read `{ key_id, key_provider, allow_plaintext }`, where `key_provider(key_id)` returns a
+    /// 32 byte Buffer.
+    pub fn from_js(cx: &mut FunctionContext, options: Handle<JsObject>) -> NeonResult<Encryption> {
+        let key_id = options.get(cx, "key_id")?;
+        let key_id = key_id.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        if key_id.is_empty() || key_id.len() > u8::MAX as usize {
+            return cx.throw_error("The key ID must be between 1 and 255 bytes long");
+        }
+        let key_provider = options
+            .get(cx, "key_provider")?
+            .downcast_or_throw::<JsFunction, _>(cx)?
+            .root(cx);
+        let allow_plaintext = options
+            .get(cx, "allow_plaintext")?
+            .downcast::<JsBoolean, _>(cx)
+            .map_or(false, |allow| allow.value(cx));
+
+        let mut queue = cx.queue();
+        // Only requests, which keep the process alive themselves, ask for keys.
+        queue.unref(cx);
+        Ok(Encryption {
+            key_id,
+            allow_plaintext,
+            queue,
+            key_provider: Arc::new(key_provider),
+            ciphers: tokio::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn cipher(&self, key_id: &str) -> Result<Arc<Aes256Gcm>> {
+        let mut ciphers = self.ciphers.lock().await;
+        if let Some(cipher) = ciphers.get(key_id) {
+            return Ok(cipher.clone());
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let key_provider = self.key_provider.clone();
+        let id = key_id.to_owned();
+        self.queue.send(move |mut cx| {
+            let key_provider = key_provider.to_inner(&mut cx);
+            let this = cx.undefined();
+            let id = cx.string(id);
+            let key = cx.try_catch(|cx| {
+                let key = key_provider.call(cx, this, vec![id])?;
+                js_value_to_bytes(cx, key)
+            });
+            let key = match key {
+                Ok(key) => Ok(key),
+                Err(err) => Err(err.to_string(&mut cx)?.value(&mut cx)),
+            };
+            let _ = sender.send(key);
+            Ok(())
+        });
+
+        let key = receiver
+            .await
+            .map_err(|_| key_error(format!("no key provided for key ID {}", key_id)))?
+            .map_err(key_error)?;
+        if key.len() != KEY_LEN {
+            return Err(key_error(format!(
+                "the key for key ID {} is {} bytes long instead of {}",
+                key_id,
+                key.len(),
+                KEY_LEN
+            )));
+        }
+        let cipher = Arc::new(Aes256Gcm::new(aes_gcm::Key::from_slice(&key)));
+        ciphers.insert(key_id.to_owned(), cipher.clone());
+        Ok(cipher)
+    }
+
+    async fn encrypt(&self, key: &Key, value: Value) -> Result<Value> {
+        let cipher = self.cipher(&self.key_id).await?;
+        seal(&cipher, &self.key_id, key.into(), &value)
+    }
+
+    async fn decrypt(&self, key: &Key, value: Value) -> Result<Value> {
+        let envelope = match Envelope::parse(&value)? {
+            Some(envelope) => envelope,
+            None if self.allow_plaintext => return Ok(value),
+            None => return Err(key_error("value is not encrypted")),
+        };
+        let cipher = self.cipher(&envelope.key_id).await?;
+        envelope.open(&cipher, key.into())
+    }
+
+    async fn encrypt_pairs(&self, pairs: Vec<KvPair>) -> Result<Vec<KvPair>> {
+        let mut encrypted = Vec::with_capacity(pairs.len());
+        for KvPair(key, value) in pairs {
+            let value = self.encrypt(&key, value).await?;
+            encrypted.push(KvPair(key, value));
+        }
+        Ok(encrypted)
+    }
+
+    async fn decrypt_pairs(&self, pairs: Vec<KvPair>) -> Result<Vec<KvPair>> {
+        let mut decrypted = Vec::with_capacity(pairs.len());
+        for KvPair(key, value) in pairs {
+            let value = self.decrypt(&key, value).await?;
+            decrypted.push(KvPair(key, value));
+        }
+        Ok(decrypted)
+    }
+
+    async fn decrypt_option(&self, key: &Key, value: Option<Value>) -> Result<Option<Value>> {
+        match value {
+            Some(value) => Ok(Some(self.decrypt(key, value).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Encrypt `value`, stored under `key`, with a new data key wrapped by `cipher`.
+fn seal(cipher: &Aes256Gcm, key_id: &str, key: &[u8], value: &[u8]) -> Result<Value> {
+    let data_key: [u8; KEY_LEN] = rand::random();
+    let wrap_nonce: [u8; NONCE_LEN] = rand::random();
+    let wrapped_key = cipher
+        .encrypt(
+            Nonce::from_slice(&wrap_nonce),
+            Payload {
+                msg: &data_key,
+                aad: key_id.as_bytes(),
+            },
+        )
+        .map_err(|_| key_error("cannot wrap data key"))?;
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = Aes256Gcm::new(aes_gcm::Key::from_slice(&data_key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: value,
+                aad: key,
+            },
+        )
+        .map_err(|_| key_error("cannot encrypt value"))?;
+
+    let mut encrypted = Vec::with_capacity(
+        3 + key_id.len() + NONCE_LEN + WRAPPED_KEY_LEN + NONCE_LEN + ciphertext.len(),
+    );
+    encrypted.push(HEADER);
+    encrypted.push(ENCRYPTED);
+    encrypted.push(key_id.len() as u8);
+    encrypted.extend_from_slice(key_id.as_bytes());
+    encrypted.extend_from_slice(&wrap_nonce);
+    encrypted.extend_from_slice(&wrapped_key);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+/// The parts of an encrypted value.
+struct Envelope<'a> {
+    key_id: String,
+    wrap_nonce: &'a [u8],
+    wrapped_key: &'a [u8],
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    /// `None` if `value` has no encryption header.
+    fn parse(value: &'a [u8]) -> Result<Option<Envelope<'a>>> {
+        if value.len() < 3 || value[0] != HEADER || value[1] != ENCRYPTED {
+            return Ok(None);
+        }
+        let key_id_end = 3 + value[2] as usize;
+        let wrapped_key_start = key_id_end + NONCE_LEN;
+        let nonce_start = wrapped_key_start + WRAPPED_KEY_LEN;
+        let ciphertext_start = nonce_start + NONCE_LEN;
+        if value.len() < ciphertext_start + TAG_LEN {
+            return Err(key_error("truncated encrypted value"));
+        }
+        Ok(Some(Envelope {
+            key_id: String::from_utf8_lossy(&value[3..key_id_end]).into_owned(),
+            wrap_nonce: &value[key_id_end..wrapped_key_start],
+            wrapped_key: &value[wrapped_key_start..nonce_start],
+            nonce: &value[nonce_start..ciphertext_start],
+            ciphertext: &value[ciphertext_start..],
+        }))
+    }
+
+    /// Decrypt the value stored under `key`, unwrapping its data key with `cipher`.
+    fn open(&self, cipher: &Aes256Gcm, key: &[u8]) -> Result<Value> {
+        let data_key = cipher
+            .decrypt(
+                Nonce::from_slice(self.wrap_nonce),
+                Payload {
+                    msg: self.wrapped_key,
+                    aad: self.key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                key_error(format!(
+                    "cannot unwrap data key with key ID {}",
+                    self.key_id
+                ))
+            })?;
+        Aes256Gcm::new(aes_gcm::Key::from_slice(&data_key))
+            .decrypt(
+                Nonce::from_slice(self.nonce),
+                Payload {
+                    msg: self.ciphertext,
+                    aad: key,
+                },
+            )
+            .map_err(|_| key_error("cannot decrypt value, or it was stored under another key"))
+    }
+}
+
+fn key_error(err: impl ToString) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+pub struct EncryptedRaw {
+    pub inner: Arc<dyn RawBackend>,
+    pub encryption: Arc<Encryption>,
+}
+
+#[async_trait]
+impl RawBackend for EncryptedRaw {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(EncryptedRaw {
+            inner: self.inner.with_cf(cf),
+            encryption: self.encryption.clone(),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.inner.placement()
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        let value = self.inner.get(key.clone()).await?;
+        self.encryption.decrypt_option(&key, value).await
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(keys).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        let value = self.encryption.encrypt(&key, value).await?;
+        self.inner.put(key, value).await
+    }
+
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        let value = self.encryption.encrypt(&key, value).await?;
+        self.inner.put_with_ttl(key, value, ttl_secs).await
+    }
+
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        self.inner.get_key_ttl(key).await
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        let pairs = self.encryption.encrypt_pairs(pairs).await?;
+        self.inner.batch_put(pairs).await
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        self.inner.batch_delete(keys).await
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        self.inner.delete_range(range).await
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(range, limit).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        let results = self.inner.batch_scan(ranges, each_limit).await?;
+        let mut decrypted = Vec::with_capacity(results.len());
+        for pairs in results {
+            decrypted.push(self.encryption.decrypt_pairs(pairs).await?);
+        }
+        Ok(decrypted)
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        self.inner.batch_scan_keys(ranges, each_limit).await
+    }
+
+    /// The checksum of the values as they are stored.
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        self.inner.checksum(range).await
+    }
+
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        self.inner.coprocessor(name, version, ranges, request).await
+    }
+}
+
+pub struct EncryptedTransactionBackend {
+    pub inner: Arc<dyn TransactionBackend>,
+    pub encryption: Arc<Encryption>,
+}
+
+#[async_trait]
+impl TransactionBackend for EncryptedTransactionBackend {
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        self.inner.placement()
+    }
+
+    async fn begin(&self, options: TxnOptions) -> Result<Box<dyn TransactionHandle>> {
+        Ok(Box::new(EncryptedTransaction {
+            inner: self.inner.begin(options).await?,
+            encryption: self.encryption.clone(),
+        }))
+    }
+
+    fn snapshot(&self, timestamp: Timestamp, options: TxnOptions) -> Box<dyn SnapshotHandle> {
+        Box::new(EncryptedSnapshot {
+            inner: self.inner.snapshot(timestamp, options),
+            encryption: self.encryption.clone(),
+        })
+    }
+
+    async fn current_timestamp(&self) -> Result<Timestamp> {
+        self.inner.current_timestamp().await
+    }
+
+    async fn gc(&self, safepoint: Timestamp) -> Result<bool> {
+        self.inner.gc(safepoint).await
+    }
+
+    async fn scan_locks(
+        &self,
+        range: BoundRange,
+        max_ts: Timestamp,
+        limit: u32,
+    ) -> Result<Vec<LockInfo>> {
+        self.inner.scan_locks(range, max_ts, limit).await
+    }
+
+    async fn cleanup_locks(
+        &self,
+        range: BoundRange,
+        safepoint: Timestamp,
+        dry_run: bool,
+    ) -> Result<Vec<LockInfo>> {
+        self.inner.cleanup_locks(range, safepoint, dry_run).await
+    }
+}
+
+struct EncryptedTransaction {
+    inner: Box<dyn TransactionHandle>,
+    encryption: Arc<Encryption>,
+}
+
+#[async_trait]
+impl TransactionHandle for EncryptedTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        let value = self.inner.get(key.clone()).await?;
+        self.encryption.decrypt_option(&key, value).await
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        let value = self.inner.get_for_update(key.clone()).await?;
+        self.encryption.decrypt_option(&key, value).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(keys).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get_for_update(keys).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(range, limit).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        self.inner.lock_keys(keys).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        let value = self.encryption.encrypt(&key, value).await?;
+        self.inner.put(key, value).await
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        let value = self.encryption.encrypt(&key, value).await?;
+        self.inner.insert(key, value).await
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.inner.commit().await
+    }
+
+    fn start_timestamp(&self) -> Result<Timestamp> {
+        self.inner.start_timestamp()
+    }
+}
+
+struct EncryptedSnapshot {
+    inner: Box<dyn SnapshotHandle>,
+    encryption: Arc<Encryption>,
+}
+
+#[async_trait]
+impl SnapshotHandle for EncryptedSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        let value = self.inner.get(key.clone()).await?;
+        self.encryption.decrypt_option(&key, value).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(keys).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(range, limit).await?;
+        self.encryption.decrypt_pairs(pairs).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> Aes256Gcm {
+        Aes256Gcm::new(aes_gcm::Key::from_slice(&[byte; KEY_LEN]))
+    }
+
+    fn open(cipher: &Aes256Gcm, key: &[u8], value: &[u8]) -> Result<Value> {
+        Envelope::parse(value)?.unwrap().open(cipher, key)
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let cipher = cipher(1);
+        for value in &[&b""[..], b"v", &[HEADER, ENCRYPTED], &[0; 1000]] {
+            let sealed = seal(&cipher, "k1", b"key", value).unwrap();
+            assert_eq!(&sealed[..2], &[HEADER, ENCRYPTED]);
+            let envelope = Envelope::parse(&sealed).unwrap().unwrap();
+            assert_eq!(envelope.key_id, "k1");
+            assert_eq!(envelope.open(&cipher, b"key").unwrap(), *value);
+        }
+    }
+
+    #[test]
+    fn each_value_has_its_own_data_key() {
+        let cipher = cipher(1);
+        let first = seal(&cipher, "k1", b"key", b"value").unwrap();
+        let second = seal(&cipher, "k1", b"key", b"value").unwrap();
+        let (first, second) = (
+            Envelope::parse(&first).unwrap().unwrap(),
+            Envelope::parse(&second).unwrap().unwrap(),
+        );
+        assert_ne!(first.wrapped_key, second.wrapped_key);
+        assert_ne!(first.ciphertext, second.ciphertext);
+    }
+
+    #[test]
+    fn tampered_values_fail() {
+        let cipher = cipher(1);
+        let sealed = seal(&cipher, "k1", b"key", b"value").unwrap();
+        // Every byte after the key ID is authenticated.
+        for i in 5..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(open(&cipher, b"key", &tampered).is_err(), "byte {}", i);
+        }
+        let mut renamed = sealed.clone();
+        renamed[4] = b'2';
+        assert!(open(&cipher, b"key", &renamed).is_err());
+        assert!(Envelope::parse(&sealed[..sealed.len() - TAG_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn values_fail_under_another_key_or_cipher() {
+        let sealed = seal(&cipher(1), "k1", b"key", b"value").unwrap();
+        assert!(open(&cipher(1), b"other", &sealed).is_err());
+        assert!(open(&cipher(2), b"key", &sealed).is_err());
+    }
+
+    #[test]
+    fn values_without_the_header_are_not_envelopes() {
+        assert!(Envelope::parse(b"plain").unwrap().is_none());
+        assert!(Envelope::parse(&[HEADER, 0, 1]).unwrap().is_none());
+    }
+}
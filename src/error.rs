@@ -3,6 +3,8 @@
 use neon::prelude::*;
 use once_cell::sync::OnceCell;
 
+use crate::limits::LimitExceeded;
+
 // Globally store a static reference to the `TRANSACTION_ERROR` class
 pub struct ClientErrors {
     pub operation_after_commit_error: OnceCell<Root<JsFunction>>,
@@ -10,6 +12,7 @@ pub struct ClientErrors {
     pub write_conlict_error: OnceCell<Root<JsFunction>>,
     pub already_exist_error: OnceCell<Root<JsFunction>>,
     pub daedlock_error: OnceCell<Root<JsFunction>>,
+    pub transaction_limit_error: OnceCell<Root<JsFunction>>,
 }
 
 pub static CLIENT_ERRORS: ClientErrors = ClientErrors {
@@ -18,6 +21,7 @@ pub static CLIENT_ERRORS: ClientErrors = ClientErrors {
     write_conlict_error: OnceCell::new(),
     already_exist_error: OnceCell::new(),
     daedlock_error: OnceCell::new(),
+    transaction_limit_error: OnceCell::new(),
 };
 
 pub trait CustomError {
@@ -59,5 +63,31 @@ pub fn init(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     CLIENT_ERRORS
         .daedlock_error
         .get_or_try_init(|| Ok(cx.argument::<JsFunction>(4)?.root(&mut cx)))?;
+    CLIENT_ERRORS
+        .transaction_limit_error
+        .get_or_try_init(|| Ok(cx.argument::<JsFunction>(5)?.root(&mut cx)))?;
     Ok(cx.undefined())
 }
+
+/// A short, stable name for the kind of an error, used to label metrics.
+pub fn error_kind(err: &tikv_client::Error) -> &'static str {
+    match err {
+        tikv_client::Error::OperationAfterCommitError => "operation_after_commit",
+        tikv_client::Error::UndeterminedError(_) => "undetermined",
+        tikv_client::Error::KeyError(e) => {
+            if e.conflict.is_some() {
+                "write_conflict"
+            } else if e.already_exist.is_some() {
+                "already_exist"
+            } else if e.deadlock.is_some() {
+                "deadlock"
+            } else {
+                "key_error"
+            }
+        }
+        tikv_client::Error::RegionError(_) => "region_error",
+        tikv_client::Error::Grpc(_) => "grpc",
+        err if LimitExceeded::find(err).is_some() => "limit_exceeded",
+        _ => "other",
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Failpoints make requests fail or stall on demand so that retry logic can be tested.
+//! A failpoint is named after the exported function it affects, e.g. `raw_get` or
+//! `txn_commit`, and is evaluated in the spawned task before the request is sent.
+//!
+//! The registry is only compiled in with the `failpoints` feature. Without it, `eval`
+//! does nothing and configuring a failpoint throws.
+
+pub use imp::*;
+
+#[cfg(feature = "failpoints")]
+mod imp {
+    use std::{collections::HashMap, io, sync::Mutex, time::Duration};
+
+    use lazy_static::lazy_static;
+    use neon::prelude::*;
+    use tikv_client::{Error, Result};
+    use tikv_client_proto::kvrpcpb;
+
+    lazy_static! {
+        static ref FAILPOINTS: Mutex<HashMap<String, Failpoint>> = Mutex::new(HashMap::new());
+    }
+
+    struct Failpoint {
+        error: Option<String>,
+        delay: Option<Duration>,
+        // Number of requests left to trigger on, `None` meaning every request.
+        remaining: Option<u32>,
+    }
+
+    pub async fn eval(name: &str) -> Result<()> {
+        let (error, delay) = {
+            let mut failpoints = FAILPOINTS.lock().unwrap();
+            let failpoint = match failpoints.get_mut(name) {
+                Some(failpoint) => failpoint,
+                None => return Ok(()),
+            };
+            let action = (failpoint.error.clone(), failpoint.delay);
+            if let Some(remaining) = &mut failpoint.remaining {
+                *remaining -= 1;
+                if *remaining == 0 {
+                    failpoints.remove(name);
+                }
+            }
+            action
+        };
+
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        match error {
+            Some(error) => Err(injected_error(&error)),
+            None => Ok(()),
+        }
+    }
+
+    fn injected_error(name: &str) -> Error {
+        match name {
+            "WriteConflict" => Error::KeyError(kvrpcpb::KeyError {
+                conflict: Some(Default::default()),
+                ..Default::default()
+            }),
+            "AlreadyExist" => Error::KeyError(kvrpcpb::KeyError {
+                already_exist: Some(Default::default()),
+                ..Default::default()
+            }),
+            "Deadlock" => Error::KeyError(kvrpcpb::KeyError {
+                deadlock: Some(Default::default()),
+                ..Default::default()
+            }),
+            "Undetermined" => Error::UndeterminedError(Box::new(Error::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "injected by failpoint",
+            )))),
+            "OperationAfterCommit" => Error::OperationAfterCommitError,
+            message => Error::Io(io::Error::new(io::ErrorKind::Other, message.to_owned())),
+        }
+    }
+
+    pub fn configure(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let name = cx.argument::<JsString>(0)?.value(&mut cx);
+        let options = cx.argument::<JsObject>(1)?;
+
+        let error = options.get(&mut cx, "error")?;
+        let error = error
+            .downcast::<JsString, _>(&mut cx)
+            .ok()
+            .map(|error| error.value(&mut cx));
+        let delay = options.get(&mut cx, "delay_ms")?;
+        let delay = delay
+            .downcast::<JsNumber, _>(&mut cx)
+            .ok()
+            .map(|delay| Duration::from_millis(delay.value(&mut cx) as u64));
+        let count = options.get(&mut cx, "count")?;
+        let remaining = count
+            .downcast::<JsNumber, _>(&mut cx)
+            .ok()
+            .map(|count| count.value(&mut cx) as u32);
+
+        let mut failpoints = FAILPOINTS.lock().unwrap();
+        if remaining == Some(0) || (error.is_none() && delay.is_none()) {
+            failpoints.remove(&name);
+        } else {
+            failpoints.insert(
+                name,
+                Failpoint {
+                    error,
+                    delay,
+                    remaining,
+                },
+            );
+        }
+        Ok(cx.undefined())
+    }
+
+    pub fn remove(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let name = cx.argument::<JsString>(0)?.value(&mut cx);
+        FAILPOINTS.lock().unwrap().remove(&name);
+        Ok(cx.undefined())
+    }
+}
+
+#[cfg(not(feature = "failpoints"))]
+mod imp {
+    use neon::prelude::*;
+    use tikv_client::Result;
+
+    pub async fn eval(_name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn configure(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        cx.throw_error("tikv-client was built without the `failpoints` feature")
+    }
+
+    pub fn remove(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        cx.throw_error("tikv-client was built without the `failpoints` feature")
+    }
+}
@@ -0,0 +1,368 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Scheduled garbage collection, with safepoints derived from the time of PD.
+//!
+//! Each run sets the safepoint to the current timestamp less the life time, the time old
+//! versions are kept for, but never past the read timestamp of a transaction or snapshot still
+//! active in this process. Transactions hold it back to their start timestamp from when they have
+//! begun until they commit or are dropped, and snapshots to their timestamp until they are
+//! dropped. Transactions and snapshots of other processes are not known.
+//!
+//! A run between a transaction taking its start timestamp and registering it can't pass it
+//! unless beginning took longer than the life time, as the run's safepoint is older by as much.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use neon::prelude::*;
+use tikv_client::{BoundRange, Key, KvPair, Result, Timestamp, TimestampExt as _, Value};
+use tokio::sync::oneshot;
+
+use crate::{
+    backend::{SnapshotHandle, TransactionBackend, TransactionHandle, TxnOptions},
+    error::error_kind,
+    transaction::at_physical_time,
+    utils::{js_millis_to_duration, RUNTIME},
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_LIFE_TIME: Duration = Duration::from_secs(10 * 60);
+
+lazy_static! {
+    /// The number of active transactions and snapshots by the timestamp they read at.
+    static ref ACTIVE: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
+}
+
+/// Holds the safepoint back to its timestamp while alive.
+struct Active(u64);
+
+impl Active {
+    fn register(ts: u64) -> Active {
+        *ACTIVE.lock().unwrap().entry(ts).or_default() += 1;
+        Active(ts)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        let mut active = ACTIVE.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.0);
+            }
+        }
+    }
+}
+
+fn oldest_active() -> Option<u64> {
+    ACTIVE.lock().unwrap().keys().next().copied()
+}
+
+/// Begin a transaction known to the schedulers of this process, including those started later.
+pub async fn begin(
+    backend: &dyn TransactionBackend,
+    options: TxnOptions,
+) -> Result<Box<dyn TransactionHandle>> {
+    let inner = backend.begin(options).await?;
+    let active = Active::register(inner.start_timestamp()?.version());
+    Ok(Box::new(ActiveTransaction {
+        inner,
+        active: Some(active),
+    }))
+}
+
+struct ActiveTransaction {
+    inner: Box<dyn TransactionHandle>,
+    active: Option<Active>,
+}
+
+#[async_trait]
+impl TransactionHandle for ActiveTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        self.inner.get(key).await
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        self.inner.get_for_update(key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get(keys).await
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get_for_update(keys).await
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.scan(range, limit).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        self.inner.lock_keys(keys).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        self.inner.put(key, value).await
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        self.inner.insert(key, value).await
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    fn start_timestamp(&self) -> Result<Timestamp> {
+        self.inner.start_timestamp()
+    }
+
+    /// The transaction is over once committed, whether or not the commit succeeded.
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        let result = self.inner.commit().await;
+        self.active = None;
+        result
+    }
+}
+
+/// Snapshot at `timestamp`, known to the schedulers of this process until dropped.
+pub fn snapshot(
+    backend: &dyn TransactionBackend,
+    timestamp: Timestamp,
+    options: TxnOptions,
+) -> Box<dyn SnapshotHandle> {
+    let active = Active::register(timestamp.version());
+    Box::new(ActiveSnapshot {
+        inner: backend.snapshot(timestamp, options),
+        _active: active,
+    })
+}
+
+struct ActiveSnapshot {
+    inner: Box<dyn SnapshotHandle>,
+    _active: Active,
+}
+
+#[async_trait]
+impl SnapshotHandle for ActiveSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        self.inner.get(key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get(keys).await
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.scan(range, limit).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GcOptions {
+    interval: Duration,
+    life_time: Duration,
+}
+
+impl GcOptions {
+    pub fn from_js<'a, C: Context<'a>>(
+        cx: &mut C,
+        options: Handle<JsObject>,
+    ) -> NeonResult<GcOptions> {
+        let interval = match options.get(cx, "interval_ms")?.downcast::<JsNumber, _>(cx) {
+            Ok(interval_ms) => {
+                let interval_ms = interval_ms.value(cx).max(1.0);
+                js_millis_to_duration(cx, "GC interval", interval_ms)?
+            }
+            Err(_) => DEFAULT_INTERVAL,
+        };
+        let life_time = match options.get(cx, "life_time_ms")?.downcast::<JsNumber, _>(cx) {
+            Ok(life_time_ms) => {
+                let life_time_ms = life_time_ms.value(cx).max(0.0);
+                js_millis_to_duration(cx, "GC life time", life_time_ms)?
+            }
+            Err(_) => DEFAULT_LIFE_TIME,
+        };
+        Ok(GcOptions {
+            interval,
+            life_time,
+        })
+    }
+}
+
+/// The outcome of a run, reported to JS.
+struct GcRun {
+    safepoint: Option<u64>,
+    oldest_active: Option<u64>,
+    /// Whether PD took the safepoint, which it doesn't if it's older than the current one.
+    updated: Result<bool>,
+    elapsed: Duration,
+}
+
+async fn run(backend: &dyn TransactionBackend, life_time: Duration) -> GcRun {
+    let start = Instant::now();
+    let oldest_active = oldest_active();
+    let safepoint = match backend.current_timestamp().await {
+        Ok(now) => {
+            let expired = at_physical_time(now.physical - life_time.as_millis() as i64).version();
+            oldest_active.map_or(expired, |oldest| expired.min(oldest))
+        }
+        Err(err) => {
+            return GcRun {
+                safepoint: None,
+                oldest_active,
+                updated: Err(err),
+                elapsed: start.elapsed(),
+            }
+        }
+    };
+    let updated = backend.gc(Timestamp::from_version(safepoint)).await;
+    GcRun {
+        safepoint: Some(safepoint),
+        oldest_active,
+        updated,
+        elapsed: start.elapsed(),
+    }
+}
+
+pub struct GcScheduler {
+    stop: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl Finalize for GcScheduler {}
+
+impl GcScheduler {
+    /// Run GC every interval until stopped, reporting each run to `callback`.
+    pub fn spawn(
+        backend: Arc<dyn TransactionBackend>,
+        options: GcOptions,
+        queue: EventQueue,
+        callback: Root<JsFunction>,
+    ) -> GcScheduler {
+        let callback = Arc::new(callback);
+        let (stop, mut stopped) = oneshot::channel();
+        RUNTIME.spawn(async move {
+            let mut interval = tokio::time::interval(options.interval);
+            loop {
+                // Dropping the scheduler stops it too.
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    _ = interval.tick() => {}
+                }
+                let outcome = run(&*backend, options.life_time).await;
+                report(&queue, callback.clone(), outcome);
+            }
+        });
+        GcScheduler {
+            stop: Mutex::new(Some(stop)),
+        }
+    }
+
+    pub fn stop(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let scheduler = cx
+            .this()
+            .downcast_or_throw::<JsBox<GcScheduler>, _>(&mut cx)?;
+        if let Some(stop) = scheduler.stop.lock().unwrap().take() {
+            let _ = stop.send(());
+        }
+        Ok(cx.undefined())
+    }
+}
+
+fn report(queue: &EventQueue, callback: Arc<Root<JsFunction>>, run: GcRun) {
+    queue.send(move |mut cx| {
+        let record = cx.empty_object();
+        let safepoint: Handle<JsValue> = match run.safepoint {
+            Some(safepoint) => cx.number(safepoint as f64).upcast(),
+            None => cx.null().upcast(),
+        };
+        record.set(&mut cx, "safepoint", safepoint)?;
+        let oldest_active: Handle<JsValue> = match run.oldest_active {
+            Some(oldest_active) => cx.number(oldest_active as f64).upcast(),
+            None => cx.null().upcast(),
+        };
+        record.set(&mut cx, "oldest_active_ts", oldest_active)?;
+        match run.updated {
+            Ok(updated) => {
+                let updated = cx.boolean(updated);
+                record.set(&mut cx, "updated", updated)?;
+            }
+            Err(err) => {
+                let updated = cx.boolean(false);
+                record.set(&mut cx, "updated", updated)?;
+                let kind = cx.string(error_kind(&err));
+                record.set(&mut cx, "error", kind)?;
+                let message = cx.string(err.to_string());
+                record.set(&mut cx, "message", message)?;
+            }
+        }
+        let elapsed_ms = cx.number(run.elapsed.as_secs_f64() * 1000.0);
+        record.set(&mut cx, "elapsed_ms", elapsed_ms)?;
+
+        let callback = callback.to_inner(&mut cx);
+        let this = cx.undefined();
+        callback.call(&mut cx, this, vec![record])?;
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mock::{MockStore, MockTransactionClient};
+
+    fn is_active(ts: &Timestamp) -> bool {
+        ACTIVE.lock().unwrap().contains_key(&ts.version())
+    }
+
+    #[test]
+    fn transactions_and_snapshots_hold_the_safepoint_back() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let options = TxnOptions::default();
+
+            let ts = client.current_timestamp().await.unwrap();
+            let snapshot = snapshot(&client, ts.clone(), options);
+            assert!(is_active(&ts));
+            drop(snapshot);
+            assert!(!is_active(&ts));
+
+            let mut txn = begin(&client, options).await.unwrap();
+            let oldest = oldest_active().unwrap();
+            assert_eq!(oldest, txn.start_timestamp().unwrap().version());
+            assert!(oldest > ts.version());
+
+            // Let the clock pass the oldest transaction, so that it holds the safepoint back.
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            let run = run(&client, Duration::from_secs(0)).await;
+            assert_eq!(run.safepoint, Some(oldest));
+            txn.commit().await.unwrap();
+            assert!(!ACTIVE.lock().unwrap().contains_key(&oldest));
+        });
+    }
+}
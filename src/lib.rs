@@ -2,35 +2,70 @@
 
 use std::sync::Arc;
 
+use backend::{RawBackend, SnapshotHandle, TransactionBackend, TransactionHandle};
+use coalesce::{Coalescer, Coalescing};
+use codec::Codec;
 use error::init;
+use limits::Limits;
 use neon::prelude::*;
 use tokio::sync::Mutex;
 
+mod backend;
+mod buffer;
+mod cache;
+mod coalesce;
+mod codec;
+mod compression;
+mod encryption;
 mod error;
+mod failpoint;
+mod gc;
+mod limits;
+mod locks;
+mod logger;
+mod metrics;
+mod mock;
 mod raw;
+mod read_only;
+mod region;
+mod replica;
+mod slowlog;
+mod store;
+mod subspace;
+mod trace;
 mod transaction;
+mod tuple;
 mod utils;
 
 pub struct RawClient {
-    inner: Arc<tikv_client::RawClient>,
+    inner: Arc<dyn RawBackend>,
+    codec: Codec,
 }
 
 impl Finalize for RawClient {}
 
 pub struct TransactionClient {
-    inner: Arc<tikv_client::TransactionClient>,
+    inner: Arc<dyn TransactionBackend>,
+    codec: Codec,
+    /// Whether gets of its transactions are coalesced.
+    coalescing: Option<Coalescing>,
+    /// The limits its transactions are held to, if any.
+    limits: Option<Limits>,
 }
 
 impl Finalize for TransactionClient {}
 
 pub struct Transaction {
-    inner: Arc<Mutex<tikv_client::Transaction>>,
+    inner: Arc<Mutex<Box<dyn TransactionHandle>>>,
+    codec: Codec,
+    coalescer: Option<Arc<Coalescer>>,
 }
 
 impl Finalize for Transaction {}
 
 pub struct Snapshot {
-    inner: Arc<Mutex<tikv_client::Snapshot>>,
+    inner: Arc<Mutex<Box<dyn SnapshotHandle>>>,
+    codec: Codec,
 }
 
 impl Finalize for Snapshot {}
@@ -38,24 +73,69 @@ impl Finalize for Snapshot {}
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("raw_connect", RawClient::connect)?;
+    cx.export_function("raw_subspace", RawClient::subspace)?;
+    cx.export_function("raw_with_codec", RawClient::with_codec)?;
+    cx.export_function("raw_with_key_type", RawClient::with_key_type)?;
+    cx.export_function("raw_with_compression", RawClient::with_compression)?;
+    cx.export_function("raw_train_dictionary", RawClient::train_dictionary)?;
+    cx.export_function("raw_with_encryption", RawClient::with_encryption)?;
+    cx.export_function("raw_with_coalescing", RawClient::with_coalescing)?;
+    cx.export_function("raw_with_cache", RawClient::with_cache)?;
     cx.export_function("raw_put", RawClient::put)?;
     cx.export_function("raw_get", RawClient::get)?;
+    cx.export_function("raw_put_with_ttl", RawClient::put_with_ttl)?;
+    cx.export_function("raw_get_key_ttl", RawClient::get_key_ttl)?;
     cx.export_function("raw_delete", RawClient::delete)?;
     cx.export_function("raw_batch_get", RawClient::batch_get)?;
     cx.export_function("raw_scan", RawClient::scan)?;
     cx.export_function("raw_scan_keys", RawClient::scan_keys)?;
+    cx.export_function("raw_batch_scan", RawClient::batch_scan)?;
+    cx.export_function("raw_batch_scan_keys", RawClient::batch_scan_keys)?;
     cx.export_function("raw_batch_put", RawClient::batch_put)?;
     cx.export_function("raw_batch_delete", RawClient::batch_delete)?;
     cx.export_function("raw_delete_range", RawClient::delete_range)?;
+    cx.export_function("raw_checksum", RawClient::checksum)?;
+    cx.export_function("raw_coprocessor", RawClient::coprocessor)?;
+    cx.export_function("raw_locate_key", RawClient::locate_key)?;
+    cx.export_function("raw_regions", RawClient::regions)?;
+    cx.export_function("raw_stores", RawClient::stores)?;
 
     cx.export_function("txn_connect", TransactionClient::connect)?;
+    cx.export_function("txn_client_with_codec", TransactionClient::with_codec)?;
+    cx.export_function("txn_client_with_key_type", TransactionClient::with_key_type)?;
+    cx.export_function(
+        "txn_client_with_compression",
+        TransactionClient::with_compression,
+    )?;
+    cx.export_function(
+        "txn_client_with_encryption",
+        TransactionClient::with_encryption,
+    )?;
+    cx.export_function(
+        "txn_client_with_coalescing",
+        TransactionClient::with_coalescing,
+    )?;
+    cx.export_function("txn_client_with_limits", TransactionClient::with_limits)?;
     cx.export_function("txn_begin", TransactionClient::begin)?;
+    cx.export_function("txn_begin_read_only", TransactionClient::begin_read_only)?;
     cx.export_function("txn_snapshot", TransactionClient::snapshot)?;
+    cx.export_function("txn_snapshot_stale", TransactionClient::snapshot_stale)?;
+    cx.export_function("txn_snapshot_at_time", TransactionClient::snapshot_at_time)?;
     cx.export_function(
         "txn_current_timestamp",
         TransactionClient::current_timestamp,
     )?;
     cx.export_function("txn_gc", TransactionClient::gc)?;
+    cx.export_function("txn_start_gc", TransactionClient::start_gc)?;
+    cx.export_function("gc_scheduler_stop", gc::GcScheduler::stop)?;
+    cx.export_function("txn_scan_locks", TransactionClient::scan_locks)?;
+    cx.export_function("txn_cleanup_locks", TransactionClient::cleanup_locks)?;
+    cx.export_function("txn_locate_key", TransactionClient::locate_key)?;
+    cx.export_function("txn_regions", TransactionClient::regions)?;
+    cx.export_function("txn_stores", TransactionClient::stores)?;
+    cx.export_function("txn_subspace", Transaction::subspace)?;
+    cx.export_function("txn_with_codec", Transaction::with_codec)?;
+    cx.export_function("txn_with_key_type", Transaction::with_key_type)?;
     cx.export_function("txn_get", Transaction::get)?;
     cx.export_function("txn_get_for_update", Transaction::get_for_update)?;
     cx.export_function("txn_key_exists", Transaction::key_exists)?;
@@ -70,14 +150,37 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("txn_put", Transaction::put)?;
     cx.export_function("txn_insert", Transaction::insert)?;
     cx.export_function("txn_delete", Transaction::delete)?;
+    cx.export_function("txn_put_if_equals", Transaction::put_if_equals)?;
+    cx.export_function("txn_put_if_absent", Transaction::put_if_absent)?;
+    cx.export_function("txn_delete_if_equals", Transaction::delete_if_equals)?;
+    cx.export_function("txn_savepoint", Transaction::savepoint)?;
+    cx.export_function("txn_rollback_to", Transaction::rollback_to)?;
+    cx.export_function("txn_pending_mutations", Transaction::pending_mutations)?;
+    cx.export_function("txn_mutation_count", Transaction::mutation_count)?;
+    cx.export_function("txn_mutation_bytes", Transaction::mutation_bytes)?;
     cx.export_function("txn_commit", Transaction::commit)?;
 
+    cx.export_function("snapshot_subspace", Snapshot::subspace)?;
+    cx.export_function("snapshot_with_codec", Snapshot::with_codec)?;
+    cx.export_function("snapshot_with_key_type", Snapshot::with_key_type)?;
+    cx.export_function("snapshot_with_cache", Snapshot::with_cache)?;
     cx.export_function("snapshot_get", Snapshot::get)?;
     cx.export_function("snapshot_key_exists", Snapshot::key_exists)?;
     cx.export_function("snapshot_batch_get", Snapshot::batch_get)?;
     cx.export_function("snapshot_scan", Snapshot::scan)?;
     cx.export_function("snapshot_scan_keys", Snapshot::scan_keys)?;
 
+    cx.export_function("set_logger", logger::set_logger)?;
+    cx.export_function("get_metrics", metrics::get_metrics)?;
+    cx.export_function("failpoint_configure", failpoint::configure)?;
+    cx.export_function("failpoint_remove", failpoint::remove)?;
+    cx.export_function("tuple_pack", tuple::pack)?;
+    cx.export_function("tuple_unpack", tuple::unpack)?;
+    cx.export_function("tuple_range", tuple::range)?;
+    cx.export_function("set_slow_log", slowlog::set_slow_log)?;
+    cx.export_function("configure_tracing", trace::configure_tracing)?;
+    cx.export_function("set_trace_context", trace::set_trace_context)?;
+
     cx.export_function("init", init)?;
     Ok(())
 }
@@ -0,0 +1,366 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Limits on the size and age of transactions, checked as they write.
+//!
+//! A transaction too big for TiKV only fails at commit, after every write was buffered. With
+//! limits set, the write which would take a transaction past one fails at once instead, and the
+//! transaction stays as it was before it. Keys only locked are not counted.
+
+use std::{
+    collections::HashMap,
+    error, fmt, io,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use neon::prelude::*;
+use tikv_client::{BoundRange, Error, Key, KvPair, Result, Timestamp, Value};
+
+use crate::{
+    backend::{PendingMutation, TransactionHandle},
+    utils::js_millis_to_duration,
+};
+
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    max_keys: Option<usize>,
+    /// Of the keys and values written, as encoded by the codec.
+    max_bytes: Option<usize>,
+    max_value_size: Option<usize>,
+    max_duration: Option<Duration>,
+}
+
+impl Limits {
+    pub fn from_js<'a, C: Context<'a>>(
+        cx: &mut C,
+        options: Handle<JsObject>,
+    ) -> NeonResult<Limits> {
+        let mut number = |name: &str| -> NeonResult<Option<f64>> {
+            let number = match options.get(cx, name)?.downcast::<JsNumber, _>(cx) {
+                Ok(number) => number.value(cx),
+                Err(_) => return Ok(None),
+            };
+            if !number.is_finite() || number < 0.0 {
+                return cx.throw_range_error(format!("Invalid {}: {}", name, number));
+            }
+            Ok(Some(number))
+        };
+        let max_keys = number("max_keys")?.map(|max| max as usize);
+        let max_bytes = number("max_bytes")?.map(|max| max as usize);
+        let max_value_size = number("max_value_size")?.map(|max| max as usize);
+        let max_duration = match number("max_duration_ms")? {
+            Some(ms) => Some(js_millis_to_duration(cx, "max_duration_ms", ms)?),
+            None => None,
+        };
+        Ok(Limits {
+            max_keys,
+            max_bytes,
+            max_value_size,
+            max_duration,
+        })
+    }
+}
+
+/// The limit a write would have taken its transaction past.
+#[derive(Debug)]
+pub enum LimitExceeded {
+    Keys(usize),
+    Bytes(usize),
+    ValueSize(usize),
+    Duration(Duration),
+}
+
+impl LimitExceeded {
+    /// The limit `err` reports exceeding, if it does.
+    pub fn find(err: &Error) -> Option<&LimitExceeded> {
+        match err {
+            Error::Io(err) => err.get_ref()?.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Keys(max) => write!(f, "Transaction would write more than {} keys", max),
+            LimitExceeded::Bytes(max) => {
+                write!(f, "Transaction would write more than {} bytes", max)
+            }
+            LimitExceeded::ValueSize(max) => write!(f, "Value is larger than {} bytes", max),
+            LimitExceeded::Duration(max) => write!(
+                f,
+                "Transaction has run for longer than {}ms",
+                max.as_millis()
+            ),
+        }
+    }
+}
+
+impl error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for Error {
+    fn from(exceeded: LimitExceeded) -> Error {
+        Error::Io(io::Error::new(io::ErrorKind::Other, exceeded))
+    }
+}
+
+pub struct LimitedTransaction {
+    inner: Box<dyn TransactionHandle>,
+    limits: Limits,
+    began: Instant,
+    /// The size of the last write to each key.
+    sizes: HashMap<Key, usize>,
+    bytes: usize,
+}
+
+impl LimitedTransaction {
+    pub fn new(inner: Box<dyn TransactionHandle>, limits: Limits) -> LimitedTransaction {
+        LimitedTransaction {
+            inner,
+            limits,
+            began: Instant::now(),
+            sizes: HashMap::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Check a write of `value` to `key`, a delete if `None`, returning its size.
+    fn check(&self, key: &Key, value: Option<&Value>) -> Result<usize> {
+        if let Some(max) = self.limits.max_duration {
+            if self.began.elapsed() > max {
+                return Err(LimitExceeded::Duration(max).into());
+            }
+        }
+        let value_size = value.map_or(0, Vec::len);
+        if let Some(max) = self.limits.max_value_size {
+            if value_size > max {
+                return Err(LimitExceeded::ValueSize(max).into());
+            }
+        }
+        let size = <&[u8]>::from(key).len() + value_size;
+        let previous = self.sizes.get(key);
+        if let Some(max) = self.limits.max_keys {
+            if previous.is_none() && self.sizes.len() >= max {
+                return Err(LimitExceeded::Keys(max).into());
+            }
+        }
+        if let Some(max) = self.limits.max_bytes {
+            if self.bytes - previous.copied().unwrap_or(0) + size > max {
+                return Err(LimitExceeded::Bytes(max).into());
+            }
+        }
+        Ok(size)
+    }
+
+    fn record(&mut self, key: Key, size: usize) {
+        let previous = self.sizes.insert(key, size);
+        self.bytes = self.bytes - previous.unwrap_or(0) + size;
+    }
+}
+
+#[async_trait]
+impl TransactionHandle for LimitedTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        self.inner.get(key).await
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        self.inner.get_for_update(key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get(keys).await
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get_for_update(keys).await
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.scan(range, limit).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        self.inner.lock_keys(keys).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        let size = self.check(&key, Some(&value))?;
+        self.inner.put(key.clone(), value).await?;
+        self.record(key, size);
+        Ok(())
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        let size = self.check(&key, Some(&value))?;
+        self.inner.insert(key.clone(), value).await?;
+        self.record(key, size);
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        let size = self.check(&key, None)?;
+        self.inner.delete(key.clone()).await?;
+        self.record(key, size);
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.inner.commit().await
+    }
+
+    async fn savepoint(&mut self, name: String) -> Result<()> {
+        self.inner.savepoint(name).await
+    }
+
+    /// The writes rolled back no longer count.
+    async fn rollback_to(&mut self, name: String) -> Result<()> {
+        self.inner.rollback_to(name).await?;
+        self.sizes.clear();
+        self.bytes = 0;
+        for mutation in self.inner.pending_mutations().await? {
+            if !matches!(mutation, PendingMutation::Lock(_)) {
+                let size = mutation.size();
+                self.record(mutation.key().clone(), size);
+            }
+        }
+        Ok(())
+    }
+
+    async fn pending_mutations(&mut self) -> Result<Vec<PendingMutation>> {
+        self.inner.pending_mutations().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        backend::{TransactionBackend, TxnOptions},
+        buffer::WriteBuffer,
+        mock::{MockStore, MockTransactionClient},
+        utils::RUNTIME,
+    };
+
+    async fn begin(limits: Limits, pessimistic: bool) -> LimitedTransaction {
+        let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+        let options = TxnOptions {
+            pessimistic,
+            read_only: false,
+            replica_read: false,
+        };
+        let txn = WriteBuffer::new(client.begin(options).await.unwrap());
+        LimitedTransaction::new(Box::new(txn), limits)
+    }
+
+    fn key(key: &str) -> Key {
+        Key::from(key.to_owned())
+    }
+
+    #[test]
+    fn keys_are_counted_once() {
+        RUNTIME.block_on(async {
+            let limits = Limits {
+                max_keys: Some(2),
+                ..Limits::default()
+            };
+            let mut txn = begin(limits, false).await;
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.put(key("a"), b"2".to_vec()).await.unwrap();
+            txn.delete(key("b")).await.unwrap();
+            let err = txn.put(key("c"), b"1".to_vec()).await.unwrap_err();
+            assert!(matches!(
+                LimitExceeded::find(&err),
+                Some(LimitExceeded::Keys(2))
+            ));
+            txn.delete(key("a")).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn bytes_count_the_last_write_to_each_key() {
+        RUNTIME.block_on(async {
+            let limits = Limits {
+                max_bytes: Some(4),
+                ..Limits::default()
+            };
+            let mut txn = begin(limits, false).await;
+            txn.put(key("a"), b"12".to_vec()).await.unwrap();
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            txn.put(key("b"), b"1".to_vec()).await.unwrap();
+            let err = txn.put(key("c"), vec![]).await.unwrap_err();
+            assert!(matches!(
+                LimitExceeded::find(&err),
+                Some(LimitExceeded::Bytes(4))
+            ));
+            txn.delete(key("a")).await.unwrap();
+            txn.put(key("c"), vec![]).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn large_values_are_rejected() {
+        RUNTIME.block_on(async {
+            let limits = Limits {
+                max_value_size: Some(1),
+                ..Limits::default()
+            };
+            let mut txn = begin(limits, false).await;
+            txn.put(key("a"), b"1".to_vec()).await.unwrap();
+            let err = txn.insert(key("b"), b"12".to_vec()).await.unwrap_err();
+            assert!(matches!(
+                LimitExceeded::find(&err),
+                Some(LimitExceeded::ValueSize(1))
+            ));
+        });
+    }
+
+    #[test]
+    fn old_transactions_are_rejected() {
+        RUNTIME.block_on(async {
+            let limits = Limits {
+                max_duration: Some(Duration::from_secs(0)),
+                ..Limits::default()
+            };
+            let mut txn = begin(limits, false).await;
+            std::thread::sleep(Duration::from_millis(1));
+            let err = txn.put(key("a"), b"1".to_vec()).await.unwrap_err();
+            assert!(matches!(
+                LimitExceeded::find(&err),
+                Some(LimitExceeded::Duration(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn writes_rolled_back_and_locks_are_not_counted() {
+        RUNTIME.block_on(async {
+            let limits = Limits {
+                max_keys: Some(1),
+                ..Limits::default()
+            };
+            let mut txn = begin(limits, true).await;
+            txn.lock_keys(vec![key("a")]).await.unwrap();
+            txn.savepoint("s".to_owned()).await.unwrap();
+            txn.put(key("b"), b"1".to_vec()).await.unwrap();
+            let err = txn.put(key("c"), b"1".to_vec()).await.unwrap_err();
+            assert!(matches!(
+                LimitExceeded::find(&err),
+                Some(LimitExceeded::Keys(1))
+            ));
+            txn.rollback_to("s".to_owned()).await.unwrap();
+            txn.put(key("c"), b"1".to_vec()).await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Finding and cleaning up the locks transactions left behind.
+//!
+//! A transaction whose client crashed leaves its locks until another transaction meets them,
+//! blocking readers meanwhile. Cleaning up resolves each expired lock the way a reader would: the
+//! primary lock of its transaction tells whether it committed, rolling it back if it expired,
+//! and the lock is then committed or rolled back alike. Locks still within their TTL are left.
+//!
+//! The Rust client keeps its lock resolver to itself, so the requests are sent to the leaders
+//! with `store::Stores`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use neon::prelude::*;
+use opentelemetry::KeyValue;
+use tikv_client::{BoundRange, Error, Result, Timestamp, TimestampExt as _};
+use tikv_client_proto::{kvrpcpb, metapb};
+
+use crate::{
+    codec::Decoded,
+    store::{reaches, Replica, Stores},
+    trace,
+    utils::ToJS,
+};
+
+const PHYSICAL_SHIFT_BITS: u64 = 18;
+/// The locks scanned and resolved at a time while cleaning up.
+const CLEANUP_BATCH: u32 = 1024;
+
+#[derive(Clone, Debug)]
+pub struct LockInfo {
+    pub key: Vec<u8>,
+    /// The key of the primary lock of its transaction.
+    pub primary: Vec<u8>,
+    pub start_ts: u64,
+    /// In milliseconds from `start_ts`.
+    pub ttl: u64,
+}
+
+impl LockInfo {
+    pub fn is_expired(&self, now: &Timestamp) -> bool {
+        (self.start_ts >> PHYSICAL_SHIFT_BITS) + self.ttl <= now.version() >> PHYSICAL_SHIFT_BITS
+    }
+}
+
+impl From<kvrpcpb::LockInfo> for LockInfo {
+    fn from(lock: kvrpcpb::LockInfo) -> LockInfo {
+        LockInfo {
+            key: lock.key,
+            primary: lock.primary_lock,
+            start_ts: lock.lock_version,
+            ttl: lock.lock_ttl,
+        }
+    }
+}
+
+/// The start and end keys of `range`, an empty end key meaning the end of the key space.
+pub fn range_keys(range: BoundRange) -> (Vec<u8>, Vec<u8>) {
+    let (start, end) = range.into_keys();
+    (start.into(), end.map(Vec::from).unwrap_or_default())
+}
+
+pub struct TikvLocks {
+    stores: Arc<Stores>,
+}
+
+impl TikvLocks {
+    pub fn new(stores: Arc<Stores>) -> TikvLocks {
+        TikvLocks { stores }
+    }
+
+    pub fn stores(&self) -> &Arc<Stores> {
+        &self.stores
+    }
+
+    /// The locks from `start` to `end` of transactions started at or before `max_ts`, at most
+    /// `limit`, in key order.
+    pub async fn scan(
+        &self,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        max_ts: Timestamp,
+        limit: u32,
+    ) -> Result<Vec<LockInfo>> {
+        let mut key = start;
+        let mut locks = vec![];
+        while (locks.len() as u32) < limit {
+            let build = |region: &metapb::Region| kvrpcpb::ScanLockRequest {
+                max_version: max_ts.version(),
+                start_key: key.clone(),
+                end_key: if reaches(region, &end) {
+                    end.clone()
+                } else {
+                    region.end_key.clone()
+                },
+                limit: limit - locks.len() as u32,
+                ..Default::default()
+            };
+            let (region, mut response) = self
+                .stores
+                .send(
+                    &key,
+                    Replica::Leader,
+                    build,
+                    |response: &mut kvrpcpb::ScanLockResponse| response.region_error.take(),
+                )
+                .await?;
+            check_key_error(response.error.take())?;
+            locks.extend(response.locks.into_iter().map(LockInfo::from));
+            if reaches(&region, &end) {
+                break;
+            }
+            key = region.end_key;
+        }
+        Ok(locks)
+    }
+
+    /// Resolve the expired locks from `start` to `end` of transactions started at or before
+    /// `safepoint`, returning them. A dry run only returns them.
+    pub async fn cleanup(
+        &self,
+        start: Vec<u8>,
+        end: Vec<u8>,
+        safepoint: Timestamp,
+        now: Timestamp,
+        dry_run: bool,
+    ) -> Result<Vec<LockInfo>> {
+        let mut commit_versions: HashMap<u64, Option<u64>> = HashMap::new();
+        let mut resolved = vec![];
+        let mut key = start;
+        loop {
+            let locks = self
+                .scan(key, end.clone(), safepoint.clone(), CLEANUP_BATCH)
+                .await?;
+            let is_last = (locks.len() as u32) < CLEANUP_BATCH;
+            key = match locks.last() {
+                Some(lock) => [lock.key.as_slice(), &[0]].concat(),
+                None => break,
+            };
+            for lock in locks.into_iter().filter(|lock| lock.is_expired(&now)) {
+                if dry_run {
+                    resolved.push(lock);
+                    continue;
+                }
+                let span = trace::start_child_span(
+                    "resolve_lock",
+                    vec![KeyValue::new("tikv.lock.start_ts", lock.start_ts as i64)],
+                );
+                let result = self.resolve(&lock, &mut commit_versions, &now).await;
+                trace::end_child_span(span, &result);
+                // The primary lock is still alive, so the transaction may yet commit.
+                if !result? {
+                    continue;
+                }
+                resolved.push(lock);
+            }
+            if is_last {
+                break;
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Commit or roll back `lock` as its transaction was, returning whether it was. The outcome
+    /// of each transaction is kept in `commit_versions`, as returned by `cleanup_primary`.
+    pub async fn resolve(
+        &self,
+        lock: &LockInfo,
+        commit_versions: &mut HashMap<u64, Option<u64>>,
+        now: &Timestamp,
+    ) -> Result<bool> {
+        let commit_version = match commit_versions.get(&lock.start_ts) {
+            Some(commit_version) => *commit_version,
+            None => {
+                let commit_version = self.cleanup_primary(lock, now).await?;
+                commit_versions.insert(lock.start_ts, commit_version);
+                commit_version
+            }
+        };
+        let commit_version = match commit_version {
+            Some(commit_version) => commit_version,
+            None => return Ok(false),
+        };
+        let build = |_: &metapb::Region| kvrpcpb::ResolveLockRequest {
+            start_version: lock.start_ts,
+            commit_version,
+            keys: vec![lock.key.clone()],
+            ..Default::default()
+        };
+        let (_, mut response) = self
+            .stores
+            .send(
+                &lock.key,
+                Replica::Leader,
+                build,
+                |response: &mut kvrpcpb::ResolveLockResponse| response.region_error.take(),
+            )
+            .await?;
+        check_key_error(response.error.take())?;
+        Ok(true)
+    }
+
+    /// Commit or roll back the transaction of `lock` by its primary lock, returning its commit
+    /// timestamp, 0 if it was rolled back, or `None` if the primary lock has not expired.
+    async fn cleanup_primary(&self, lock: &LockInfo, now: &Timestamp) -> Result<Option<u64>> {
+        let build = |_: &metapb::Region| kvrpcpb::CleanupRequest {
+            key: lock.primary.clone(),
+            start_version: lock.start_ts,
+            current_ts: now.version(),
+            ..Default::default()
+        };
+        let (_, mut response) = self
+            .stores
+            .send(
+                &lock.primary,
+                Replica::Leader,
+                build,
+                |response: &mut kvrpcpb::CleanupResponse| response.region_error.take(),
+            )
+            .await?;
+        match response.error.take() {
+            Some(error) if error.locked.is_some() => Ok(None),
+            error => {
+                check_key_error(error)?;
+                Ok(Some(response.commit_version))
+            }
+        }
+    }
+}
+
+fn check_key_error(key_error: Option<kvrpcpb::KeyError>) -> Result<()> {
+    match key_error {
+        Some(error) => Err(Error::KeyError(error)),
+        None => Ok(()),
+    }
+}
+
+impl ToJS for Decoded<Vec<LockInfo>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let locks = JsArray::new(cx, self.values.len() as u32);
+        for (i, lock) in self.values.into_iter().enumerate() {
+            let object = cx.empty_object();
+            let key = self.codec.key(cx, lock.key)?;
+            object.set(cx, "key", key)?;
+            let primary = self.codec.key(cx, lock.primary)?;
+            object.set(cx, "primary", primary)?;
+            let start_ts = cx.number(lock.start_ts as f64);
+            object.set(cx, "start_ts", start_ts)?;
+            let ttl = cx.number(lock.ttl as f64);
+            object.set(cx, "ttl", ttl)?;
+            locks.set(cx, i as u32, object)?;
+        }
+        Ok(locks.upcast())
+    }
+}
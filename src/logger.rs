@@ -0,0 +1,252 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Forwards log records to a JS function installed with `set_logger`.
+//!
+//! The binding logs through the `log` crate, while the TiKV client logs through the
+//! `slog` logger handed to it on connect. Both end up in `forward`, which rate limits
+//! records before queueing them on the JS thread so a noisy client cannot flood the
+//! event loop.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
+
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use neon::prelude::*;
+use slog::{Drain, OwnedKVList, KV};
+
+const DEFAULT_MAX_RECORDS_PER_SECOND: f64 = 100.0;
+
+lazy_static! {
+    static ref LOGGER: RwLock<Option<JsLogger>> = RwLock::new(None);
+}
+
+struct JsLogger {
+    queue: EventQueue,
+    callback: Arc<Root<JsFunction>>,
+    level: LevelFilter,
+    limiter: Mutex<RateLimiter>,
+}
+
+// A token bucket refilled at `rate` tokens per second, holding at most one second's worth, and
+// at least one token so rates below one record per second still let records through.
+struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+    dropped: u64,
+}
+
+impl RateLimiter {
+    fn new(rate: f64) -> RateLimiter {
+        RateLimiter {
+            rate,
+            capacity: rate.max(1.0),
+            tokens: rate.max(1.0),
+            last_refill: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    /// Take a token, returning how many records were dropped since the last one that got
+    /// through, or `None` if this record must be dropped too.
+    fn acquire(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            self.dropped += 1;
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(std::mem::replace(&mut self.dropped, 0))
+    }
+}
+
+fn forward(level: Level, target: &str, message: String, mut fields: Vec<(String, String)>) {
+    let logger = LOGGER.read().unwrap();
+    let logger = match &*logger {
+        Some(logger) if level <= logger.level => logger,
+        _ => return,
+    };
+    let dropped = match logger.limiter.lock().unwrap().acquire() {
+        Some(dropped) => dropped,
+        None => return,
+    };
+    if dropped > 0 {
+        fields.push(("dropped_records".to_owned(), dropped.to_string()));
+    }
+
+    let callback = logger.callback.clone();
+    let target = target.to_owned();
+    logger.queue.send(move |mut cx| {
+        let record = cx.empty_object();
+        let level = cx.string(level.to_string().to_lowercase());
+        record.set(&mut cx, "level", level)?;
+        let target = cx.string(target);
+        record.set(&mut cx, "target", target)?;
+        let message = cx.string(message);
+        record.set(&mut cx, "message", message)?;
+        let js_fields = cx.empty_object();
+        for (key, value) in fields {
+            let value = cx.string(value);
+            js_fields.set(&mut cx, key.as_str(), value)?;
+        }
+        record.set(&mut cx, "fields", js_fields)?;
+
+        let callback = callback.to_inner(&mut cx);
+        let this = cx.undefined();
+        callback.call(&mut cx, this, vec![record])?;
+        Ok(())
+    });
+}
+
+struct LogBridge;
+
+impl Log for LogBridge {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            forward(
+                record.level(),
+                record.target(),
+                record.args().to_string(),
+                vec![],
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+struct SlogBridge;
+
+impl Drain for SlogBridge {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        let level = match record.level() {
+            slog::Level::Critical | slog::Level::Error => Level::Error,
+            slog::Level::Warning => Level::Warn,
+            slog::Level::Info => Level::Info,
+            slog::Level::Debug => Level::Debug,
+            slog::Level::Trace => Level::Trace,
+        };
+        let mut fields = FieldCollector(vec![]);
+        let _ = record.kv().serialize(record, &mut fields);
+        let _ = values.serialize(record, &mut fields);
+        forward(level, record.module(), record.msg().to_string(), fields.0);
+        Ok(())
+    }
+}
+
+struct FieldCollector(Vec<(String, String)>);
+
+impl slog::Serializer for FieldCollector {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), val.to_string()));
+        Ok(())
+    }
+}
+
+/// The logger to hand to the TiKV client on connect. It forwards to the JS logger installed at
+/// the time of each record, so loggers installed after connecting receive them too.
+pub fn slog_logger() -> slog::Logger {
+    slog::Logger::root(SlogBridge.fuse(), slog::o!())
+}
+
+fn parse_level(level: &str) -> Option<LevelFilter> {
+    match level {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+pub fn set_logger(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let callback = cx.argument_opt(0);
+    let callback = match callback.and_then(|c| c.downcast::<JsFunction, _>(&mut cx).ok()) {
+        Some(callback) => callback.root(&mut cx),
+        None => {
+            *LOGGER.write().unwrap() = None;
+            log::set_max_level(LevelFilter::Off);
+            return Ok(cx.undefined());
+        }
+    };
+    let level = cx.argument::<JsString>(1)?.value(&mut cx);
+    let level = match parse_level(&level) {
+        Some(level) => level,
+        None => return cx.throw_error(format!("Unknown log level: {}", level)),
+    };
+    let max_records_per_second = match cx.argument_opt(2) {
+        Some(rate) => match rate.downcast::<JsNumber, _>(&mut cx) {
+            Ok(rate) => rate.value(&mut cx),
+            Err(_) => DEFAULT_MAX_RECORDS_PER_SECOND,
+        },
+        None => DEFAULT_MAX_RECORDS_PER_SECOND,
+    };
+    // A rate of zero or less would drop every record, and NaN or infinity break the bucket.
+    if !max_records_per_second.is_finite() || max_records_per_second <= 0.0 {
+        return cx.throw_range_error(format!(
+            "Invalid max records per second: {}",
+            max_records_per_second
+        ));
+    }
+
+    let mut queue = cx.queue();
+    // Don't keep the process alive just to deliver log records.
+    queue.unref(&mut cx);
+    *LOGGER.write().unwrap() = Some(JsLogger {
+        queue,
+        callback: Arc::new(callback),
+        level,
+        limiter: Mutex::new(RateLimiter::new(max_records_per_second)),
+    });
+
+    // Installing fails if a logger is already set, in which case it is ours.
+    let _ = log::set_boxed_logger(Box::new(LogBridge));
+    log::set_max_level(level);
+    Ok(cx.undefined())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_past_the_rate_are_dropped_and_counted() {
+        let mut limiter = RateLimiter::new(2.0);
+        assert_eq!(limiter.acquire(), Some(0));
+        assert_eq!(limiter.acquire(), Some(0));
+        assert_eq!(limiter.acquire(), None);
+        assert_eq!(limiter.acquire(), None);
+
+        limiter.last_refill -= std::time::Duration::from_secs(1);
+        assert_eq!(limiter.acquire(), Some(2));
+    }
+
+    #[test]
+    fn rates_below_one_let_records_through() {
+        let mut limiter = RateLimiter::new(0.5);
+        assert_eq!(limiter.acquire(), Some(0));
+        assert_eq!(limiter.acquire(), None);
+
+        limiter.last_refill -= std::time::Duration::from_secs(1);
+        assert_eq!(limiter.acquire(), None);
+        limiter.last_refill -= std::time::Duration::from_secs(1);
+        assert_eq!(limiter.acquire(), Some(2));
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use neon::prelude::*;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec,
+    IntCounterVec, TextEncoder,
+};
+
+use crate::{error::error_kind, utils::Request};
+
+lazy_static! {
+    static ref REQUEST_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_client_node_requests_total",
+        "Total number of requests sent through the Node.js client",
+        &["method", "cf"]
+    )
+    .unwrap();
+    static ref REQUEST_ERROR_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_client_node_request_errors_total",
+        "Total number of failed requests sent through the Node.js client",
+        &["method", "cf", "error"]
+    )
+    .unwrap();
+    static ref REQUEST_DURATION_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "tikv_client_node_request_duration_seconds",
+        "Bucketed histogram of request duration",
+        &["method", "cf"],
+        exponential_buckets(0.0005, 2.0, 18).unwrap()
+    )
+    .unwrap();
+}
+
+pub fn observe<T>(request: &Request, duration: Duration, result: &Result<T, tikv_client::Error>) {
+    let cf = request.cf.as_deref().unwrap_or("");
+    REQUEST_COUNTER
+        .with_label_values(&[request.method, cf])
+        .inc();
+    REQUEST_DURATION_HISTOGRAM
+        .with_label_values(&[request.method, cf])
+        .observe(duration.as_secs_f64());
+    if let Err(err) = result {
+        REQUEST_ERROR_COUNTER
+            .with_label_values(&[request.method, cf, error_kind(err)])
+            .inc();
+    }
+}
+
+/// Render the metrics of this process, including those of the underlying TiKV client, in the
+/// Prometheus text exposition format.
+pub fn get_metrics(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut buffer = vec![];
+    if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        return cx.throw_error(err.to_string());
+    }
+    Ok(cx.string(String::from_utf8(buffer).unwrap()))
+}
@@ -0,0 +1,1075 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! An in-process stand-in for a TiKV cluster, selected by connecting to `mock://<name>`.
+//!
+//! Raw data is kept per column family in ordered maps, and values put with a TTL are dropped
+//! once it passes. Transactional data is kept as
+//! MVCC versions keyed by commit timestamp, so snapshots observe exactly what was
+//! committed before their timestamp. Pessimistic locks carry a TTL and conflict with
+//! other transactions until they expire; optimistic transactions detect write
+//! conflicts at commit time.
+
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tikv_client::{
+    BoundRange, ColumnFamily, Error, Key, KvPair, Result, Timestamp, TimestampExt as _, Value,
+};
+use tikv_client_proto::kvrpcpb;
+
+use crate::{
+    backend::{
+        Checksum, CoprocessorResponse, RawBackend, SnapshotHandle, TransactionBackend,
+        TransactionHandle, TxnOptions, MOCK_SCHEME,
+    },
+    locks::LockInfo,
+    region::{PeerInfo, PlacementBackend, RegionInfo, StoreInfo},
+};
+
+const PHYSICAL_SHIFT_BITS: u64 = 18;
+const LOCK_TTL_MS: u64 = 3000;
+const MAX_RAW_KV_SCAN_LIMIT: u32 = 10240;
+const DEFAULT_CF: &str = "Default";
+
+lazy_static! {
+    static ref STORES: Mutex<HashMap<String, Arc<MockStore>>> = Mutex::new(HashMap::new());
+}
+
+/// Get the store registered under `name`, creating an empty one on first use.
+pub fn store(name: &str) -> Arc<MockStore> {
+    STORES
+        .lock()
+        .unwrap()
+        .entry(name.to_owned())
+        .or_insert_with(|| Arc::new(MockStore::default()))
+        .clone()
+}
+
+#[derive(Default)]
+pub struct MockStore {
+    raw: Mutex<HashMap<String, RawData>>,
+    mvcc: Mutex<Mvcc>,
+    last_ts: AtomicU64,
+}
+
+impl MockStore {
+    /// Allocate a timestamp the way PD does: physical milliseconds in the high bits and a
+    /// logical counter in the low bits, strictly increasing.
+    fn next_ts(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let physical = now << PHYSICAL_SHIFT_BITS;
+        let last = self
+            .last_ts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(cmp::max(last + 1, physical))
+            })
+            .unwrap();
+        cmp::max(last + 1, physical)
+    }
+}
+
+#[derive(Default)]
+struct RawData {
+    values: BTreeMap<Key, Value>,
+    /// When each value put with a TTL expires.
+    expiries: BTreeMap<Key, Instant>,
+}
+
+impl RawData {
+    fn put(&mut self, key: Key, value: Value, ttl_secs: u64) {
+        if ttl_secs > 0 {
+            self.expiries
+                .insert(key.clone(), Instant::now() + Duration::from_secs(ttl_secs));
+        } else {
+            self.expiries.remove(&key);
+        }
+        self.values.insert(key, value);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        self.expiries.remove(key);
+        self.values.remove(key);
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<Key> = self
+            .expiries
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Mvcc {
+    // Committed versions of each key by commit timestamp, `None` being a deletion.
+    writes: BTreeMap<Key, BTreeMap<u64, Option<Value>>>,
+    locks: BTreeMap<Key, Lock>,
+}
+
+#[derive(Clone)]
+struct Lock {
+    primary: Key,
+    start_ts: u64,
+    ttl: u64,
+}
+
+impl Lock {
+    fn is_expired(&self, now: u64) -> bool {
+        (self.start_ts >> PHYSICAL_SHIFT_BITS) + self.ttl <= now >> PHYSICAL_SHIFT_BITS
+    }
+
+    fn info(&self, key: &Key) -> LockInfo {
+        LockInfo {
+            key: key.clone().into(),
+            primary: self.primary.clone().into(),
+            start_ts: self.start_ts,
+            ttl: self.ttl,
+        }
+    }
+}
+
+impl Mvcc {
+    fn read(&self, key: &Key, ts: u64) -> Option<Value> {
+        self.writes
+            .get(key)
+            .and_then(|versions| versions.range(..=ts).next_back())
+            .and_then(|(_, value)| value.clone())
+    }
+
+    fn latest_commit_ts(&self, key: &Key) -> Option<u64> {
+        self.writes
+            .get(key)
+            .and_then(|versions| versions.keys().next_back().copied())
+    }
+
+    fn scan(&self, range: &BoundRange, ts: u64) -> BTreeMap<Key, Value> {
+        range_of(&self.writes, range)
+            .filter_map(|(key, _)| self.read(key, ts).map(|value| (key.clone(), value)))
+            .collect()
+    }
+
+    fn conflicting_lock(&self, key: &Key, start_ts: u64, now: u64) -> Option<&Lock> {
+        self.locks
+            .get(key)
+            .filter(|lock| lock.start_ts != start_ts && !lock.is_expired(now))
+    }
+
+    fn release_locks(&mut self, start_ts: u64) {
+        self.locks.retain(|_, lock| lock.start_ts != start_ts);
+    }
+
+    fn gc(&mut self, safepoint: u64) {
+        for versions in self.writes.values_mut() {
+            let latest_visible = versions.range(..=safepoint).next_back().map(|(ts, _)| *ts);
+            if let Some(ts) = latest_visible {
+                *versions = versions.split_off(&ts);
+                if let Some(None) = versions.get(&ts) {
+                    versions.remove(&ts);
+                }
+            }
+        }
+        self.writes.retain(|_, versions| !versions.is_empty());
+        self.locks.retain(|_, lock| lock.start_ts > safepoint);
+    }
+}
+
+// TiKV treats an empty end key as the end of the key space.
+fn range_of<'a, V>(
+    map: &'a BTreeMap<Key, V>,
+    range: &BoundRange,
+) -> Box<dyn Iterator<Item = (&'a Key, &'a V)> + 'a> {
+    let from = range.from.clone();
+    let to = match range.to.clone() {
+        Bound::Included(key) | Bound::Excluded(key) if Vec::from(key.clone()).is_empty() => {
+            Bound::Unbounded
+        }
+        to => to,
+    };
+    let is_empty = match (&from, &to) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    };
+    if is_empty {
+        return Box::new(std::iter::empty());
+    }
+    Box::new(map.range((from, to)))
+}
+
+fn check_scan_limit(limit: u32) -> Result<()> {
+    if limit > MAX_RAW_KV_SCAN_LIMIT {
+        return Err(Error::MaxScanLimitExceeded {
+            limit,
+            max_limit: MAX_RAW_KV_SCAN_LIMIT,
+        });
+    }
+    Ok(())
+}
+
+fn write_conflict(key: &Key, start_ts: u64, conflict_ts: u64, primary: &Key) -> Error {
+    Error::KeyError(kvrpcpb::KeyError {
+        conflict: Some(kvrpcpb::WriteConflict {
+            start_ts,
+            conflict_ts,
+            key: key.clone().into(),
+            primary: primary.clone().into(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+fn already_exist(key: &Key) -> Error {
+    Error::KeyError(kvrpcpb::KeyError {
+        already_exist: Some(kvrpcpb::AlreadyExist {
+            key: key.clone().into(),
+        }),
+        ..Default::default()
+    })
+}
+
+/// The mock store is a single region on a single store.
+struct MockPlacement;
+
+#[async_trait]
+impl PlacementBackend for MockPlacement {
+    async fn locate_key(&self, _key: Key) -> Result<RegionInfo> {
+        Ok(RegionInfo {
+            id: 1,
+            start_key: vec![],
+            end_key: vec![],
+            conf_ver: 1,
+            version: 1,
+            leader_store_id: Some(1),
+            peers: vec![PeerInfo {
+                id: 1,
+                store_id: 1,
+                is_learner: false,
+            }],
+        })
+    }
+
+    async fn stores(&self) -> Result<Vec<StoreInfo>> {
+        Ok(vec![StoreInfo {
+            id: 1,
+            address: MOCK_SCHEME.to_owned(),
+            state: "Up".to_owned(),
+        }])
+    }
+}
+
+#[derive(Clone)]
+pub struct MockRawClient {
+    store: Arc<MockStore>,
+    cf: String,
+}
+
+impl MockRawClient {
+    pub fn new(store: Arc<MockStore>) -> MockRawClient {
+        MockRawClient {
+            store,
+            cf: DEFAULT_CF.to_owned(),
+        }
+    }
+
+    fn with_data<R>(&self, f: impl FnOnce(&mut RawData) -> R) -> R {
+        let mut raw = self.store.raw.lock().unwrap();
+        let data = raw.entry(self.cf.clone()).or_default();
+        data.remove_expired();
+        f(data)
+    }
+}
+
+#[async_trait]
+impl RawBackend for MockRawClient {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(MockRawClient {
+            store: self.store.clone(),
+            cf: format!("{:?}", cf),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        Arc::new(MockPlacement)
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        Ok(self.with_data(|data| data.values.get(&key).cloned()))
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        Ok(self.with_data(|data| {
+            keys.into_iter()
+                .filter_map(|key| {
+                    let value = data.values.get(&key).cloned();
+                    value.map(|value| KvPair::new(key, value))
+                })
+                .collect()
+        }))
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        self.with_data(|data| data.put(key, value, 0));
+        Ok(())
+    }
+
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        self.with_data(|data| data.put(key, value, ttl_secs));
+        Ok(())
+    }
+
+    /// Rounded up, as a value with any time left has not expired.
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        Ok(self.with_data(|data| {
+            data.values.get(&key)?;
+            Some(data.expiries.get(&key).map_or(0, |expiry| {
+                let left = expiry.saturating_duration_since(Instant::now());
+                left.as_secs() + (left.subsec_nanos() > 0) as u64
+            }))
+        }))
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        self.with_data(|data| {
+            for KvPair(key, value) in pairs {
+                data.put(key, value, 0);
+            }
+        });
+        Ok(())
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.with_data(|data| data.remove(&key));
+        Ok(())
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        self.with_data(|data| {
+            for key in keys {
+                data.remove(&key);
+            }
+        });
+        Ok(())
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        self.with_data(|data| {
+            let keys: Vec<Key> = range_of(&data.values, &range)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                data.remove(&key);
+            }
+        });
+        Ok(())
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        check_scan_limit(limit)?;
+        Ok(self.with_data(|data| {
+            range_of(&data.values, &range)
+                .take(limit as usize)
+                .map(|(key, value)| KvPair::new(key.clone(), value.clone()))
+                .collect()
+        }))
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        check_scan_limit(limit)?;
+        Ok(self.with_data(|data| {
+            range_of(&data.values, &range)
+                .take(limit as usize)
+                .map(|(key, _)| key.clone())
+                .collect()
+        }))
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        let mut results = vec![];
+        for range in ranges {
+            results.push(self.scan(range, each_limit).await?);
+        }
+        Ok(results)
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        let mut results = vec![];
+        for range in ranges {
+            results.push(self.scan_keys(range, each_limit).await?);
+        }
+        Ok(results)
+    }
+
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        Ok(self.with_data(|data| {
+            let mut checksum = Checksum::default();
+            for (key, value) in range_of(&data.values, &range) {
+                let key: &[u8] = key.into();
+                let mut digest = crc64fast::Digest::new();
+                digest.write(key);
+                digest.write(value);
+                checksum.crc64_xor ^= digest.sum64();
+                checksum.total_kvs += 1;
+                checksum.total_bytes += (key.len() + value.len()) as u64;
+            }
+            checksum
+        }))
+    }
+
+    /// Coprocessor plugins run inside TiKV, so there is nothing to send requests to.
+    async fn coprocessor(
+        &self,
+        _name: String,
+        _version: String,
+        _ranges: Vec<BoundRange>,
+        _request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        Err(Error::Unimplemented)
+    }
+}
+
+pub struct MockTransactionClient {
+    store: Arc<MockStore>,
+}
+
+impl MockTransactionClient {
+    pub fn new(store: Arc<MockStore>) -> MockTransactionClient {
+        MockTransactionClient { store }
+    }
+}
+
+#[async_trait]
+impl TransactionBackend for MockTransactionClient {
+    async fn begin(&self, options: TxnOptions) -> Result<Box<dyn TransactionHandle>> {
+        Ok(Box::new(MockTransaction {
+            store: self.store.clone(),
+            start_ts: self.store.next_ts(),
+            options,
+            buffer: BTreeMap::new(),
+            primary: None,
+            finished: false,
+        }))
+    }
+
+    fn snapshot(&self, timestamp: Timestamp, _options: TxnOptions) -> Box<dyn SnapshotHandle> {
+        Box::new(MockSnapshot {
+            store: self.store.clone(),
+            timestamp: timestamp.version(),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        Arc::new(MockPlacement)
+    }
+
+    async fn current_timestamp(&self) -> Result<Timestamp> {
+        Ok(Timestamp::from_version(self.store.next_ts()))
+    }
+
+    async fn gc(&self, safepoint: Timestamp) -> Result<bool> {
+        self.store.mvcc.lock().unwrap().gc(safepoint.version());
+        Ok(true)
+    }
+
+    async fn scan_locks(
+        &self,
+        range: BoundRange,
+        max_ts: Timestamp,
+        limit: u32,
+    ) -> Result<Vec<LockInfo>> {
+        let mvcc = self.store.mvcc.lock().unwrap();
+        Ok(range_of(&mvcc.locks, &range)
+            .filter(|(_, lock)| lock.start_ts <= max_ts.version())
+            .take(limit as usize)
+            .map(|(key, lock)| lock.info(key))
+            .collect())
+    }
+
+    /// Mock transactions only take pessimistic locks, so expired ones are rolled back.
+    async fn cleanup_locks(
+        &self,
+        range: BoundRange,
+        safepoint: Timestamp,
+        dry_run: bool,
+    ) -> Result<Vec<LockInfo>> {
+        let now = self.store.next_ts();
+        let mut mvcc = self.store.mvcc.lock().unwrap();
+        let expired: Vec<Key> = range_of(&mvcc.locks, &range)
+            .filter(|(_, lock)| lock.start_ts <= safepoint.version() && lock.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        Ok(expired
+            .into_iter()
+            .filter_map(|key| {
+                let lock = if dry_run {
+                    mvcc.locks.get(&key).cloned()
+                } else {
+                    mvcc.locks.remove(&key)
+                };
+                lock.map(|lock| lock.info(&key))
+            })
+            .collect())
+    }
+}
+
+enum Mutation {
+    Put(Value),
+    Insert(Value),
+    Delete,
+    Lock,
+}
+
+pub struct MockTransaction {
+    store: Arc<MockStore>,
+    start_ts: u64,
+    options: TxnOptions,
+    buffer: BTreeMap<Key, Mutation>,
+    primary: Option<Key>,
+    finished: bool,
+}
+
+impl MockTransaction {
+    fn check_allow_operation(&self) -> Result<()> {
+        if self.finished {
+            return Err(Error::OperationAfterCommitError);
+        }
+        Ok(())
+    }
+
+    fn acquire_pessimistic_locks(&mut self, keys: &[Key]) -> Result<()> {
+        let mut mvcc = self.store.mvcc.lock().unwrap();
+        let now = self.store.next_ts();
+        for key in keys {
+            if let Some(lock) = mvcc.conflicting_lock(key, self.start_ts, now) {
+                return Err(write_conflict(
+                    key,
+                    self.start_ts,
+                    lock.start_ts,
+                    &lock.primary,
+                ));
+            }
+        }
+        for key in keys {
+            let primary = self.primary.get_or_insert_with(|| key.clone()).clone();
+            mvcc.locks.insert(
+                key.clone(),
+                Lock {
+                    primary,
+                    start_ts: self.start_ts,
+                    ttl: LOCK_TTL_MS,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn lock_for_write(&mut self, key: &Key) -> Result<()> {
+        self.check_allow_operation()?;
+        if self.options.pessimistic {
+            self.acquire_pessimistic_locks(std::slice::from_ref(key))?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, key: &Key, ts: u64) -> Option<Value> {
+        match self.buffer.get(key) {
+            Some(Mutation::Put(value)) | Some(Mutation::Insert(value)) => Some(value.clone()),
+            Some(Mutation::Delete) => None,
+            Some(Mutation::Lock) | None => self.store.mvcc.lock().unwrap().read(key, ts),
+        }
+    }
+
+    // Pessimistic transactions read the latest committed data once the keys are locked.
+    fn for_update_ts(&self) -> u64 {
+        if self.options.pessimistic {
+            self.store.next_ts()
+        } else {
+            self.start_ts
+        }
+    }
+
+    fn check_commit(&self, mvcc: &Mvcc, now: u64) -> Result<()> {
+        let primary = self
+            .primary
+            .clone()
+            .unwrap_or_else(|| Key::from(Vec::new()));
+        for (key, mutation) in &self.buffer {
+            if let Some(lock) = mvcc.conflicting_lock(key, self.start_ts, now) {
+                return Err(write_conflict(
+                    key,
+                    self.start_ts,
+                    lock.start_ts,
+                    &lock.primary,
+                ));
+            }
+            if self.options.pessimistic {
+                let locked = mvcc.locks.get(key).map(|lock| lock.start_ts) == Some(self.start_ts);
+                if !locked && !matches!(mutation, Mutation::Lock) {
+                    return Err(write_conflict(key, self.start_ts, now, &primary));
+                }
+            } else if let Some(commit_ts) = mvcc.latest_commit_ts(key) {
+                if commit_ts > self.start_ts {
+                    return Err(write_conflict(key, self.start_ts, commit_ts, &primary));
+                }
+            }
+            if let Mutation::Insert(_) = mutation {
+                if mvcc.read(key, u64::MAX).is_some() {
+                    return Err(already_exist(key));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MockTransaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Ok(mut mvcc) = self.store.mvcc.lock() {
+                mvcc.release_locks(self.start_ts);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionHandle for MockTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        self.check_allow_operation()?;
+        Ok(self.read(&key, self.start_ts))
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        self.lock_keys(vec![key.clone()]).await?;
+        Ok(self.read(&key, self.for_update_ts()))
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.get(key).await.map(|value| value.is_some())
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.check_allow_operation()?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| {
+                self.read(&key, self.start_ts)
+                    .map(|value| KvPair::new(key, value))
+            })
+            .collect())
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.lock_keys(keys.clone()).await?;
+        let ts = self.for_update_ts();
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| self.read(&key, ts).map(|value| KvPair::new(key, value)))
+            .collect())
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.check_allow_operation()?;
+        let mut pairs = self.store.mvcc.lock().unwrap().scan(&range, self.start_ts);
+        for (key, mutation) in range_of(&self.buffer, &range) {
+            match mutation {
+                Mutation::Put(value) | Mutation::Insert(value) => {
+                    pairs.insert(key.clone(), value.clone());
+                }
+                Mutation::Delete => {
+                    pairs.remove(key);
+                }
+                Mutation::Lock => {}
+            }
+        }
+        Ok(pairs
+            .into_iter()
+            .take(limit as usize)
+            .map(|(key, value)| KvPair::new(key, value))
+            .collect())
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.scan(range, limit)
+            .await
+            .map(|pairs| pairs.into_iter().map(|KvPair(key, _)| key).collect())
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        self.check_allow_operation()?;
+        if self.options.pessimistic {
+            return self.acquire_pessimistic_locks(&keys);
+        }
+        for key in keys {
+            self.buffer.entry(key).or_insert(Mutation::Lock);
+        }
+        Ok(())
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        self.lock_for_write(&key)?;
+        self.buffer.insert(key, Mutation::Put(value));
+        Ok(())
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        self.lock_for_write(&key)?;
+        let mutation = match self.buffer.get(&key) {
+            Some(Mutation::Put(_)) | Some(Mutation::Insert(_)) => {
+                return Err(Error::DuplicateKeyInsertion)
+            }
+            Some(Mutation::Delete) => Mutation::Put(value),
+            Some(Mutation::Lock) | None => Mutation::Insert(value),
+        };
+        self.buffer.insert(key, mutation);
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        self.lock_for_write(&key)?;
+        self.buffer.insert(key, Mutation::Delete);
+        Ok(())
+    }
+
+    fn start_timestamp(&self) -> Result<Timestamp> {
+        Ok(Timestamp::from_version(self.start_ts))
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.check_allow_operation()?;
+        self.finished = true;
+
+        let mut mvcc = self.store.mvcc.lock().unwrap();
+        let now = self.store.next_ts();
+        let result = self.check_commit(&mvcc, now);
+        if let Err(e) = result {
+            mvcc.release_locks(self.start_ts);
+            return Err(e);
+        }
+        if self.buffer.is_empty() {
+            mvcc.release_locks(self.start_ts);
+            return Ok(None);
+        }
+
+        let commit_ts = self.store.next_ts();
+        for (key, mutation) in std::mem::take(&mut self.buffer) {
+            let value = match mutation {
+                Mutation::Put(value) | Mutation::Insert(value) => Some(value),
+                Mutation::Delete => None,
+                Mutation::Lock => continue,
+            };
+            mvcc.writes.entry(key).or_default().insert(commit_ts, value);
+        }
+        mvcc.release_locks(self.start_ts);
+        Ok(Some(Timestamp::from_version(commit_ts)))
+    }
+}
+
+pub struct MockSnapshot {
+    store: Arc<MockStore>,
+    timestamp: u64,
+}
+
+#[async_trait]
+impl SnapshotHandle for MockSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        Ok(self.store.mvcc.lock().unwrap().read(&key, self.timestamp))
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.get(key).await.map(|value| value.is_some())
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let mvcc = self.store.mvcc.lock().unwrap();
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| {
+                mvcc.read(&key, self.timestamp)
+                    .map(|value| KvPair::new(key, value))
+            })
+            .collect())
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        Ok(self
+            .store
+            .mvcc
+            .lock()
+            .unwrap()
+            .scan(&range, self.timestamp)
+            .into_iter()
+            .take(limit as usize)
+            .map(|(key, value)| KvPair::new(key, value))
+            .collect())
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.scan(range, limit)
+            .await
+            .map(|pairs| pairs.into_iter().map(|KvPair(key, _)| key).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::RUNTIME;
+
+    fn key(key: &str) -> Key {
+        Key::from(key.to_owned())
+    }
+
+    fn options(pessimistic: bool) -> TxnOptions {
+        TxnOptions {
+            pessimistic,
+            read_only: false,
+            replica_read: false,
+        }
+    }
+
+    fn is_write_conflict(err: &Error) -> bool {
+        matches!(err, Error::KeyError(err) if err.conflict.is_some())
+    }
+
+    #[test]
+    fn column_families_are_apart() {
+        RUNTIME.block_on(async {
+            let raw = MockRawClient::new(Arc::new(MockStore::default()));
+            let write = raw.with_cf(ColumnFamily::Write);
+            raw.put(key("k"), b"default".to_vec()).await.unwrap();
+            write.put(key("k"), b"write".to_vec()).await.unwrap();
+            assert_eq!(raw.get(key("k")).await.unwrap(), Some(b"default".to_vec()));
+            assert_eq!(write.get(key("k")).await.unwrap(), Some(b"write".to_vec()));
+
+            raw.delete_range((key("")..).into()).await.unwrap();
+            assert_eq!(raw.get(key("k")).await.unwrap(), None);
+            assert_eq!(write.get(key("k")).await.unwrap(), Some(b"write".to_vec()));
+        });
+    }
+
+    #[test]
+    fn values_expire_after_their_ttl() {
+        RUNTIME.block_on(async {
+            let store = Arc::new(MockStore::default());
+            let raw = MockRawClient::new(store.clone());
+            raw.put_with_ttl(key("a"), vec![1], 60).await.unwrap();
+            raw.put_with_ttl(key("b"), vec![2], 60).await.unwrap();
+            raw.put(key("c"), vec![3]).await.unwrap();
+            assert_eq!(raw.get_key_ttl(key("a")).await.unwrap(), Some(60));
+            assert_eq!(raw.get_key_ttl(key("c")).await.unwrap(), Some(0));
+            assert_eq!(raw.get_key_ttl(key("d")).await.unwrap(), None);
+
+            // Putting a value again without a TTL keeps it.
+            raw.put(key("b"), vec![2]).await.unwrap();
+            assert_eq!(raw.get_key_ttl(key("b")).await.unwrap(), Some(0));
+
+            let past = Instant::now() - Duration::from_millis(1);
+            store
+                .raw
+                .lock()
+                .unwrap()
+                .get_mut(DEFAULT_CF)
+                .unwrap()
+                .expiries
+                .insert(key("a"), past);
+            assert_eq!(raw.get(key("a")).await.unwrap(), None);
+            let keys = raw.scan_keys((key("")..).into(), 10).await.unwrap();
+            assert_eq!(keys, vec![key("b"), key("c")]);
+        });
+    }
+
+    #[test]
+    fn snapshots_see_what_was_committed_before_them() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let mut txn = client.begin(options(false)).await.unwrap();
+            txn.put(key("k"), b"1".to_vec()).await.unwrap();
+            let first = txn.commit().await.unwrap().unwrap();
+            let between = client.current_timestamp().await.unwrap();
+
+            let mut txn = client.begin(options(false)).await.unwrap();
+            txn.delete(key("k")).await.unwrap();
+            txn.commit().await.unwrap();
+
+            let mut before =
+                client.snapshot(Timestamp::from_version(first.version() - 1), options(false));
+            assert_eq!(before.get(key("k")).await.unwrap(), None);
+            let mut after = client.snapshot(between, options(false));
+            assert_eq!(after.get(key("k")).await.unwrap(), Some(b"1".to_vec()));
+            let now = client.current_timestamp().await.unwrap();
+            assert_eq!(
+                client
+                    .snapshot(now, options(false))
+                    .get(key("k"))
+                    .await
+                    .unwrap(),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn optimistic_transactions_conflict_at_commit() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let mut txn1 = client.begin(options(false)).await.unwrap();
+            let mut txn2 = client.begin(options(false)).await.unwrap();
+            txn1.put(key("k"), b"1".to_vec()).await.unwrap();
+            txn2.put(key("k"), b"2".to_vec()).await.unwrap();
+            txn1.commit().await.unwrap();
+            assert!(is_write_conflict(&txn2.commit().await.unwrap_err()));
+            assert!(matches!(
+                txn2.get(key("k")).await,
+                Err(Error::OperationAfterCommitError)
+            ));
+        });
+    }
+
+    #[test]
+    fn inserts_fail_on_existing_keys() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let mut txn = client.begin(options(false)).await.unwrap();
+            txn.insert(key("k"), b"1".to_vec()).await.unwrap();
+            assert!(matches!(
+                txn.insert(key("k"), b"2".to_vec()).await,
+                Err(Error::DuplicateKeyInsertion)
+            ));
+            txn.commit().await.unwrap();
+
+            let mut txn = client.begin(options(false)).await.unwrap();
+            txn.insert(key("k"), b"3".to_vec()).await.unwrap();
+            let err = txn.commit().await.unwrap_err();
+            assert!(matches!(err, Error::KeyError(err) if err.already_exist.is_some()));
+        });
+    }
+
+    #[test]
+    fn pessimistic_locks_conflict_until_released() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let mut txn1 = client.begin(options(true)).await.unwrap();
+            let mut txn2 = client.begin(options(true)).await.unwrap();
+            txn1.get_for_update(key("k")).await.unwrap();
+            assert!(is_write_conflict(
+                &txn2.put(key("k"), vec![]).await.unwrap_err()
+            ));
+
+            let now = client.current_timestamp().await.unwrap();
+            let locks = client
+                .scan_locks((key("")..).into(), now, 10)
+                .await
+                .unwrap();
+            assert_eq!(locks.len(), 1);
+            assert_eq!(locks[0].key, b"k".to_vec());
+
+            drop(txn1);
+            txn2.put(key("k"), vec![]).await.unwrap();
+            txn2.commit().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn expired_locks_are_cleaned_up() {
+        RUNTIME.block_on(async {
+            let store = Arc::new(MockStore::default());
+            let client = MockTransactionClient::new(store.clone());
+            let start_ts = 1 << PHYSICAL_SHIFT_BITS;
+            store.mvcc.lock().unwrap().locks.insert(
+                key("stale"),
+                Lock {
+                    primary: key("stale"),
+                    start_ts,
+                    ttl: 1,
+                },
+            );
+            let mut live = client.begin(options(true)).await.unwrap();
+            live.get_for_update(key("live")).await.unwrap();
+
+            // Expired locks no longer conflict.
+            let mut txn = client.begin(options(true)).await.unwrap();
+            txn.get_for_update(key("stale")).await.unwrap();
+            drop(txn);
+            store.mvcc.lock().unwrap().locks.insert(
+                key("stale"),
+                Lock {
+                    primary: key("stale"),
+                    start_ts,
+                    ttl: 1,
+                },
+            );
+
+            let now = client.current_timestamp().await.unwrap();
+            let dry_run = client
+                .cleanup_locks((key("")..).into(), now.clone(), true)
+                .await
+                .unwrap();
+            assert_eq!(dry_run.len(), 1);
+            let cleaned = client
+                .cleanup_locks((key("")..).into(), now.clone(), false)
+                .await
+                .unwrap();
+            assert_eq!(cleaned[0].key, b"stale".to_vec());
+            let locks = client
+                .scan_locks((key("")..).into(), now, 10)
+                .await
+                .unwrap();
+            assert_eq!(locks.len(), 1);
+            assert_eq!(locks[0].key, b"live".to_vec());
+        });
+    }
+
+    #[test]
+    fn gc_keeps_the_versions_visible_at_the_safepoint() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let mut timestamps = vec![];
+            for value in &[b"1", b"2"] {
+                let mut txn = client.begin(options(false)).await.unwrap();
+                txn.put(key("k"), value.to_vec()).await.unwrap();
+                txn.commit().await.unwrap();
+                timestamps.push(client.current_timestamp().await.unwrap());
+            }
+            let mut txn = client.begin(options(false)).await.unwrap();
+            txn.put(key("k"), b"3".to_vec()).await.unwrap();
+            txn.commit().await.unwrap();
+
+            assert!(client.gc(timestamps[1].clone()).await.unwrap());
+            let mut collected = client.snapshot(timestamps[0].clone(), options(false));
+            assert_eq!(collected.get(key("k")).await.unwrap(), None);
+            let mut kept = client.snapshot(timestamps[1].clone(), options(false));
+            assert_eq!(kept.get(key("k")).await.unwrap(), Some(b"2".to_vec()));
+            let now = client.current_timestamp().await.unwrap();
+            let mut latest = client.snapshot(now, options(false));
+            assert_eq!(latest.get(key("k")).await.unwrap(), Some(b"3".to_vec()));
+        });
+    }
+}
@@ -1,40 +1,184 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
 use crate::{
-    utils::{js_array_to_rust_keys, js_array_to_rust_pairs, send_result, to_bound_range, RUNTIME},
+    backend::connect_raw,
+    cache::{Cache, CachedRaw},
+    coalesce::{CoalescedRaw, Coalescer, Coalescing},
+    codec::{Codec, KeyType},
+    compression::{CompressedRaw, Compression, Dictionary},
+    encryption::{EncryptedRaw, Encryption},
+    subspace::{Subspace, SubspaceRaw},
+    utils::{
+        argument_to_bytes, js_array_to_rust_keys, js_array_to_rust_pairs, js_array_to_rust_ranges,
+        spawn_request, to_bound_range, Request,
+    },
     RawClient,
 };
 use neon::prelude::*;
-use std::{convert::TryInto, u32};
+use std::{convert::TryInto, sync::Arc, u32};
 
 impl RawClient {
     pub fn connect(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let pd_endpoint = cx.argument::<JsString>(0)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
-        let result = tikv_client::RawClient::new(vec![pd_endpoint], None);
+        let result = connect_raw(pd_endpoint);
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = result.await;
-            send_result(queue, callback, result);
+        let request = Request::new("raw_connect");
+        spawn_request(request, queue, callback, async move {
+            result.await.map(|inner| RawClient {
+                inner,
+                codec: Codec::default(),
+            })
         });
         Ok(cx.undefined())
     }
 
+    pub fn subspace(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let prefix = argument_to_bytes(&mut cx, 0)?;
+        let inner = Arc::new(SubspaceRaw {
+            inner: client.inner.clone(),
+            subspace: Subspace::new(prefix),
+        });
+        Ok(cx.boxed(RawClient {
+            inner,
+            codec: client.codec.clone(),
+        }))
+    }
+
+    pub fn with_codec(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let codec = cx.argument::<JsValue>(0)?;
+        let codec = client.codec.with_values(&mut cx, codec)?;
+        Ok(cx.boxed(RawClient {
+            inner: client.inner.clone(),
+            codec,
+        }))
+    }
+
+    pub fn with_key_type(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let key_type = cx.argument::<JsString>(0)?;
+        let key_type = KeyType::from_js(&mut cx, key_type)?;
+        Ok(cx.boxed(RawClient {
+            inner: client.inner.clone(),
+            codec: client.codec.with_keys(key_type),
+        }))
+    }
+
+    pub fn with_compression(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let compression = Compression::from_js(&mut cx, options)?;
+        let inner = Arc::new(CompressedRaw {
+            inner: client.inner.clone(),
+            compression: Arc::new(compression),
+        });
+        Ok(cx.boxed(RawClient {
+            inner,
+            codec: client.codec.clone(),
+        }))
+    }
+
+    pub fn with_encryption(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let encryption = Encryption::from_js(&mut cx, options)?;
+        let inner = Arc::new(EncryptedRaw {
+            inner: client.inner.clone(),
+            encryption: Arc::new(encryption),
+        });
+        Ok(cx.boxed(RawClient {
+            inner,
+            codec: client.codec.clone(),
+        }))
+    }
+
+    /// Coalesce gets issued close together into batch gets.
+    pub fn with_coalescing(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let coalescing = Coalescing::from_js(&mut cx, options)?;
+        let inner = Arc::new(CoalescedRaw {
+            inner: client.inner.clone(),
+            coalescer: Arc::new(Coalescer::new(coalescing)),
+            cf: String::new(),
+        });
+        Ok(cx.boxed(RawClient {
+            inner,
+            codec: client.codec.clone(),
+        }))
+    }
+
+    /// Cache the values it reads, until they expire or are written through it.
+    pub fn with_cache(mut cx: FunctionContext) -> JsResult<JsBox<RawClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let cache = Cache::from_js(&mut cx, options)?;
+        let inner = Arc::new(CachedRaw {
+            inner: client.inner.clone(),
+            cache: Arc::new(cache),
+            cf: String::new(),
+        });
+        Ok(cx.boxed(RawClient {
+            inner,
+            codec: client.codec.clone(),
+        }))
+    }
+
+    /// Train a zstd dictionary from the values of sample keys.
+    pub fn train_dictionary(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let keys = cx.argument::<JsArray>(0)?;
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
+        let max_size = cx.argument::<JsNumber>(1)?.value(&mut cx) as usize;
+        let cf = cx.argument::<JsString>(2)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let queue = cx.queue();
+
+        let request = Request::with_cf("raw_train_dictionary", cf).keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            let samples = inner.batch_get(keys).await?;
+            Dictionary::train(&samples, max_size)
+        });
+
+        Ok(cx.undefined())
+    }
+
     pub fn put(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
-        let value = cx.argument::<JsString>(1)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let value = cx.argument::<JsValue>(1)?;
+        let value = client.codec.encode(&mut cx, value)?;
         let cf = cx.argument::<JsString>(2)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
 
-        let inner = client.inner.with_cf(cf.try_into().unwrap()); // TODO: #22 make CF optional
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap()); // TODO: #22 make CF optional
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.put(key, value).await;
-            send_result(queue, callback, result);
+        let request = Request::with_cf("raw_put", cf).key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.put(key.into(), value.into()).await
         });
 
         Ok(cx.undefined())
@@ -44,16 +188,66 @@ impl RawClient {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let cf = cx.argument::<JsString>(1)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let codec = client.codec.clone();
+        let queue = cx.queue();
+
+        let request = Request::with_cf("raw_get", cf).key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner
+                .get(key.into())
+                .await
+                .map(|value| codec.decoded(value))
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn put_with_ttl(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let value = cx.argument::<JsValue>(1)?;
+        let value = client.codec.encode(&mut cx, value)?;
+        let ttl_secs = cx.argument::<JsNumber>(2)?.value(&mut cx);
+        if !(ttl_secs.is_finite() && ttl_secs >= 0.0) {
+            return cx.throw_range_error("TTL must be a finite number of seconds, at least 0");
+        }
+        let cf = cx.argument::<JsString>(3)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(4)?.root(&mut cx);
+
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let queue = cx.queue();
+
+        let request = Request::with_cf("raw_put_with_ttl", cf).key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner
+                .put_with_ttl(key.into(), value.into(), ttl_secs.ceil() as u64)
+                .await
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn get_key_ttl(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
         let cf = cx.argument::<JsString>(1)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
 
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let value: Option<Vec<u8>> = inner.get(key).await.unwrap();
-            send_result(queue, callback, Ok(value));
+        let request = Request::with_cf("raw_get_key_ttl", cf).key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.get_key_ttl(key.into()).await
         });
 
         Ok(cx.undefined())
@@ -63,15 +257,15 @@ impl RawClient {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
         let cf = cx.argument::<JsString>(1)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
 
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = inner.delete(key).await;
-            send_result(queue, callback, result);
+        let request = Request::with_cf("raw_delete", cf).key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.delete(key.into()).await
         });
 
         Ok(cx.undefined())
@@ -82,16 +276,20 @@ impl RawClient {
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
         let keys = cx.argument::<JsArray>(0)?;
-        let keys = js_array_to_rust_keys(&mut cx, keys);
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
         let cf = cx.argument::<JsString>(1)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
 
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.batch_get(keys).await;
-            send_result(queue, callback, result);
+        let request = Request::with_cf("raw_batch_get", cf).keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            inner
+                .batch_get(keys)
+                .await
+                .map(|pairs| codec.decoded(pairs))
         });
 
         Ok(cx.undefined())
@@ -101,21 +299,25 @@ impl RawClient {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
-        let start = cx.argument::<JsString>(0)?.value(&mut cx).into_bytes();
-        let end = cx.argument::<JsString>(1)?.value(&mut cx).into_bytes();
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
         let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
         let include_start = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(4)?.value(&mut cx);
         let cf = cx.argument::<JsString>(5)?.value(&mut cx);
 
         let callback = cx.argument::<JsFunction>(6)?.root(&mut cx);
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let codec = client.codec.clone();
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
+        let request = Request::with_cf("raw_scan", cf).range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(Some(start), Some(end), include_start, include_end);
 
-            let result = inner.scan(range, limit).await;
-            send_result(queue, callback, result);
+            inner
+                .scan(range, limit)
+                .await
+                .map(|pairs| codec.decoded(pairs))
         });
 
         Ok(cx.undefined())
@@ -125,21 +327,98 @@ impl RawClient {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
-        let start = cx.argument::<JsString>(0)?.value(&mut cx).into_bytes();
-        let end = cx.argument::<JsString>(1)?.value(&mut cx).into_bytes();
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
         let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
         let include_start = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(4)?.value(&mut cx);
         let cf = cx.argument::<JsString>(5)?.value(&mut cx);
 
         let callback = cx.argument::<JsFunction>(6)?.root(&mut cx);
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let codec = client.codec.clone();
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
+        let request = Request::with_cf("raw_scan_keys", cf).range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(Some(start), Some(end), include_start, include_end);
 
-            let result = inner.scan_keys(range, limit).await;
-            send_result(queue, callback, result);
+            inner
+                .scan_keys(range, limit)
+                .await
+                .map(|keys| codec.decoded(keys))
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn batch_scan(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let ranges = cx.argument::<JsArray>(0)?;
+        let ranges = js_array_to_rust_ranges(&mut cx, ranges)?;
+        let each_limit = cx.argument::<JsNumber>(1)?.value(&mut cx) as u32;
+        let cf = cx.argument::<JsString>(2)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let codec = client.codec.clone();
+        let queue = cx.queue();
+        let request = Request::with_cf("raw_batch_scan", cf).ranges(ranges.len());
+        spawn_request(request, queue, callback, async move {
+            inner
+                .batch_scan(ranges, each_limit)
+                .await
+                .map(|results| codec.decoded(results))
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn batch_scan_keys(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let ranges = cx.argument::<JsArray>(0)?;
+        let ranges = js_array_to_rust_ranges(&mut cx, ranges)?;
+        let each_limit = cx.argument::<JsNumber>(1)?.value(&mut cx) as u32;
+        let cf = cx.argument::<JsString>(2)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let codec = client.codec.clone();
+        let queue = cx.queue();
+        let request = Request::with_cf("raw_batch_scan_keys", cf).ranges(ranges.len());
+        spawn_request(request, queue, callback, async move {
+            inner
+                .batch_scan_keys(ranges, each_limit)
+                .await
+                .map(|keys| codec.decoded(keys))
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn coprocessor(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let name = cx.argument::<JsString>(0)?.value(&mut cx);
+        let version = cx.argument::<JsString>(1)?.value(&mut cx);
+        let ranges = cx.argument::<JsArray>(2)?;
+        let ranges = js_array_to_rust_ranges(&mut cx, ranges)?;
+        let data = argument_to_bytes(&mut cx, 3)?;
+        let callback = cx.argument::<JsFunction>(4)?.root(&mut cx);
+
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+        let queue = cx.queue();
+        let request = Request::new("raw_coprocessor").ranges(ranges.len());
+        spawn_request(request, queue, callback, async move {
+            inner
+                .coprocessor(name, version, ranges, data)
+                .await
+                .map(|responses| codec.decoded(responses))
         });
 
         Ok(cx.undefined())
@@ -150,15 +429,15 @@ impl RawClient {
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
         let pairs = cx.argument::<JsArray>(0)?;
-        let pairs = js_array_to_rust_pairs(&mut cx, pairs);
+        let pairs = js_array_to_rust_pairs(&mut cx, pairs, &client.codec)?;
         let cf = cx.argument::<JsString>(1)?.value(&mut cx);
 
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = inner.batch_put(pairs).await;
-            send_result(queue, callback, result);
+        let request = Request::with_cf("raw_batch_put", cf).keys(pairs.len());
+        spawn_request(request, queue, callback, async move {
+            inner.batch_put(pairs).await
         });
 
         Ok(cx.undefined())
@@ -169,15 +448,15 @@ impl RawClient {
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
         let keys = cx.argument::<JsArray>(0)?;
-        let keys = js_array_to_rust_keys(&mut cx, keys);
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
         let cf = cx.argument::<JsString>(1)?.value(&mut cx);
 
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = inner.batch_delete(keys).await;
-            send_result(queue, callback, result);
+        let request = Request::with_cf("raw_batch_delete", cf).keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            inner.batch_delete(keys).await
         });
 
         Ok(cx.undefined())
@@ -188,22 +467,106 @@ impl RawClient {
             .this()
             .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
         let queue = cx.queue();
-        let start = cx.argument::<JsString>(0)?.value(&mut cx).into_bytes();
-        let end = cx.argument::<JsString>(1)?.value(&mut cx).into_bytes();
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
         let include_start = cx.argument::<JsBoolean>(2)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let cf = cx.argument::<JsString>(4)?.value(&mut cx);
-        let inner = client.inner.with_cf(cf.try_into().unwrap());
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
 
         let callback = cx.argument::<JsFunction>(5)?.root(&mut cx);
 
-        RUNTIME.spawn(async move {
+        let request = Request::with_cf("raw_delete_range", cf).range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(Some(start), Some(end), include_start, include_end);
 
-            let result = inner.delete_range(range).await;
-            send_result(queue, callback, result);
+            inner.delete_range(range).await
         });
 
         Ok(cx.undefined())
     }
+
+    pub fn checksum(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
+        let include_start = cx.argument::<JsBoolean>(2)?.value(&mut cx);
+        let include_end = cx.argument::<JsBoolean>(3)?.value(&mut cx);
+        let cf = cx.argument::<JsString>(4)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(5)?.root(&mut cx);
+
+        let inner = client.inner.with_cf(cf.clone().try_into().unwrap());
+        let queue = cx.queue();
+        let request = Request::with_cf("raw_checksum", cf).range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
+            let range = to_bound_range(Some(start), Some(end), include_start, include_end);
+
+            inner.checksum(range).await
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn locate_key(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
+        let placement = client.inner.placement();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("raw_locate_key").key(&key);
+        spawn_request(request, queue, callback, async move {
+            placement
+                .locate_key(key.into())
+                .await
+                .map(|region| codec.decoded(region))
+        });
+        Ok(cx.undefined())
+    }
+
+    pub fn regions(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
+        let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+        let placement = client.inner.placement();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("raw_regions").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
+            let range = to_bound_range(Some(start), Some(end), true, false);
+
+            placement
+                .regions(range)
+                .await
+                .map(|regions| codec.decoded(regions))
+        });
+        Ok(cx.undefined())
+    }
+
+    pub fn stores(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<RawClient>, _>(&mut cx)?;
+        let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+        let placement = client.inner.placement();
+
+        let queue = cx.queue();
+        let request = Request::new("raw_stores");
+        spawn_request(
+            request,
+            queue,
+            callback,
+            async move { placement.stores().await },
+        );
+        Ok(cx.undefined())
+    }
 }
@@ -0,0 +1,152 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Transactions which only read.
+//!
+//! Writes, including the locks taken by `get_for_update`, fail before reaching TiKV. With
+//! nothing to buffer, they aren't wrapped in a `buffer::WriteBuffer`: savepoints only have to be
+//! named, and committing only ends the transaction.
+
+use std::io;
+
+use async_trait::async_trait;
+use tikv_client::{BoundRange, Error, Key, KvPair, Result, Timestamp, Value};
+
+use crate::backend::{PendingMutation, TransactionHandle};
+
+pub struct ReadOnlyTransaction {
+    inner: Box<dyn TransactionHandle>,
+    savepoints: Vec<String>,
+}
+
+impl ReadOnlyTransaction {
+    pub fn new(inner: Box<dyn TransactionHandle>) -> ReadOnlyTransaction {
+        ReadOnlyTransaction {
+            inner,
+            savepoints: vec![],
+        }
+    }
+}
+
+fn read_only_error(operation: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Cannot {} in a read-only transaction", operation),
+    ))
+}
+
+#[async_trait]
+impl TransactionHandle for ReadOnlyTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        self.inner.get(key).await
+    }
+
+    async fn get_for_update(&mut self, _key: Key) -> Result<Option<Value>> {
+        Err(read_only_error("get for update"))
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        self.inner.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        self.inner.batch_get(keys).await
+    }
+
+    async fn batch_get_for_update(&mut self, _keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        Err(read_only_error("get for update"))
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        self.inner.scan(range, limit).await
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        self.inner.scan_keys(range, limit).await
+    }
+
+    async fn lock_keys(&mut self, _keys: Vec<Key>) -> Result<()> {
+        Err(read_only_error("lock keys"))
+    }
+
+    async fn put(&mut self, _key: Key, _value: Value) -> Result<()> {
+        Err(read_only_error("put"))
+    }
+
+    async fn insert(&mut self, _key: Key, _value: Value) -> Result<()> {
+        Err(read_only_error("insert"))
+    }
+
+    async fn delete(&mut self, _key: Key) -> Result<()> {
+        Err(read_only_error("delete"))
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.inner.commit().await
+    }
+
+    async fn savepoint(&mut self, name: String) -> Result<()> {
+        self.savepoints.retain(|existing| *existing != name);
+        self.savepoints.push(name);
+        Ok(())
+    }
+
+    async fn rollback_to(&mut self, name: String) -> Result<()> {
+        match self
+            .savepoints
+            .iter()
+            .position(|existing| *existing == name)
+        {
+            Some(index) => {
+                self.savepoints.truncate(index + 1);
+                Ok(())
+            }
+            None => Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No savepoint named {}", name),
+            ))),
+        }
+    }
+
+    async fn pending_mutations(&mut self) -> Result<Vec<PendingMutation>> {
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        backend::{TransactionBackend, TxnOptions},
+        mock::{MockStore, MockTransactionClient},
+        utils::RUNTIME,
+    };
+
+    fn key(key: &str) -> Key {
+        Key::from(key.to_owned())
+    }
+
+    #[test]
+    fn writes_fail_and_savepoints_only_need_names() {
+        RUNTIME.block_on(async {
+            let client = MockTransactionClient::new(Arc::new(MockStore::default()));
+            let options = TxnOptions {
+                pessimistic: false,
+                read_only: true,
+                replica_read: false,
+            };
+            let mut txn = ReadOnlyTransaction::new(client.begin(options).await.unwrap());
+            assert!(txn.put(key("a"), b"1".to_vec()).await.is_err());
+            assert!(txn.get_for_update(key("a")).await.is_err());
+            assert_eq!(txn.get(key("a")).await.unwrap(), None);
+
+            txn.savepoint("s".to_owned()).await.unwrap();
+            txn.savepoint("t".to_owned()).await.unwrap();
+            txn.rollback_to("s".to_owned()).await.unwrap();
+            assert!(txn.rollback_to("t".to_owned()).await.is_err());
+            assert!(txn.pending_mutations().await.unwrap().is_empty());
+            txn.commit().await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Where keys live: the regions covering keys and ranges, and the stores holding them.
+//!
+//! The Rust client keeps its PD client to itself, so clients connect a PD client of their own
+//! to the same endpoints, with the same config, the first time one is needed.
+
+use std::{ops::Bound, sync::Arc};
+
+use async_trait::async_trait;
+use neon::prelude::*;
+use tikv_client::{BoundRange, Config, Key, Result};
+use tikv_client_common::SecurityManager;
+use tikv_client_pd::RetryClient;
+use tikv_client_proto::metapb;
+use tokio::sync::Mutex;
+
+use crate::{codec::Decoded, utils::ToJS};
+
+pub struct RegionInfo {
+    pub id: u64,
+    pub start_key: Vec<u8>,
+    /// Empty for the last region.
+    pub end_key: Vec<u8>,
+    pub conf_ver: u64,
+    pub version: u64,
+    pub leader_store_id: Option<u64>,
+    pub peers: Vec<PeerInfo>,
+}
+
+pub struct PeerInfo {
+    pub id: u64,
+    pub store_id: u64,
+    pub is_learner: bool,
+}
+
+pub struct StoreInfo {
+    pub id: u64,
+    pub address: String,
+    pub state: String,
+}
+
+impl RegionInfo {
+    fn new(region: metapb::Region, leader: Option<metapb::Peer>) -> RegionInfo {
+        let epoch = region.region_epoch.unwrap_or_default();
+        RegionInfo {
+            id: region.id,
+            start_key: region.start_key,
+            end_key: region.end_key,
+            conf_ver: epoch.conf_ver,
+            version: epoch.version,
+            leader_store_id: leader.map(|leader| leader.store_id),
+            peers: region
+                .peers
+                .into_iter()
+                .map(|peer| PeerInfo {
+                    id: peer.id,
+                    store_id: peer.store_id,
+                    is_learner: peer.role == metapb::PeerRole::Learner as i32,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<metapb::Store> for StoreInfo {
+    fn from(store: metapb::Store) -> StoreInfo {
+        let state = metapb::StoreState::from_i32(store.state)
+            .map(|state| format!("{:?}", state))
+            .unwrap_or_default();
+        StoreInfo {
+            id: store.id,
+            address: store.address,
+            state,
+        }
+    }
+}
+
+#[async_trait]
+pub trait PlacementBackend: Send + Sync {
+    async fn locate_key(&self, key: Key) -> Result<RegionInfo>;
+    async fn stores(&self) -> Result<Vec<StoreInfo>>;
+
+    /// The regions overlapping `range`, in order, found by locating the key after each region.
+    async fn regions(&self, range: BoundRange) -> Result<Vec<RegionInfo>> {
+        let mut key = match range.from {
+            Bound::Included(key) | Bound::Excluded(key) => Vec::from(key),
+            Bound::Unbounded => vec![],
+        };
+        let mut regions = vec![];
+        loop {
+            let region = self.locate_key(key.into()).await?;
+            let end_key = region.end_key.clone();
+            regions.push(region);
+            // An empty end key means the end of the key space.
+            let is_last = end_key.is_empty()
+                || match &range.to {
+                    Bound::Included(end) | Bound::Excluded(end)
+                        if <&[u8]>::from(end).is_empty() =>
+                    {
+                        false
+                    }
+                    Bound::Included(end) => end_key.as_slice() > <&[u8]>::from(end),
+                    Bound::Excluded(end) => end_key.as_slice() >= <&[u8]>::from(end),
+                    Bound::Unbounded => false,
+                };
+            if is_last {
+                return Ok(regions);
+            }
+            key = end_key;
+        }
+    }
+}
+
+/// The security a client connects with, as the Rust client sets it up from `config`.
+pub fn security_manager(config: &Config) -> Result<Arc<SecurityManager>> {
+    let security = match (&config.ca_path, &config.cert_path, &config.key_path) {
+        (Some(ca_path), Some(cert_path), Some(key_path)) => {
+            SecurityManager::load(ca_path, cert_path, key_path)?
+        }
+        _ => SecurityManager::default(),
+    };
+    Ok(Arc::new(security))
+}
+
+pub struct PdPlacement {
+    pd_endpoints: Vec<String>,
+    config: Config,
+    pd: Mutex<Option<Arc<RetryClient>>>,
+}
+
+impl PdPlacement {
+    pub fn new(pd_endpoints: Vec<String>, config: Config) -> PdPlacement {
+        PdPlacement {
+            pd_endpoints,
+            config,
+            pd: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The PD client, connected on first use.
+    pub async fn pd(&self) -> Result<Arc<RetryClient>> {
+        let mut pd = self.pd.lock().await;
+        if let Some(pd) = &*pd {
+            return Ok(pd.clone());
+        }
+        let connected = RetryClient::connect(
+            &self.pd_endpoints,
+            security_manager(&self.config)?,
+            self.config.timeout,
+        )
+        .await?;
+        Ok(pd.get_or_insert(Arc::new(connected)).clone())
+    }
+}
+
+#[async_trait]
+impl PlacementBackend for PdPlacement {
+    async fn locate_key(&self, key: Key) -> Result<RegionInfo> {
+        let region = self.pd().await?.get_region(key.into()).await?;
+        Ok(RegionInfo::new(region.region, region.leader))
+    }
+
+    async fn stores(&self) -> Result<Vec<StoreInfo>> {
+        let stores = self.pd().await?.get_all_stores().await?;
+        Ok(stores.into_iter().map(StoreInfo::from).collect())
+    }
+}
+
+impl ToJS for Decoded<RegionInfo> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let Decoded {
+            codec,
+            values: info,
+        } = self;
+        let region = cx.empty_object();
+        let id = cx.number(info.id as f64);
+        region.set(cx, "id", id)?;
+        let start_key = codec.key(cx, info.start_key)?;
+        region.set(cx, "start_key", start_key)?;
+        let end_key = codec.key(cx, info.end_key)?;
+        region.set(cx, "end_key", end_key)?;
+
+        let epoch = cx.empty_object();
+        let conf_ver = cx.number(info.conf_ver as f64);
+        epoch.set(cx, "conf_ver", conf_ver)?;
+        let version = cx.number(info.version as f64);
+        epoch.set(cx, "version", version)?;
+        region.set(cx, "epoch", epoch)?;
+
+        let leader_store_id: Handle<JsValue> = match info.leader_store_id {
+            Some(store_id) => cx.number(store_id as f64).upcast(),
+            None => cx.null().upcast(),
+        };
+        region.set(cx, "leader_store_id", leader_store_id)?;
+
+        let peers = JsArray::new(cx, info.peers.len() as u32);
+        for (i, peer) in info.peers.into_iter().enumerate() {
+            let object = cx.empty_object();
+            let id = cx.number(peer.id as f64);
+            object.set(cx, "id", id)?;
+            let store_id = cx.number(peer.store_id as f64);
+            object.set(cx, "store_id", store_id)?;
+            let is_learner = cx.boolean(peer.is_learner);
+            object.set(cx, "is_learner", is_learner)?;
+            peers.set(cx, i as u32, object)?;
+        }
+        region.set(cx, "peers", peers)?;
+        Ok(region.upcast())
+    }
+}
+
+impl ToJS for Decoded<Vec<RegionInfo>> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let regions = JsArray::new(cx, self.values.len() as u32);
+        for (i, region) in self.values.into_iter().enumerate() {
+            let region = self.codec.decoded(region).to_js_value(cx)?;
+            regions.set(cx, i as u32, region)?;
+        }
+        Ok(regions.upcast())
+    }
+}
+
+impl ToJS for Vec<StoreInfo> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let stores = JsArray::new(cx, self.len() as u32);
+        for (i, store) in self.into_iter().enumerate() {
+            let object = cx.empty_object();
+            let id = cx.number(store.id as f64);
+            object.set(cx, "id", id)?;
+            let address = cx.string(store.address);
+            object.set(cx, "address", address)?;
+            let state = cx.string(store.state);
+            object.set(cx, "state", state)?;
+            stores.set(cx, i as u32, object)?;
+        }
+        Ok(stores.upcast())
+    }
+}
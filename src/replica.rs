@@ -0,0 +1,198 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Snapshots reading from followers.
+//!
+//! The Rust client sends every read to the leader of its region, so snapshots with follower reads
+//! send theirs with `store::Stores`. A follower asks the leader how far to catch up before
+//! serving a read, so it reads what the leader would, with less load on the leader.
+//!
+//! A read meeting a lock resolves it if expired, and otherwise waits for it to be.
+
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use tikv_client::{BoundRange, Error, Key, KvPair, Result, Timestamp, TimestampExt as _, Value};
+use tikv_client_proto::{errorpb, kvrpcpb, metapb};
+use tikv_client_store::Request;
+
+use crate::{
+    backend::SnapshotHandle,
+    locks::{range_keys, LockInfo, TikvLocks},
+    store::{reaches, Replica, REGION_BACKOFF},
+};
+
+/// The times a read meeting a lock is sent again, before failing with the lock.
+const LOCK_RETRIES: u32 = 10;
+
+pub struct ReplicaSnapshot {
+    locks: Arc<TikvLocks>,
+    timestamp: Timestamp,
+}
+
+impl ReplicaSnapshot {
+    pub fn new(locks: Arc<TikvLocks>, timestamp: Timestamp) -> ReplicaSnapshot {
+        ReplicaSnapshot { locks, timestamp }
+    }
+
+    /// Send the read `build` makes for the region holding `key` to a follower, returning the
+    /// region and the response. Locks, which `key_error` takes from the response, are resolved or
+    /// waited for.
+    async fn read<R, T>(
+        &self,
+        key: &[u8],
+        build: impl Fn(&metapb::Region) -> R,
+        region_error: impl Fn(&mut T) -> Option<errorpb::Error>,
+        key_error: impl Fn(&mut T) -> Option<kvrpcpb::KeyError>,
+    ) -> Result<(metapb::Region, T)>
+    where
+        R: Request,
+        T: Any,
+    {
+        let stores = self.locks.stores();
+        let mut retries = 0;
+        loop {
+            let (region, mut response) = stores
+                .send(key, Replica::Follower, &build, &region_error)
+                .await?;
+            let error = match key_error(&mut response) {
+                None => return Ok((region, response)),
+                Some(error) => error,
+            };
+            let lock = match error.locked.clone() {
+                Some(lock) if retries < LOCK_RETRIES => LockInfo::from(lock),
+                _ => return Err(Error::KeyError(error)),
+            };
+            retries += 1;
+            let now = stores.current_timestamp().await?;
+            let resolved = lock.is_expired(&now)
+                && self.locks.resolve(&lock, &mut HashMap::new(), &now).await?;
+            if !resolved {
+                tokio::time::sleep(REGION_BACKOFF * retries).await;
+            }
+        }
+    }
+
+    async fn scan_pairs(
+        &self,
+        range: BoundRange,
+        limit: u32,
+        key_only: bool,
+    ) -> Result<Vec<kvrpcpb::KvPair>> {
+        let (mut key, end) = range_keys(range);
+        let mut pairs = vec![];
+        while (pairs.len() as u32) < limit {
+            let build = |region: &metapb::Region| kvrpcpb::ScanRequest {
+                start_key: key.clone(),
+                end_key: if reaches(region, &end) {
+                    end.clone()
+                } else {
+                    region.end_key.clone()
+                },
+                limit: limit - pairs.len() as u32,
+                key_only,
+                version: self.timestamp.version(),
+                ..Default::default()
+            };
+            let (region, response) = self
+                .read(
+                    &key,
+                    build,
+                    |response: &mut kvrpcpb::ScanResponse| response.region_error.take(),
+                    |response: &mut kvrpcpb::ScanResponse| {
+                        response.pairs.iter_mut().find_map(|pair| pair.error.take())
+                    },
+                )
+                .await?;
+            pairs.extend(response.pairs);
+            if reaches(&region, &end) {
+                break;
+            }
+            key = region.end_key;
+        }
+        Ok(pairs)
+    }
+}
+
+/// The leading keys of the sorted `keys` which are in `region`, the first being in it.
+fn in_region<'a>(region: &metapb::Region, keys: &'a [Vec<u8>]) -> &'a [Vec<u8>] {
+    let count = keys
+        .iter()
+        .take_while(|key| region.end_key.is_empty() || **key < region.end_key)
+        .count();
+    &keys[..count]
+}
+
+#[async_trait]
+impl SnapshotHandle for ReplicaSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        let key = Vec::from(key);
+        let build = |_: &metapb::Region| kvrpcpb::GetRequest {
+            key: key.clone(),
+            version: self.timestamp.version(),
+            ..Default::default()
+        };
+        let (_, response) = self
+            .read(
+                &key,
+                build,
+                |response: &mut kvrpcpb::GetResponse| response.region_error.take(),
+                |response: &mut kvrpcpb::GetResponse| response.error.take(),
+            )
+            .await?;
+        Ok(if response.not_found {
+            None
+        } else {
+            Some(response.value)
+        })
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let mut keys: Vec<Vec<u8>> = keys.into_iter().map(Vec::from).collect();
+        keys.sort();
+        keys.dedup();
+        let mut pairs = vec![];
+        let mut rest = keys.as_slice();
+        while let Some(first) = rest.first() {
+            let build = |region: &metapb::Region| kvrpcpb::BatchGetRequest {
+                keys: in_region(region, rest).to_vec(),
+                version: self.timestamp.version(),
+                ..Default::default()
+            };
+            let (region, response) = self
+                .read(
+                    first,
+                    build,
+                    |response: &mut kvrpcpb::BatchGetResponse| response.region_error.take(),
+                    |response: &mut kvrpcpb::BatchGetResponse| {
+                        response.pairs.iter_mut().find_map(|pair| pair.error.take())
+                    },
+                )
+                .await?;
+            pairs.extend(
+                response
+                    .pairs
+                    .into_iter()
+                    .map(|pair| KvPair(pair.key.into(), pair.value)),
+            );
+            rest = &rest[in_region(&region, rest).len()..];
+        }
+        Ok(pairs)
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.scan_pairs(range, limit, false).await?;
+        Ok(pairs
+            .into_iter()
+            .map(|pair| KvPair(pair.key.into(), pair.value))
+            .collect())
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        let pairs = self.scan_pairs(range, limit, true).await?;
+        Ok(pairs.into_iter().map(|pair| pair.key.into()).collect())
+    }
+}
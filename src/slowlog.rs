@@ -0,0 +1,111 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Reports requests slower than a threshold to a JS function installed with `set_slow_log`.
+//!
+//! Region retries and their backoff happen inside the Rust client, which doesn't report them, so
+//! records don't carry a retry count or backoff time yet; that needs support in the client.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use neon::prelude::*;
+
+use crate::{
+    error::error_kind,
+    utils::{bytes_to_js_value, js_millis_to_duration, Request, Target},
+};
+
+lazy_static! {
+    static ref SLOW_LOG: RwLock<Option<SlowLog>> = RwLock::new(None);
+}
+
+struct SlowLog {
+    queue: EventQueue,
+    callback: Arc<Root<JsFunction>>,
+    threshold: Duration,
+}
+
+pub fn observe<T>(request: &Request, elapsed: Duration, result: &Result<T, tikv_client::Error>) {
+    let slow_log = SLOW_LOG.read().unwrap();
+    let slow_log = match &*slow_log {
+        Some(slow_log) if elapsed >= slow_log.threshold => slow_log,
+        _ => return,
+    };
+
+    let callback = slow_log.callback.clone();
+    let method = request.method;
+    let cf = request.cf.clone();
+    let target = request.target.clone();
+    let error = result.as_ref().err().map(error_kind);
+    slow_log.queue.send(move |mut cx| {
+        let record = cx.empty_object();
+        let method = cx.string(method);
+        record.set(&mut cx, "method", method)?;
+        if let Some(cf) = cf {
+            let cf = cx.string(cf);
+            record.set(&mut cx, "cf", cf)?;
+        }
+        match target {
+            Some(Target::Key(key)) => {
+                let key = bytes_to_js_value(&mut cx, key)?;
+                record.set(&mut cx, "key", key)?;
+            }
+            Some(Target::Keys(count)) => {
+                let count = cx.number(count as f64);
+                record.set(&mut cx, "key_count", count)?;
+            }
+            Some(Target::Ranges(count)) => {
+                let count = cx.number(count as f64);
+                record.set(&mut cx, "range_count", count)?;
+            }
+            Some(Target::Range(start, end)) => {
+                if let Some(start) = start {
+                    let start = bytes_to_js_value(&mut cx, start)?;
+                    record.set(&mut cx, "start", start)?;
+                }
+                if let Some(end) = end {
+                    let end = bytes_to_js_value(&mut cx, end)?;
+                    record.set(&mut cx, "end", end)?;
+                }
+            }
+            None => {}
+        }
+        let elapsed_ms = cx.number(elapsed.as_secs_f64() * 1000.0);
+        record.set(&mut cx, "elapsed_ms", elapsed_ms)?;
+        if let Some(error) = error {
+            let error = cx.string(error);
+            record.set(&mut cx, "error", error)?;
+        }
+
+        let callback = callback.to_inner(&mut cx);
+        let this = cx.undefined();
+        callback.call(&mut cx, this, vec![record])?;
+        Ok(())
+    });
+}
+
+pub fn set_slow_log(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let callback = cx.argument_opt(0);
+    let callback = match callback.and_then(|c| c.downcast::<JsFunction, _>(&mut cx).ok()) {
+        Some(callback) => callback.root(&mut cx),
+        None => {
+            *SLOW_LOG.write().unwrap() = None;
+            return Ok(cx.undefined());
+        }
+    };
+    let threshold_ms = cx.argument::<JsNumber>(1)?.value(&mut cx);
+    let threshold = js_millis_to_duration(&mut cx, "slow log threshold", threshold_ms)?;
+
+    let mut queue = cx.queue();
+    // Don't keep the process alive just to report slow requests.
+    queue.unref(&mut cx);
+    *SLOW_LOG.write().unwrap() = Some(SlowLog {
+        queue,
+        callback: Arc::new(callback),
+        threshold,
+    });
+    Ok(cx.undefined())
+}
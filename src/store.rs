@@ -0,0 +1,174 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Requests sent to the stores directly, for what the Rust client keeps to itself.
+//!
+//! Requests are routed with the PD client of `region::PdPlacement`, to the leader of the region
+//! holding a key or, for replica reads, to one of its followers. A request answered with a
+//! region error, as when the region moved or split, is sent again to the region looked up anew.
+
+use std::{
+    any::Any,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use grpcio::{EnvBuilder, Environment};
+use opentelemetry::{trace::Span as _, KeyValue};
+use tikv_client::{Error, Result, Timestamp};
+use tikv_client_proto::{errorpb, kvrpcpb, metapb};
+use tikv_client_store::{KvClient, KvConnect, KvRpcClient, Request, TikvConnect};
+
+use crate::{
+    region::{security_manager, PdPlacement},
+    trace,
+};
+
+/// The times a request answered with a region error is sent again.
+const REGION_RETRIES: u32 = 10;
+/// The wait before sending a request again, growing with each retry.
+pub const REGION_BACKOFF: Duration = Duration::from_millis(20);
+
+/// The peer of a region a request goes to.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Replica {
+    Leader,
+    /// A voting follower, taking turns between them, or the leader if the region has none. It
+    /// serves reads after asking the leader how far to catch up.
+    Follower,
+}
+
+pub struct Stores {
+    placement: Arc<PdPlacement>,
+    connect: TikvConnect,
+    /// Counts follower reads, to spread them over the followers.
+    follower_reads: AtomicUsize,
+}
+
+/// A client of a peer of the region holding a key.
+struct RegionClient {
+    region: metapb::Region,
+    context: kvrpcpb::Context,
+    client: KvRpcClient,
+}
+
+impl RegionClient {
+    async fn send<R: Request, T: Any>(&self, mut request: R) -> Result<T> {
+        request.set_context(self.context.clone());
+        let response = self.client.dispatch(&request).await?;
+        match response.downcast::<T>() {
+            Ok(response) => Ok(*response),
+            Err(_) => Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The store answered with a response of another type",
+            ))),
+        }
+    }
+}
+
+impl Stores {
+    /// Connect to the stores with the config of `placement`, once per client.
+    pub fn new(placement: Arc<PdPlacement>) -> Result<Stores> {
+        let env: Arc<Environment> = Arc::new(
+            EnvBuilder::new()
+                .cq_count(1)
+                .name_prefix("tikv-stores")
+                .build(),
+        );
+        let config = placement.config();
+        let connect = TikvConnect::new(env, security_manager(config)?, config.timeout);
+        Ok(Stores {
+            placement,
+            connect,
+            follower_reads: AtomicUsize::new(0),
+        })
+    }
+
+    pub async fn current_timestamp(&self) -> Result<Timestamp> {
+        self.placement.pd().await?.get_timestamp().await
+    }
+
+    async fn region_client(&self, key: Vec<u8>, replica: Replica) -> Result<RegionClient> {
+        let pd = self.placement.pd().await?;
+        let region = pd.clone().get_region(key).await?;
+        let leader = region.leader.ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Region {} has no leader", region.region.id),
+            ))
+        })?;
+        let followers: Vec<&metapb::Peer> = region
+            .region
+            .peers
+            .iter()
+            .filter(|peer| peer.id != leader.id && peer.role != metapb::PeerRole::Learner as i32)
+            .collect();
+        let (peer, replica_read) = if replica == Replica::Follower && !followers.is_empty() {
+            let turn = self.follower_reads.fetch_add(1, Ordering::Relaxed);
+            (followers[turn % followers.len()].clone(), true)
+        } else {
+            (leader, false)
+        };
+        let store = pd.get_store(peer.store_id).await?;
+        let client = self.connect.connect(&store.address)?;
+        let context = kvrpcpb::Context {
+            region_id: region.region.id,
+            region_epoch: region.region.region_epoch.clone(),
+            peer: Some(peer),
+            replica_read,
+            ..Default::default()
+        };
+        Ok(RegionClient {
+            region: region.region,
+            context,
+            client,
+        })
+    }
+
+    /// Send the request `build` makes for the region holding `key` to its `replica`, returning
+    /// the region and the response. Region errors, which `region_error` takes from the response,
+    /// are retried.
+    pub async fn send<R, T>(
+        &self,
+        key: &[u8],
+        replica: Replica,
+        build: impl Fn(&metapb::Region) -> R,
+        region_error: impl Fn(&mut T) -> Option<errorpb::Error>,
+    ) -> Result<(metapb::Region, T)>
+    where
+        R: Request,
+        T: Any,
+    {
+        let mut retries = 0;
+        loop {
+            let region = self.region_client(key.to_vec(), replica).await?;
+            let mut response: T = region.send(build(&region.region)).await?;
+            match region_error(&mut response) {
+                None => return Ok((region.region, response)),
+                Some(error) if retries >= REGION_RETRIES => return Err(Error::RegionError(error)),
+                Some(error) => {
+                    retries += 1;
+                    // Covers the backoff, after which the region is looked up again.
+                    let mut span = trace::start_child_span(
+                        "region_retry",
+                        vec![
+                            KeyValue::new("tikv.region_id", region.region.id as i64),
+                            KeyValue::new("tikv.retry", retries as i64),
+                            KeyValue::new("tikv.region_error", error.message),
+                        ],
+                    );
+                    tokio::time::sleep(REGION_BACKOFF * retries).await;
+                    span.end();
+                }
+            }
+        }
+    }
+}
+
+/// Whether `region` reaches `end`, an empty end key meaning the end of the key space.
+pub fn reaches(region: &metapb::Region, end: &[u8]) -> bool {
+    region.end_key.is_empty() || (!end.is_empty() && region.end_key.as_slice() >= end)
+}
@@ -0,0 +1,392 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Backends confining every request to the keys starting with a prefix.
+//!
+//! Keys and range bounds are relative to the prefix: it is prepended to them on the way in
+//! and stripped from returned keys on the way out. An unbounded or empty end bound means the
+//! end of the subspace, so a range can never reach keys outside of it.
+
+use std::{ops::Bound, sync::Arc};
+
+use async_trait::async_trait;
+use tikv_client::{BoundRange, ColumnFamily, Key, KvPair, Result, Timestamp, Value};
+use tokio::sync::Mutex;
+
+use crate::{
+    backend::{
+        Checksum, CoprocessorResponse, PendingMutation, RawBackend, SnapshotHandle,
+        TransactionHandle,
+    },
+    region::{PlacementBackend, RegionInfo, StoreInfo},
+};
+
+#[derive(Clone)]
+pub struct Subspace {
+    prefix: Vec<u8>,
+}
+
+impl Subspace {
+    pub fn new(prefix: Vec<u8>) -> Subspace {
+        Subspace { prefix }
+    }
+
+    fn key(&self, key: Key) -> Key {
+        let mut prefixed = self.prefix.clone();
+        prefixed.extend_from_slice(&Vec::from(key));
+        prefixed.into()
+    }
+
+    fn keys(&self, keys: Vec<Key>) -> Vec<Key> {
+        keys.into_iter().map(|key| self.key(key)).collect()
+    }
+
+    fn owns(&self, key: &Key) -> bool {
+        <&[u8]>::from(key).starts_with(&self.prefix)
+    }
+
+    fn strip(&self, key: Key) -> Key {
+        let key = Vec::from(key);
+        key[self.prefix.len().min(key.len())..].to_vec().into()
+    }
+
+    fn strip_keys(&self, keys: Vec<Key>) -> Vec<Key> {
+        keys.into_iter().map(|key| self.strip(key)).collect()
+    }
+
+    fn pairs(&self, pairs: Vec<KvPair>) -> Vec<KvPair> {
+        pairs
+            .into_iter()
+            .map(|KvPair(key, value)| KvPair(self.key(key), value))
+            .collect()
+    }
+
+    fn strip_pairs(&self, pairs: Vec<KvPair>) -> Vec<KvPair> {
+        pairs
+            .into_iter()
+            .map(|KvPair(key, value)| KvPair(self.strip(key), value))
+            .collect()
+    }
+
+    /// The first key after all keys starting with the prefix, if there is one.
+    fn end(&self) -> Option<Vec<u8>> {
+        let mut end = self.prefix.clone();
+        while let Some(last) = end.pop() {
+            if last < 0xff {
+                end.push(last + 1);
+                return Some(end);
+            }
+        }
+        None
+    }
+
+    fn range(&self, range: BoundRange) -> BoundRange {
+        let from = match range.from {
+            Bound::Included(key) => Bound::Included(self.key(key)),
+            Bound::Excluded(key) => Bound::Excluded(self.key(key)),
+            Bound::Unbounded => Bound::Included(self.prefix.clone().into()),
+        };
+        let to = match range.to {
+            Bound::Included(key) if !Vec::from(key.clone()).is_empty() => {
+                Bound::Included(self.key(key))
+            }
+            Bound::Excluded(key) if !Vec::from(key.clone()).is_empty() => {
+                Bound::Excluded(self.key(key))
+            }
+            _ => match self.end() {
+                Some(end) => Bound::Excluded(end.into()),
+                None => Bound::Unbounded,
+            },
+        };
+        BoundRange { from, to }
+    }
+}
+
+pub struct SubspaceRaw {
+    pub inner: Arc<dyn RawBackend>,
+    pub subspace: Subspace,
+}
+
+#[async_trait]
+impl RawBackend for SubspaceRaw {
+    fn with_cf(&self, cf: ColumnFamily) -> Arc<dyn RawBackend> {
+        Arc::new(SubspaceRaw {
+            inner: self.inner.with_cf(cf),
+            subspace: self.subspace.clone(),
+        })
+    }
+
+    fn placement(&self) -> Arc<dyn PlacementBackend> {
+        Arc::new(SubspacePlacement {
+            inner: self.inner.placement(),
+            subspace: self.subspace.clone(),
+        })
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        self.inner.get(self.subspace.key(key)).await
+    }
+
+    async fn batch_get(&self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.batch_get(self.subspace.keys(keys)).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<()> {
+        self.inner.put(self.subspace.key(key), value).await
+    }
+
+    async fn put_with_ttl(&self, key: Key, value: Value, ttl_secs: u64) -> Result<()> {
+        self.inner
+            .put_with_ttl(self.subspace.key(key), value, ttl_secs)
+            .await
+    }
+
+    async fn get_key_ttl(&self, key: Key) -> Result<Option<u64>> {
+        self.inner.get_key_ttl(self.subspace.key(key)).await
+    }
+
+    async fn batch_put(&self, pairs: Vec<KvPair>) -> Result<()> {
+        self.inner.batch_put(self.subspace.pairs(pairs)).await
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.inner.delete(self.subspace.key(key)).await
+    }
+
+    async fn batch_delete(&self, keys: Vec<Key>) -> Result<()> {
+        self.inner.batch_delete(self.subspace.keys(keys)).await
+    }
+
+    async fn delete_range(&self, range: BoundRange) -> Result<()> {
+        self.inner.delete_range(self.subspace.range(range)).await
+    }
+
+    async fn scan(&self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs = self.inner.scan(self.subspace.range(range), limit).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn scan_keys(&self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        let keys = self
+            .inner
+            .scan_keys(self.subspace.range(range), limit)
+            .await?;
+        Ok(self.subspace.strip_keys(keys))
+    }
+
+    async fn batch_scan(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<KvPair>>> {
+        let ranges = ranges.into_iter().map(|range| self.subspace.range(range));
+        let results = self.inner.batch_scan(ranges.collect(), each_limit).await?;
+        Ok(results
+            .into_iter()
+            .map(|pairs| self.subspace.strip_pairs(pairs))
+            .collect())
+    }
+
+    async fn batch_scan_keys(
+        &self,
+        ranges: Vec<BoundRange>,
+        each_limit: u32,
+    ) -> Result<Vec<Vec<Key>>> {
+        let ranges = ranges.into_iter().map(|range| self.subspace.range(range));
+        let results = self
+            .inner
+            .batch_scan_keys(ranges.collect(), each_limit)
+            .await?;
+        Ok(results
+            .into_iter()
+            .map(|keys| self.subspace.strip_keys(keys))
+            .collect())
+    }
+
+    async fn checksum(&self, range: BoundRange) -> Result<Checksum> {
+        self.inner.checksum(self.subspace.range(range)).await
+    }
+
+    async fn coprocessor(
+        &self,
+        name: String,
+        version: String,
+        ranges: Vec<BoundRange>,
+        request: Vec<u8>,
+    ) -> Result<Vec<CoprocessorResponse>> {
+        let ranges = ranges.into_iter().map(|range| self.subspace.range(range));
+        let responses = self
+            .inner
+            .coprocessor(name, version, ranges.collect(), request)
+            .await?;
+        Ok(responses
+            .into_iter()
+            .map(|response| CoprocessorResponse {
+                data: response.data,
+                ranges: response
+                    .ranges
+                    .into_iter()
+                    .map(|range| self.subspace.strip(range.start)..self.subspace.strip(range.end))
+                    .collect(),
+            })
+            .collect())
+    }
+}
+
+/// Locates keys and ranges relative to the prefix, returning regions with their keys as stored.
+struct SubspacePlacement {
+    inner: Arc<dyn PlacementBackend>,
+    subspace: Subspace,
+}
+
+#[async_trait]
+impl PlacementBackend for SubspacePlacement {
+    async fn locate_key(&self, key: Key) -> Result<RegionInfo> {
+        self.inner.locate_key(self.subspace.key(key)).await
+    }
+
+    async fn stores(&self) -> Result<Vec<StoreInfo>> {
+        self.inner.stores().await
+    }
+
+    async fn regions(&self, range: BoundRange) -> Result<Vec<RegionInfo>> {
+        self.inner.regions(self.subspace.range(range)).await
+    }
+}
+
+/// Shares the transaction of the `Transaction` it was created from.
+pub struct SubspaceTransaction {
+    pub inner: Arc<Mutex<Box<dyn TransactionHandle>>>,
+    pub subspace: Subspace,
+}
+
+#[async_trait]
+impl TransactionHandle for SubspaceTransaction {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.get(key).await
+    }
+
+    async fn get_for_update(&mut self, key: Key) -> Result<Option<Value>> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.get_for_update(key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let keys = self.subspace.keys(keys);
+        let pairs = self.inner.lock().await.batch_get(keys).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn batch_get_for_update(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let keys = self.subspace.keys(keys);
+        let pairs = self.inner.lock().await.batch_get_for_update(keys).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let range = self.subspace.range(range);
+        let pairs = self.inner.lock().await.scan(range, limit).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        let range = self.subspace.range(range);
+        let keys = self.inner.lock().await.scan_keys(range, limit).await?;
+        Ok(self.subspace.strip_keys(keys))
+    }
+
+    async fn lock_keys(&mut self, keys: Vec<Key>) -> Result<()> {
+        let keys = self.subspace.keys(keys);
+        self.inner.lock().await.lock_keys(keys).await
+    }
+
+    async fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.put(key, value).await
+    }
+
+    async fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.insert(key, value).await
+    }
+
+    async fn delete(&mut self, key: Key) -> Result<()> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.delete(key).await
+    }
+
+    async fn commit(&mut self) -> Result<Option<Timestamp>> {
+        self.inner.lock().await.commit().await
+    }
+
+    /// Savepoints are shared with the transaction the subspace is a view of.
+    async fn savepoint(&mut self, name: String) -> Result<()> {
+        self.inner.lock().await.savepoint(name).await
+    }
+
+    async fn rollback_to(&mut self, name: String) -> Result<()> {
+        self.inner.lock().await.rollback_to(name).await
+    }
+
+    /// Only the mutations of keys in the subspace.
+    async fn pending_mutations(&mut self) -> Result<Vec<PendingMutation>> {
+        let pending = self.inner.lock().await.pending_mutations().await?;
+        Ok(pending
+            .into_iter()
+            .filter(|mutation| self.subspace.owns(mutation.key()))
+            .map(|mutation| match mutation {
+                PendingMutation::Put(key, value) => {
+                    PendingMutation::Put(self.subspace.strip(key), value)
+                }
+                PendingMutation::Insert(key, value) => {
+                    PendingMutation::Insert(self.subspace.strip(key), value)
+                }
+                PendingMutation::Delete(key) => PendingMutation::Delete(self.subspace.strip(key)),
+                PendingMutation::Lock(key) => PendingMutation::Lock(self.subspace.strip(key)),
+            })
+            .collect())
+    }
+}
+
+/// Shares the snapshot of the `Snapshot` it was created from.
+pub struct SubspaceSnapshot {
+    pub inner: Arc<Mutex<Box<dyn SnapshotHandle>>>,
+    pub subspace: Subspace,
+}
+
+#[async_trait]
+impl SnapshotHandle for SubspaceSnapshot {
+    async fn get(&mut self, key: Key) -> Result<Option<Value>> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.get(key).await
+    }
+
+    async fn key_exists(&mut self, key: Key) -> Result<bool> {
+        let key = self.subspace.key(key);
+        self.inner.lock().await.key_exists(key).await
+    }
+
+    async fn batch_get(&mut self, keys: Vec<Key>) -> Result<Vec<KvPair>> {
+        let keys = self.subspace.keys(keys);
+        let pairs = self.inner.lock().await.batch_get(keys).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn scan(&mut self, range: BoundRange, limit: u32) -> Result<Vec<KvPair>> {
+        let range = self.subspace.range(range);
+        let pairs = self.inner.lock().await.scan(range, limit).await?;
+        Ok(self.subspace.strip_pairs(pairs))
+    }
+
+    async fn scan_keys(&mut self, range: BoundRange, limit: u32) -> Result<Vec<Key>> {
+        let range = self.subspace.range(range);
+        let keys = self.inner.lock().await.scan_keys(range, limit).await?;
+        Ok(self.subspace.strip_keys(keys))
+    }
+}
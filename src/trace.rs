@@ -0,0 +1,211 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! OpenTelemetry spans for requests.
+//!
+//! Requests are created on the JS thread, so the JS wrappers pass a W3C `traceparent` by
+//! calling `set_trace_context` right before the native function; the next `Request`
+//! created takes it and the span of the request becomes its child. Steps of a request
+//! taken by this crate, such as the region retries and lock resolutions of cleaning up
+//! locks, get child spans of the request span. Spans are exported through the provider set
+//! up by `configure_tracing`, and dropped until then.
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use neon::prelude::*;
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    propagation::TextMapPropagator,
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        propagation::TraceContextPropagator,
+        trace as sdktrace, Resource,
+    },
+    trace::{Span, SpanKind, StatusCode, TraceContextExt, TraceError, Tracer},
+    Context, KeyValue,
+};
+
+use crate::{error::error_kind, utils::Request, utils::RUNTIME};
+
+const TRACER_NAME: &str = "tikv-client-node";
+
+thread_local! {
+    static NEXT_TRACE_CONTEXT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Take the trace context set for the request being created on this thread.
+pub fn take_trace_context() -> Option<String> {
+    NEXT_TRACE_CONTEXT.with(|context| context.borrow_mut().take())
+}
+
+pub fn set_trace_context(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let traceparent = cx
+        .argument_opt(0)
+        .and_then(|traceparent| traceparent.downcast::<JsString, _>(&mut cx).ok())
+        .map(|traceparent| traceparent.value(&mut cx));
+    NEXT_TRACE_CONTEXT.with(|context| *context.borrow_mut() = traceparent);
+    Ok(cx.undefined())
+}
+
+/// Start the span of `request`, returning the context to run it in so that the spans of its
+/// steps become children of it.
+pub fn start_span(request: &Request) -> Context {
+    let parent = match &request.trace_context {
+        Some(traceparent) => {
+            let mut carrier = HashMap::new();
+            carrier.insert("traceparent".to_owned(), traceparent.clone());
+            TraceContextPropagator::new().extract(&carrier)
+        }
+        None => Context::new(),
+    };
+
+    let mut attributes = vec![
+        KeyValue::new("db.system", "tikv"),
+        KeyValue::new("db.operation", request.method),
+    ];
+    if let Some(cf) = &request.cf {
+        attributes.push(KeyValue::new("tikv.cf", cf.clone()));
+    }
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(request.method)
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &parent);
+    parent.with_span(span)
+}
+
+pub fn end_span<T>(context: &Context, result: &Result<T, tikv_client::Error>) {
+    let span = context.span();
+    if let Err(err) = result {
+        span.set_attribute(KeyValue::new("error.kind", error_kind(err)));
+        span.set_status(StatusCode::Error, err.to_string());
+    }
+    span.end();
+}
+
+/// Start the span of a step of the request being run, as a child of its span.
+pub fn start_child_span(name: &'static str, attributes: Vec<KeyValue>) -> BoxedSpan {
+    let tracer = global::tracer(TRACER_NAME);
+    tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start(&tracer)
+}
+
+pub fn end_child_span<T>(mut span: BoxedSpan, result: &Result<T, tikv_client::Error>) {
+    if let Err(err) = result {
+        span.set_attribute(KeyValue::new("error.kind", error_kind(err)));
+        span.set_status(StatusCode::Error, err.to_string());
+    }
+    span.end();
+}
+
+/// Writes each finished span as a line of JSON, for tests and local debugging.
+#[derive(Debug)]
+struct FileExporter {
+    file: File,
+}
+
+#[async_trait]
+impl SpanExporter for FileExporter {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        for span in batch {
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| (key.as_str().to_owned(), value.as_str().into_owned().into()))
+                .collect();
+            let line = serde_json::json!({
+                "trace_id": span.span_context.trace_id().to_hex(),
+                "span_id": span.span_context.span_id().to_hex(),
+                "parent_span_id": span.parent_span_id.to_hex(),
+                "name": span.name,
+                "start_time_unix_nano": unix_nanos(span.start_time),
+                "end_time_unix_nano": unix_nanos(span.end_time),
+                "status_code": format!("{:?}", span.status_code),
+                "status_message": span.status_message,
+                "attributes": attributes,
+            });
+            writeln!(self.file, "{}", line).map_err(|err| TraceError::from(err.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default()
+}
+
+fn get_string<'a>(
+    cx: &mut FunctionContext<'a>,
+    options: Handle<JsObject>,
+    key: &str,
+) -> NeonResult<Option<String>> {
+    let value = options.get(cx, key)?;
+    Ok(value
+        .downcast::<JsString, _>(cx)
+        .ok()
+        .map(|value| value.value(cx)))
+}
+
+pub fn configure_tracing(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let options = cx.argument::<JsObject>(0)?;
+    let exporter = get_string(&mut cx, options, "exporter")?.unwrap_or_else(|| "none".to_owned());
+    let service_name = get_string(&mut cx, options, "service_name")?
+        .map(Cow::Owned)
+        .unwrap_or(Cow::Borrowed(TRACER_NAME));
+    let config = sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        service_name,
+    )]));
+
+    match exporter.as_str() {
+        "otlp" => {
+            let endpoint = get_string(&mut cx, options, "endpoint")?
+                .unwrap_or_else(|| "http://localhost:4317".to_owned());
+            // The batch exporter runs on the client runtime.
+            let _guard = RUNTIME.enter();
+            let result = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(config)
+                .install_batch(opentelemetry::runtime::Tokio);
+            if let Err(err) = result {
+                return cx.throw_error(err.to_string());
+            }
+        }
+        "file" => {
+            let path = match get_string(&mut cx, options, "path")? {
+                Some(path) => path,
+                None => return cx.throw_error("The file exporter requires a `path`"),
+            };
+            let file = OpenOptions::new().create(true).append(true).open(&path);
+            let file = match file {
+                Ok(file) => file,
+                Err(err) => return cx.throw_error(format!("Cannot open {}: {}", path, err)),
+            };
+            let provider = sdktrace::TracerProvider::builder()
+                .with_simple_exporter(FileExporter { file })
+                .with_config(config)
+                .build();
+            global::set_tracer_provider(provider);
+        }
+        "none" => global::shutdown_tracer_provider(),
+        exporter => return cx.throw_error(format!("Unknown exporter: {}", exporter)),
+    }
+    Ok(cx.undefined())
+}
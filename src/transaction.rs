@@ -1,27 +1,143 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
 use crate::{
-    utils::{js_array_to_rust_keys, send_result, to_bound_range, RUNTIME},
+    backend::{
+        connect_transaction, PendingMutation, SnapshotHandle, TransactionHandle, TxnOptions,
+    },
+    buffer::WriteBuffer,
+    cache::{CachedSnapshot, DEFAULT_CAPACITY},
+    coalesce::{Coalescer, Coalescing},
+    codec::{Codec, KeyType},
+    compression::{CompressedTransactionBackend, Compression},
+    encryption::{EncryptedTransactionBackend, Encryption},
+    gc::{self, GcOptions, GcScheduler},
+    limits::{LimitedTransaction, Limits},
+    read_only::ReadOnlyTransaction,
+    subspace::{Subspace, SubspaceSnapshot, SubspaceTransaction},
+    utils::{
+        argument_to_bytes, js_array_to_rust_keys, js_value_to_bytes, spawn_request, to_bound_range,
+        Request,
+    },
     Snapshot, Transaction, TransactionClient,
 };
+use lru::LruCache;
 use neon::prelude::*;
+use std::{io, sync::Arc};
 use tikv_client::TimestampExt as _;
-use tikv_client::TransactionOptions;
-use tikv_client::{Key, KvPair};
+use tokio::sync::Mutex;
 
 impl TransactionClient {
     pub fn connect(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let pd_endpoint = cx.argument::<JsString>(0)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
-        let result = tikv_client::TransactionClient::new(vec![pd_endpoint], None);
+        let result = connect_transaction(pd_endpoint);
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = result.await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_connect");
+        spawn_request(request, queue, callback, async move {
+            result.await.map(|inner| TransactionClient {
+                inner,
+                codec: Codec::default(),
+                coalescing: None,
+                limits: None,
+            })
         });
         Ok(cx.undefined())
     }
 
+    pub fn with_codec(mut cx: FunctionContext) -> JsResult<JsBox<TransactionClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let codec = cx.argument::<JsValue>(0)?;
+        let codec = client.codec.with_values(&mut cx, codec)?;
+        Ok(cx.boxed(TransactionClient {
+            inner: client.inner.clone(),
+            codec,
+            coalescing: client.coalescing,
+            limits: client.limits,
+        }))
+    }
+
+    pub fn with_key_type(mut cx: FunctionContext) -> JsResult<JsBox<TransactionClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let key_type = cx.argument::<JsString>(0)?;
+        let key_type = KeyType::from_js(&mut cx, key_type)?;
+        Ok(cx.boxed(TransactionClient {
+            inner: client.inner.clone(),
+            codec: client.codec.with_keys(key_type),
+            coalescing: client.coalescing,
+            limits: client.limits,
+        }))
+    }
+
+    pub fn with_compression(mut cx: FunctionContext) -> JsResult<JsBox<TransactionClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let compression = Compression::from_js(&mut cx, options)?;
+        let inner = Arc::new(CompressedTransactionBackend {
+            inner: client.inner.clone(),
+            compression: Arc::new(compression),
+        });
+        Ok(cx.boxed(TransactionClient {
+            inner,
+            codec: client.codec.clone(),
+            coalescing: client.coalescing,
+            limits: client.limits,
+        }))
+    }
+
+    pub fn with_encryption(mut cx: FunctionContext) -> JsResult<JsBox<TransactionClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let encryption = Encryption::from_js(&mut cx, options)?;
+        let inner = Arc::new(EncryptedTransactionBackend {
+            inner: client.inner.clone(),
+            encryption: Arc::new(encryption),
+        });
+        Ok(cx.boxed(TransactionClient {
+            inner,
+            codec: client.codec.clone(),
+            coalescing: client.coalescing,
+            limits: client.limits,
+        }))
+    }
+
+    /// Coalesce the gets of its transactions issued close together into batch gets.
+    pub fn with_coalescing(mut cx: FunctionContext) -> JsResult<JsBox<TransactionClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let coalescing = Coalescing::from_js(&mut cx, options)?;
+        Ok(cx.boxed(TransactionClient {
+            inner: client.inner.clone(),
+            codec: client.codec.clone(),
+            coalescing: Some(coalescing),
+            limits: client.limits,
+        }))
+    }
+
+    /// Limit the size and duration of its transactions, failing the writes which would exceed them.
+    pub fn with_limits(mut cx: FunctionContext) -> JsResult<JsBox<TransactionClient>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let limits = Limits::from_js(&mut cx, options)?;
+        Ok(cx.boxed(TransactionClient {
+            inner: client.inner.clone(),
+            codec: client.codec.clone(),
+            coalescing: client.coalescing,
+            limits: Some(limits),
+        }))
+    }
+
     pub fn begin(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx
             .this()
@@ -29,15 +145,55 @@ impl TransactionClient {
         let pessimistic = cx.argument::<JsBoolean>(0)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
+        let coalescing = client.coalescing;
+        let limits = client.limits;
+
+        let queue = cx.queue();
+        let request = Request::new("txn_begin");
+        spawn_request(request, queue, callback, async move {
+            let options = TxnOptions {
+                pessimistic,
+                ..TxnOptions::default()
+            };
+            let mut inner: Box<dyn TransactionHandle> =
+                Box::new(WriteBuffer::new(gc::begin(&*inner, options).await?));
+            if let Some(limits) = limits {
+                inner = Box::new(LimitedTransaction::new(inner, limits));
+            }
+            Ok(Transaction {
+                inner: Arc::new(Mutex::new(inner)),
+                codec,
+                coalescer: coalescing.map(|coalescing| Arc::new(Coalescer::new(coalescing))),
+            })
+        });
+        Ok(cx.undefined())
+    }
+
+    /// Begin a transaction which can only read, and rejects writes without sending them.
+    pub fn begin_read_only(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+        let coalescing = client.coalescing;
 
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let inner = if pessimistic {
-                inner.begin_pessimistic().await
-            } else {
-                inner.begin_optimistic().await
+        let request = Request::new("txn_begin_read_only");
+        spawn_request(request, queue, callback, async move {
+            let options = TxnOptions {
+                read_only: true,
+                ..TxnOptions::default()
             };
-            send_result(queue, callback, inner);
+            let inner: Box<dyn TransactionHandle> =
+                Box::new(ReadOnlyTransaction::new(gc::begin(&*inner, options).await?));
+            Ok(Transaction {
+                inner: Arc::new(Mutex::new(inner)),
+                codec,
+                coalescer: coalescing.map(|coalescing| Arc::new(Coalescer::new(coalescing))),
+            })
         });
         Ok(cx.undefined())
     }
@@ -48,20 +204,108 @@ impl TransactionClient {
             .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
         let timestamp = cx.argument::<JsNumber>(0)?.value(&mut cx) as u64;
         let pessimistic = cx.argument::<JsBoolean>(1)?.value(&mut cx);
-        let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+        let replica_read = cx.argument::<JsBoolean>(2)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
 
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let inner = inner.snapshot(
+        let request = Request::new("txn_snapshot");
+        spawn_request(request, queue, callback, async move {
+            let inner = gc::snapshot(
+                &*inner,
                 tikv_client::Timestamp::from_version(timestamp),
-                if pessimistic {
-                    TransactionOptions::new_pessimistic()
-                } else {
-                    TransactionOptions::new_optimistic()
+                TxnOptions {
+                    pessimistic,
+                    replica_read,
+                    ..TxnOptions::default()
+                },
+            );
+            Ok(Snapshot {
+                inner: Arc::new(Mutex::new(inner)),
+                codec,
+            })
+        });
+        Ok(cx.undefined())
+    }
+
+    /// Snapshot the data as it was `seconds` ago by the clock of PD, for reads which can be stale.
+    pub fn snapshot_stale(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let seconds = cx.argument::<JsNumber>(0)?.value(&mut cx);
+        let pessimistic = cx.argument::<JsBoolean>(1)?.value(&mut cx);
+        let replica_read = cx.argument::<JsBoolean>(2)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+        if !seconds.is_finite() || seconds < 0.0 {
+            return cx.throw_range_error(format!("Invalid staleness: {}", seconds));
+        }
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_snapshot");
+        spawn_request(request, queue, callback, async move {
+            let now = inner.current_timestamp().await?;
+            let physical = now.physical - (seconds * 1000.0) as i64;
+            let inner = gc::snapshot(
+                &*inner,
+                at_physical_time(physical),
+                TxnOptions {
+                    pessimistic,
+                    replica_read,
+                    ..TxnOptions::default()
+                },
+            );
+            Ok(Snapshot {
+                inner: Arc::new(Mutex::new(inner)),
+                codec,
+            })
+        });
+        Ok(cx.undefined())
+    }
+
+    /// Snapshot the data as it was at a time in milliseconds since the Unix epoch, which must not
+    /// be after the current time of PD: commits could still land before a later timestamp, so
+    /// reading at it wouldn't repeat.
+    pub fn snapshot_at_time(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let time_ms = cx.argument::<JsNumber>(0)?.value(&mut cx);
+        let pessimistic = cx.argument::<JsBoolean>(1)?.value(&mut cx);
+        let replica_read = cx.argument::<JsBoolean>(2)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+        if !time_ms.is_finite() || time_ms < 0.0 {
+            return cx.throw_range_error(format!("Invalid snapshot time: {}", time_ms));
+        }
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_snapshot");
+        spawn_request(request, queue, callback, async move {
+            let now = inner.current_timestamp().await?;
+            if time_ms as i64 > now.physical {
+                return Err(tikv_client::Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Snapshot time {} is after the current time of PD", time_ms),
+                )));
+            }
+            let inner = gc::snapshot(
+                &*inner,
+                at_physical_time(time_ms as i64),
+                TxnOptions {
+                    pessimistic,
+                    replica_read,
+                    ..TxnOptions::default()
                 },
             );
-            send_result(queue, callback, Ok(inner));
+            Ok(Snapshot {
+                inner: Arc::new(Mutex::new(inner)),
+                codec,
+            })
         });
         Ok(cx.undefined())
     }
@@ -74,9 +318,9 @@ impl TransactionClient {
         let inner = client.inner.clone();
 
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = inner.current_timestamp().await;
-            send_result(queue, callback, result.map(|op| Some(op)));
+        let request = Request::new("txn_current_timestamp");
+        spawn_request(request, queue, callback, async move {
+            inner.current_timestamp().await.map(Some)
         });
         Ok(cx.undefined())
     }
@@ -90,28 +334,226 @@ impl TransactionClient {
         let inner = client.inner.clone();
 
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
-            let result = inner
+        let request = Request::new("txn_gc");
+        spawn_request(request, queue, callback, async move {
+            inner
                 .gc(tikv_client::Timestamp::from_version(safepoint))
-                .await;
-            send_result(queue, callback, result);
+                .await
+        });
+        Ok(cx.undefined())
+    }
+
+    /// Collect garbage in the background every interval, reporting each run to the callback.
+    pub fn start_gc(mut cx: FunctionContext) -> JsResult<JsBox<GcScheduler>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let options = cx.argument::<JsObject>(0)?;
+        let options = GcOptions::from_js(&mut cx, options)?;
+        let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
+        let mut queue = cx.queue();
+        // Don't keep the process alive just to collect garbage.
+        queue.unref(&mut cx);
+        let scheduler = GcScheduler::spawn(client.inner.clone(), options, queue, callback);
+        Ok(cx.boxed(scheduler))
+    }
+
+    /// List the locks in a range, of transactions started up to now.
+    pub fn scan_locks(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
+        let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_scan_locks").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
+            let range = to_bound_range(Some(start), Some(end), true, false);
+            let now = inner.current_timestamp().await?;
+            inner
+                .scan_locks(range, now, limit)
+                .await
+                .map(|locks| codec.decoded(locks))
+        });
+        Ok(cx.undefined())
+    }
+
+    pub fn cleanup_locks(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
+        let safepoint = cx.argument::<JsNumber>(2)?.value(&mut cx) as u64;
+        let dry_run = cx.argument::<JsBoolean>(3)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(4)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_cleanup_locks").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
+            let range = to_bound_range(Some(start), Some(end), true, false);
+            inner
+                .cleanup_locks(
+                    range,
+                    tikv_client::Timestamp::from_version(safepoint),
+                    dry_run,
+                )
+                .await
+                .map(|locks| codec.decoded(locks))
+        });
+        Ok(cx.undefined())
+    }
+
+    pub fn locate_key(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
+        let placement = client.inner.placement();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_locate_key").key(&key);
+        spawn_request(request, queue, callback, async move {
+            placement
+                .locate_key(key.into())
+                .await
+                .map(|region| codec.decoded(region))
+        });
+        Ok(cx.undefined())
+    }
+
+    pub fn regions(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
+        let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+        let placement = client.inner.placement();
+        let codec = client.codec.clone();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_regions").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
+            let range = to_bound_range(Some(start), Some(end), true, false);
+
+            placement
+                .regions(range)
+                .await
+                .map(|regions| codec.decoded(regions))
         });
         Ok(cx.undefined())
     }
+
+    pub fn stores(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<TransactionClient>, _>(&mut cx)?;
+        let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+        let placement = client.inner.placement();
+
+        let queue = cx.queue();
+        let request = Request::new("txn_stores");
+        spawn_request(
+            request,
+            queue,
+            callback,
+            async move { placement.stores().await },
+        );
+        Ok(cx.undefined())
+    }
+}
+
+/// The first timestamp PD can allocate at a time in milliseconds, which sees every transaction
+/// committed before it.
+pub fn at_physical_time(physical: i64) -> tikv_client::Timestamp {
+    tikv_client::Timestamp {
+        physical: physical.max(0),
+        logical: 0,
+        ..Default::default()
+    }
 }
 
 impl Snapshot {
+    pub fn subspace(mut cx: FunctionContext) -> JsResult<JsBox<Snapshot>> {
+        let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
+        let prefix = argument_to_bytes(&mut cx, 0)?;
+        let inner: Box<dyn SnapshotHandle> = Box::new(SubspaceSnapshot {
+            inner: client.inner.clone(),
+            subspace: Subspace::new(prefix),
+        });
+        Ok(cx.boxed(Snapshot {
+            inner: Arc::new(Mutex::new(inner)),
+            codec: client.codec.clone(),
+        }))
+    }
+
+    pub fn with_codec(mut cx: FunctionContext) -> JsResult<JsBox<Snapshot>> {
+        let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
+        let codec = cx.argument::<JsValue>(0)?;
+        let codec = client.codec.with_values(&mut cx, codec)?;
+        Ok(cx.boxed(Snapshot {
+            inner: client.inner.clone(),
+            codec,
+        }))
+    }
+
+    pub fn with_key_type(mut cx: FunctionContext) -> JsResult<JsBox<Snapshot>> {
+        let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
+        let key_type = cx.argument::<JsString>(0)?;
+        let key_type = KeyType::from_js(&mut cx, key_type)?;
+        Ok(cx.boxed(Snapshot {
+            inner: client.inner.clone(),
+            codec: client.codec.with_keys(key_type),
+        }))
+    }
+
+    /// Remember up to `capacity` values read, which never change at the snapshot's timestamp.
+    pub fn with_cache(mut cx: FunctionContext) -> JsResult<JsBox<Snapshot>> {
+        let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
+        let capacity = match cx.argument_opt(0) {
+            Some(capacity) => match capacity.downcast::<JsNumber, _>(&mut cx) {
+                Ok(capacity) => capacity.value(&mut cx) as usize,
+                Err(_) => DEFAULT_CAPACITY,
+            },
+            None => DEFAULT_CAPACITY,
+        };
+        let inner: Box<dyn SnapshotHandle> = Box::new(CachedSnapshot {
+            inner: client.inner.clone(),
+            values: LruCache::new(capacity.max(1)),
+        });
+        Ok(cx.boxed(Snapshot {
+            inner: Arc::new(Mutex::new(inner)),
+            codec: client.codec.clone(),
+        }))
+    }
+
     pub fn get(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let value = inner.lock().await.get(key).await;
-            send_result(queue, callback, value);
+        let request = Request::new("snapshot_get").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner
+                .lock()
+                .await
+                .get(key.into())
+                .await
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -119,15 +561,15 @@ impl Snapshot {
 
     pub fn key_exists(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let value = inner.lock().await.key_exists(key).await;
-            send_result(queue, callback, value);
+        let request = Request::new("snapshot_key_exists").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.key_exists(key.into()).await
         });
 
         Ok(cx.undefined())
@@ -136,20 +578,21 @@ impl Snapshot {
     pub fn batch_get(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
         let keys = cx.argument::<JsArray>(0)?;
-        let keys: Vec<String> = js_array_to_rust_keys(&mut cx, keys);
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner
+        let request = Request::new("snapshot_batch_get").keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            inner
                 .lock()
                 .await
                 .batch_get(keys)
                 .await
-                .map(|kvpairs| kvpairs.collect::<Vec<KvPair>>());
-            send_result(queue, callback, result);
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -157,38 +600,32 @@ impl Snapshot {
 
     pub fn scan(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
-        let start = cx.argument_opt(0).map(|start| {
-            start
-                .downcast::<JsString, _>(&mut cx)
-                .or_throw(&mut cx)
-                .expect("Start should be a string")
-                .value(&mut cx)
-                .into_bytes()
-        });
-        let end = cx.argument_opt(1).map(|end| {
-            end.downcast::<JsString, _>(&mut cx)
-                .or_throw(&mut cx)
-                .expect("End should be a string")
-                .value(&mut cx)
-                .into_bytes()
-        });
+        let start = match cx.argument_opt(0) {
+            Some(start) => Some(js_value_to_bytes(&mut cx, start)?),
+            None => None,
+        };
+        let end = match cx.argument_opt(1) {
+            Some(end) => Some(js_value_to_bytes(&mut cx, end)?),
+            None => None,
+        };
         let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
         let include_start = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(4)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(5)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
+        let request = Request::new("snapshot_scan").range(start.as_deref(), end.as_deref());
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(start, end, include_start, include_end);
 
-            let result = inner
+            inner
                 .lock()
                 .await
                 .scan(range, limit)
                 .await
-                .map(|kvpairs| kvpairs.collect::<Vec<KvPair>>());
-            send_result(queue, callback, result);
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -196,25 +633,26 @@ impl Snapshot {
 
     pub fn scan_keys(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx.this().downcast_or_throw::<JsBox<Snapshot>, _>(&mut cx)?;
-        let start = cx.argument::<JsString>(0)?.value(&mut cx).into_bytes();
-        let end = cx.argument::<JsString>(1)?.value(&mut cx).into_bytes();
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
         let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
         let include_start = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(4)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(5)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
+        let request = Request::new("snapshot_scan_keys").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(Some(start), Some(end), include_start, include_end);
 
-            let result = inner
+            inner
                 .lock()
                 .await
                 .scan_keys(range, limit)
                 .await
-                .map(|kvpairs| kvpairs.collect::<Vec<Key>>());
-            send_result(queue, callback, result);
+                .map(|keys| codec.decoded(keys))
         });
 
         Ok(cx.undefined())
@@ -222,19 +660,77 @@ impl Snapshot {
 }
 
 impl Transaction {
+    pub fn subspace(mut cx: FunctionContext) -> JsResult<JsBox<Transaction>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let prefix = argument_to_bytes(&mut cx, 0)?;
+        let inner: Box<dyn TransactionHandle> = Box::new(SubspaceTransaction {
+            inner: client.inner.clone(),
+            subspace: Subspace::new(prefix),
+        });
+        Ok(cx.boxed(Transaction {
+            inner: Arc::new(Mutex::new(inner)),
+            codec: client.codec.clone(),
+            // Keys of the subspace go in batches of their own.
+            coalescer: client
+                .coalescer
+                .as_ref()
+                .map(|coalescer| Arc::new(Coalescer::new(coalescer.coalescing()))),
+        }))
+    }
+
+    pub fn with_codec(mut cx: FunctionContext) -> JsResult<JsBox<Transaction>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let codec = cx.argument::<JsValue>(0)?;
+        let codec = client.codec.with_values(&mut cx, codec)?;
+        Ok(cx.boxed(Transaction {
+            inner: client.inner.clone(),
+            codec,
+            coalescer: client.coalescer.clone(),
+        }))
+    }
+
+    pub fn with_key_type(mut cx: FunctionContext) -> JsResult<JsBox<Transaction>> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let key_type = cx.argument::<JsString>(0)?;
+        let key_type = KeyType::from_js(&mut cx, key_type)?;
+        Ok(cx.boxed(Transaction {
+            inner: client.inner.clone(),
+            codec: client.codec.with_keys(key_type),
+            coalescer: client.coalescer.clone(),
+        }))
+    }
+
     pub fn get(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let value = inner.lock().await.get(key).await;
-            send_result(queue, callback, value);
+        let coalescer = client.coalescer.clone();
+        let request = Request::new("txn_get").key(&key);
+        spawn_request(request, queue, callback, async move {
+            let value = match coalescer {
+                Some(coalescer) => {
+                    coalescer
+                        .get("", key.into(), |keys| async move {
+                            inner.lock().await.batch_get(keys).await
+                        })
+                        .await
+                }
+                None => inner.lock().await.get(key.into()).await,
+            };
+            value.map(|value| codec.decoded(value))
         });
 
         Ok(cx.undefined())
@@ -244,15 +740,21 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let value = inner.lock().await.get_for_update(key).await;
-            send_result(queue, callback, value);
+        let request = Request::new("txn_get_for_update").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner
+                .lock()
+                .await
+                .get_for_update(key.into())
+                .await
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -262,15 +764,15 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let value = inner.lock().await.key_exists(key).await;
-            send_result(queue, callback, value);
+        let request = Request::new("txn_key_exists").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.key_exists(key.into()).await
         });
 
         Ok(cx.undefined())
@@ -281,20 +783,21 @@ impl Transaction {
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
         let keys = cx.argument::<JsArray>(0)?;
-        let keys: Vec<String> = js_array_to_rust_keys(&mut cx, keys);
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner
+        let request = Request::new("txn_batch_get").keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            inner
                 .lock()
                 .await
                 .batch_get(keys)
                 .await
-                .map(|kvpairs| kvpairs.collect::<Vec<KvPair>>());
-            send_result(queue, callback, result);
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -305,15 +808,21 @@ impl Transaction {
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
         let keys = cx.argument::<JsArray>(0)?;
-        let keys: Vec<String> = js_array_to_rust_keys(&mut cx, keys);
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.lock().await.batch_get_for_update(keys).await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_batch_get_for_update").keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            inner
+                .lock()
+                .await
+                .batch_get_for_update(keys)
+                .await
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -323,25 +832,26 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let start = cx.argument::<JsString>(0)?.value(&mut cx).into_bytes();
-        let end = cx.argument::<JsString>(1)?.value(&mut cx).into_bytes();
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
         let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
         let include_start = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(4)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(5)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
+        let request = Request::new("txn_scan").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(Some(start), Some(end), include_start, include_end);
 
-            let result = inner
+            inner
                 .lock()
                 .await
                 .scan(range, limit)
                 .await
-                .map(|kvpairs| kvpairs.collect::<Vec<KvPair>>());
-            send_result(queue, callback, result);
+                .map(|values| codec.decoded(values))
         });
 
         Ok(cx.undefined())
@@ -351,25 +861,26 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let start = cx.argument::<JsString>(0)?.value(&mut cx).into_bytes();
-        let end = cx.argument::<JsString>(1)?.value(&mut cx).into_bytes();
+        let start = argument_to_bytes(&mut cx, 0)?;
+        let end = argument_to_bytes(&mut cx, 1)?;
         let limit = cx.argument::<JsNumber>(2)?.value(&mut cx) as u32;
         let include_start = cx.argument::<JsBoolean>(3)?.value(&mut cx);
         let include_end = cx.argument::<JsBoolean>(4)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(5)?.root(&mut cx);
 
         let inner = client.inner.clone();
+        let codec = client.codec.clone();
         let queue = cx.queue();
-        RUNTIME.spawn(async move {
+        let request = Request::new("txn_scan_keys").range(Some(&start), Some(&end));
+        spawn_request(request, queue, callback, async move {
             let range = to_bound_range(Some(start), Some(end), include_start, include_end);
 
-            let result = inner
+            inner
                 .lock()
                 .await
                 .scan_keys(range, limit)
                 .await
-                .map(|kvpairs| kvpairs.collect::<Vec<Key>>());
-            send_result(queue, callback, result);
+                .map(|keys| codec.decoded(keys))
         });
 
         Ok(cx.undefined())
@@ -380,15 +891,15 @@ impl Transaction {
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
         let keys = cx.argument::<JsArray>(0)?;
-        let keys: Vec<String> = js_array_to_rust_keys(&mut cx, keys);
+        let keys = js_array_to_rust_keys(&mut cx, keys)?;
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
 
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.lock().await.lock_keys(keys).await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_lock_keys").keys(keys.len());
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.lock_keys(keys).await
         });
 
         Ok(cx.undefined())
@@ -398,15 +909,16 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
-        let value = cx.argument::<JsString>(1)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let value = cx.argument::<JsValue>(1)?;
+        let value = client.codec.encode(&mut cx, value)?;
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.lock().await.put(key, value).await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_put").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.put(key.into(), value.into()).await
         });
 
         Ok(cx.undefined())
@@ -416,15 +928,16 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
-        let value = cx.argument::<JsString>(1)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let value = cx.argument::<JsValue>(1)?;
+        let value = client.codec.encode(&mut cx, value)?;
         let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.lock().await.insert(key, value).await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_insert").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.insert(key.into(), value.into()).await
         });
 
         Ok(cx.undefined())
@@ -434,14 +947,181 @@ impl Transaction {
         let client = cx
             .this()
             .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
-        let key = cx.argument::<JsString>(0)?.value(&mut cx);
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_delete").key(&key);
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.delete(key.into()).await
+        });
+
+        Ok(cx.undefined())
+    }
+
+    /// Put the value if the key holds the expected one, locking the key to read it. Values are
+    /// compared with `Codec::equal`.
+    pub fn put_if_equals(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let expected = cx.argument::<JsValue>(1)?;
+        let expected = client.codec.encode(&mut cx, expected)?;
+        let value = cx.argument::<JsValue>(2)?;
+        let value = client.codec.encode(&mut cx, value)?;
+        let callback = cx.argument::<JsFunction>(3)?.root(&mut cx);
+        let codec = client.codec.clone();
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_put_if_equals").key(&key);
+        spawn_request(request, queue, callback, async move {
+            let mut inner = inner.lock().await;
+            match inner.get_for_update(key.clone().into()).await? {
+                Some(current) if codec.equal(&current, &expected) => {}
+                _ => return Ok(false),
+            }
+            inner.put(key.into(), value).await?;
+            Ok(true)
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn put_if_absent(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let value = cx.argument::<JsValue>(1)?;
+        let value = client.codec.encode(&mut cx, value)?;
+        let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_put_if_absent").key(&key);
+        spawn_request(request, queue, callback, async move {
+            let mut inner = inner.lock().await;
+            if inner.get_for_update(key.clone().into()).await?.is_some() {
+                return Ok(false);
+            }
+            inner.put(key.into(), value).await?;
+            Ok(true)
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn delete_if_equals(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let key = argument_to_bytes(&mut cx, 0)?;
+        let expected = cx.argument::<JsValue>(1)?;
+        let expected = client.codec.encode(&mut cx, expected)?;
+        let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+        let codec = client.codec.clone();
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_delete_if_equals").key(&key);
+        spawn_request(request, queue, callback, async move {
+            let mut inner = inner.lock().await;
+            match inner.get_for_update(key.clone().into()).await? {
+                Some(current) if codec.equal(&current, &expected) => {}
+                _ => return Ok(false),
+            }
+            inner.delete(key.into()).await?;
+            Ok(true)
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn savepoint(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let name = cx.argument::<JsString>(0)?.value(&mut cx);
         let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.lock().await.delete(key).await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_savepoint");
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.savepoint(name).await
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn rollback_to(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let name = cx.argument::<JsString>(0)?.value(&mut cx);
+        let callback = cx.argument::<JsFunction>(1)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_rollback_to");
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.rollback_to(name).await
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn pending_mutations(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let codec = client.codec.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_pending_mutations");
+        spawn_request(request, queue, callback, async move {
+            let pending = inner.lock().await.pending_mutations().await?;
+            Ok(codec.decoded(pending))
+        });
+
+        Ok(cx.undefined())
+    }
+
+    pub fn mutation_count(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_mutation_count");
+        spawn_request(request, queue, callback, async move {
+            let pending = inner.lock().await.pending_mutations().await?;
+            Ok(pending.len())
+        });
+
+        Ok(cx.undefined())
+    }
+
+    /// The total size of the keys and values the transaction will write.
+    pub fn mutation_bytes(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let client = cx
+            .this()
+            .downcast_or_throw::<JsBox<Transaction>, _>(&mut cx)?;
+        let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+        let inner = client.inner.clone();
+        let queue = cx.queue();
+
+        let request = Request::new("txn_mutation_bytes");
+        spawn_request(request, queue, callback, async move {
+            let pending = inner.lock().await.pending_mutations().await?;
+            Ok(pending.iter().map(PendingMutation::size).sum::<usize>())
         });
 
         Ok(cx.undefined())
@@ -455,9 +1135,9 @@ impl Transaction {
         let inner = client.inner.clone();
         let queue = cx.queue();
 
-        RUNTIME.spawn(async move {
-            let result = inner.lock().await.commit().await;
-            send_result(queue, callback, result);
+        let request = Request::new("txn_commit");
+        spawn_request(request, queue, callback, async move {
+            inner.lock().await.commit().await
         });
 
         Ok(cx.undefined())
@@ -0,0 +1,513 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Order-preserving encoding of tuples into keys.
+//!
+//! The encoding is the one of the FoundationDB tuple layer: packed tuples compare bytewise in
+//! the same order as their elements compare, so integers sort numerically and strings may
+//! contain any character, and the keys of every tuple starting with a prefix lie in
+//! `range(prefix)`.
+//!
+//! Every JS number is a double, so numbers are encoded as doubles, which sort numerically among
+//! themselves whether integral or not. Integers are BigInts, encoded as FoundationDB integers and
+//! decoded back into BigInts; they sort before every double.
+
+use neon::prelude::*;
+
+use crate::utils::{bytes_to_js_buffer, js_value_to_bytes};
+
+const NULL: u8 = 0x00;
+const BYTES: u8 = 0x01;
+const STRING: u8 = 0x02;
+const NESTED: u8 = 0x05;
+const NEG_BIG_INT: u8 = 0x0b;
+const INT_ZERO: u8 = 0x14;
+const POS_BIG_INT: u8 = 0x1d;
+const DOUBLE: u8 = 0x21;
+const FALSE: u8 = 0x26;
+const TRUE: u8 = 0x27;
+const ESCAPE: u8 = 0xff;
+
+#[derive(Debug)]
+enum Element {
+    Null,
+    Bytes(Vec<u8>),
+    String(String),
+    Tuple(Vec<Element>),
+    /// The big-endian magnitude has no leading zero bytes, and is empty for zero.
+    Int {
+        negative: bool,
+        magnitude: Vec<u8>,
+    },
+    Double(f64),
+    Bool(bool),
+}
+
+impl Element {
+    fn int(negative: bool, magnitude: &[u8]) -> Element {
+        let start = magnitude
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(magnitude.len());
+        let magnitude = magnitude[start..].to_vec();
+        Element::Int {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+}
+
+fn encode(element: &Element, nested: bool, out: &mut Vec<u8>) {
+    match element {
+        Element::Null if nested => out.extend_from_slice(&[NULL, ESCAPE]),
+        Element::Null => out.push(NULL),
+        Element::Bytes(bytes) => encode_bytes(BYTES, bytes, out),
+        Element::String(string) => encode_bytes(STRING, string.as_bytes(), out),
+        Element::Tuple(elements) => {
+            out.push(NESTED);
+            for element in elements {
+                encode(element, true, out);
+            }
+            out.push(NULL);
+        }
+        Element::Int {
+            negative,
+            magnitude,
+        } => {
+            let len = magnitude.len();
+            match (negative, len) {
+                (_, 0) => out.push(INT_ZERO),
+                (false, 1..=8) => out.push(INT_ZERO + len as u8),
+                (true, 1..=8) => out.push(INT_ZERO - len as u8),
+                (false, _) => out.extend_from_slice(&[POS_BIG_INT, len as u8]),
+                (true, _) => out.extend_from_slice(&[NEG_BIG_INT, len as u8 ^ 0xff]),
+            }
+            if *negative {
+                out.extend(magnitude.iter().map(|b| !b));
+            } else {
+                out.extend_from_slice(magnitude);
+            }
+        }
+        Element::Double(value) => {
+            let bits = value.to_bits();
+            let bits = if bits >> 63 == 1 {
+                !bits
+            } else {
+                bits | 1 << 63
+            };
+            out.push(DOUBLE);
+            out.extend_from_slice(&bits.to_be_bytes());
+        }
+        Element::Bool(false) => out.push(FALSE),
+        Element::Bool(true) => out.push(TRUE),
+    }
+}
+
+fn encode_bytes(code: u8, bytes: &[u8], out: &mut Vec<u8>) {
+    out.push(code);
+    for b in bytes {
+        out.push(*b);
+        if *b == NULL {
+            out.push(ESCAPE);
+        }
+    }
+    out.push(NULL);
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.bytes.len() {
+            return Err("Truncated tuple".to_owned());
+        }
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(taken)
+    }
+
+    fn decode(&mut self, nested: bool) -> Result<Element, String> {
+        let code = self.take(1)?[0];
+        match code {
+            NULL => {
+                if nested {
+                    self.take(1)?;
+                }
+                Ok(Element::Null)
+            }
+            BYTES => self.decode_bytes().map(Element::Bytes),
+            STRING => String::from_utf8(self.decode_bytes()?)
+                .map(Element::String)
+                .map_err(|err| err.to_string()),
+            NESTED => {
+                let mut elements = vec![];
+                loop {
+                    // A null not followed by the escape ends the nested tuple.
+                    if self.bytes.get(self.pos) == Some(&NULL)
+                        && self.bytes.get(self.pos + 1) != Some(&ESCAPE)
+                    {
+                        self.pos += 1;
+                        return Ok(Element::Tuple(elements));
+                    }
+                    elements.push(self.decode(true)?);
+                }
+            }
+            NEG_BIG_INT => {
+                let len = (self.take(1)?[0] ^ 0xff) as usize;
+                let magnitude: Vec<u8> = self.take(len)?.iter().map(|b| !b).collect();
+                Ok(Element::int(true, &magnitude))
+            }
+            0x0c..=0x13 => {
+                let len = (INT_ZERO - code) as usize;
+                let magnitude: Vec<u8> = self.take(len)?.iter().map(|b| !b).collect();
+                Ok(Element::int(true, &magnitude))
+            }
+            INT_ZERO..=0x1c => {
+                let len = (code - INT_ZERO) as usize;
+                Ok(Element::int(false, self.take(len)?))
+            }
+            POS_BIG_INT => {
+                let len = self.take(1)?[0] as usize;
+                Ok(Element::int(false, self.take(len)?))
+            }
+            DOUBLE => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(self.take(8)?);
+                let bits = u64::from_be_bytes(bits);
+                let bits = if bits >> 63 == 1 {
+                    bits & !(1 << 63)
+                } else {
+                    !bits
+                };
+                Ok(Element::Double(f64::from_bits(bits)))
+            }
+            FALSE => Ok(Element::Bool(false)),
+            TRUE => Ok(Element::Bool(true)),
+            code => Err(format!("Unknown tuple type code: {:#04x}", code)),
+        }
+    }
+
+    fn decode_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![];
+        loop {
+            match self.take(1)?[0] {
+                NULL if self.bytes.get(self.pos) == Some(&ESCAPE) => {
+                    self.pos += 1;
+                    bytes.push(NULL);
+                }
+                NULL => return Ok(bytes),
+                b => bytes.push(b),
+            }
+        }
+    }
+}
+
+fn unpack_elements(bytes: &[u8]) -> Result<Vec<Element>, String> {
+    let mut decoder = Decoder { bytes, pos: 0 };
+    let mut elements = vec![];
+    while decoder.pos < bytes.len() {
+        elements.push(decoder.decode(false)?);
+    }
+    Ok(elements)
+}
+
+/// Parse the decimal representation of a BigInt.
+fn parse_big_int(decimal: &str) -> Option<Element> {
+    let (negative, digits) = match decimal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, decimal),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    // Little-endian while accumulating.
+    let mut magnitude: Vec<u8> = vec![];
+    for digit in digits.chars() {
+        let mut carry = digit.to_digit(10)?;
+        for b in magnitude.iter_mut() {
+            let v = *b as u32 * 10 + carry;
+            *b = v as u8;
+            carry = v >> 8;
+        }
+        if carry > 0 {
+            magnitude.push(carry as u8);
+        }
+    }
+    // The encoded length takes a single byte.
+    if magnitude.len() > 255 {
+        return None;
+    }
+    magnitude.reverse();
+    Some(Element::int(negative, &magnitude))
+}
+
+fn format_big_int(negative: bool, magnitude: &[u8]) -> String {
+    let mut magnitude = magnitude.to_vec();
+    let mut digits = vec![];
+    while !magnitude.is_empty() {
+        let mut remainder = 0u32;
+        for b in magnitude.iter_mut() {
+            let v = remainder << 8 | *b as u32;
+            *b = (v / 10) as u8;
+            remainder = v % 10;
+        }
+        digits.push(std::char::from_digit(remainder, 10).unwrap());
+        let start = magnitude
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(magnitude.len());
+        magnitude.drain(..start);
+    }
+    if digits.is_empty() {
+        digits.push('0');
+    }
+    if negative {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+fn from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<JsValue>) -> NeonResult<Element> {
+    if value.is_a::<JsNull, _>(cx) || value.is_a::<JsUndefined, _>(cx) {
+        return Ok(Element::Null);
+    }
+    if let Ok(value) = value.downcast::<JsBoolean, _>(cx) {
+        return Ok(Element::Bool(value.value(cx)));
+    }
+    if let Ok(value) = value.downcast::<JsNumber, _>(cx) {
+        return Ok(Element::Double(value.value(cx)));
+    }
+    if let Ok(value) = value.downcast::<JsString, _>(cx) {
+        return Ok(Element::String(value.value(cx)));
+    }
+    if value.is_a::<JsBuffer, _>(cx) {
+        return js_value_to_bytes(cx, value).map(Element::Bytes);
+    }
+    if let Ok(value) = value.downcast::<JsArray, _>(cx) {
+        let values = value.to_vec(cx)?;
+        let mut elements = Vec::with_capacity(values.len());
+        for value in values {
+            elements.push(from_js(cx, value)?);
+        }
+        return Ok(Element::Tuple(elements));
+    }
+    if value.is_a::<JsObject, _>(cx) {
+        return cx.throw_type_error("Unsupported tuple element");
+    }
+    // Neon has no BigInt type, but BigInts are the only other values whose string is a number.
+    let decimal = value.to_string(cx)?.value(cx);
+    match parse_big_int(&decimal) {
+        Some(element) => Ok(element),
+        None => cx.throw_type_error("Unsupported tuple element"),
+    }
+}
+
+fn to_js<'a, C: Context<'a>>(cx: &mut C, element: Element) -> JsResult<'a, JsValue> {
+    Ok(match element {
+        Element::Null => cx.null().upcast(),
+        Element::Bytes(bytes) => bytes_to_js_buffer(cx, &bytes)?.upcast(),
+        Element::String(string) => cx.string(string).upcast(),
+        Element::Tuple(elements) => {
+            let array = JsArray::new(cx, elements.len() as u32);
+            for (i, element) in elements.into_iter().enumerate() {
+                let value = to_js(cx, element)?;
+                array.set(cx, i as u32, value)?;
+            }
+            array.upcast()
+        }
+        Element::Int {
+            negative,
+            magnitude,
+        } => {
+            let big_int = cx.global().get(cx, "BigInt")?;
+            let big_int = big_int.downcast_or_throw::<JsFunction, _>(cx)?;
+            let decimal = cx.string(format_big_int(negative, &magnitude));
+            let this = cx.undefined();
+            big_int.call(cx, this, vec![decimal])?
+        }
+        Element::Double(value) => cx.number(value).upcast(),
+        Element::Bool(value) => cx.boolean(value).upcast(),
+    })
+}
+
+fn pack_argument(cx: &mut FunctionContext) -> NeonResult<Vec<u8>> {
+    let tuple = cx.argument::<JsArray>(0)?.to_vec(cx)?;
+    let mut packed = vec![];
+    for value in tuple {
+        let element = from_js(cx, value)?;
+        encode(&element, false, &mut packed);
+    }
+    Ok(packed)
+}
+
+pub fn pack(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let packed = pack_argument(&mut cx)?;
+    bytes_to_js_buffer(&mut cx, &packed)
+}
+
+pub fn unpack(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let key = cx.argument::<JsValue>(0)?;
+    let key = js_value_to_bytes(&mut cx, key)?;
+    let elements = match unpack_elements(&key) {
+        Ok(elements) => elements,
+        Err(err) => return cx.throw_error(err),
+    };
+    let array = JsArray::new(&mut cx, elements.len() as u32);
+    for (i, element) in elements.into_iter().enumerate() {
+        let value = to_js(&mut cx, element)?;
+        array.set(&mut cx, i as u32, value)?;
+    }
+    Ok(array)
+}
+
+/// The start (inclusive) and end (exclusive) of the keys of all tuples strictly extending a
+/// prefix.
+pub fn range(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let prefix = pack_argument(&mut cx)?;
+    let mut start = prefix.clone();
+    start.push(0x00);
+    let mut end = prefix;
+    end.push(0xff);
+
+    let range = JsArray::new(&mut cx, 2);
+    let start = bytes_to_js_buffer(&mut cx, &start)?;
+    range.set(&mut cx, 0, start)?;
+    let end = bytes_to_js_buffer(&mut cx, &end)?;
+    range.set(&mut cx, 1, end)?;
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(elements: &[Element]) -> Vec<u8> {
+        let mut packed = vec![];
+        for element in elements {
+            encode(element, false, &mut packed);
+        }
+        packed
+    }
+
+    fn int(value: i64) -> Element {
+        Element::int(value < 0, &value.unsigned_abs().to_be_bytes())
+    }
+
+    fn assert_sorted(tuples: Vec<Vec<Element>>) {
+        let packed: Vec<Vec<u8>> = tuples.iter().map(|tuple| pack(tuple)).collect();
+        for (i, pair) in packed.windows(2).enumerate() {
+            assert!(
+                pair[0] < pair[1],
+                "{:?} should sort before {:?}",
+                tuples[i],
+                tuples[i + 1]
+            );
+        }
+    }
+
+    #[test]
+    fn doubles_sort_numerically() {
+        let doubles = [
+            f64::NEG_INFINITY,
+            -1e300,
+            -2.5,
+            -1.0,
+            -0.0,
+            0.0,
+            0.5,
+            1.0,
+            42.0,
+            1e300,
+            f64::INFINITY,
+        ];
+        assert_sorted(doubles.iter().map(|d| vec![Element::Double(*d)]).collect());
+    }
+
+    #[test]
+    fn ints_sort_numerically() {
+        let mut ints = vec![parse_big_int(&format!("-{}", "9".repeat(30))).unwrap()];
+        ints.extend(
+            [
+                i64::MIN,
+                -65536,
+                -256,
+                -255,
+                -1,
+                0,
+                1,
+                255,
+                256,
+                65536,
+                i64::MAX,
+            ]
+            .iter()
+            .map(|i| int(*i)),
+        );
+        ints.push(parse_big_int(&"9".repeat(30)).unwrap());
+        assert_sorted(ints.into_iter().map(|i| vec![i]).collect());
+    }
+
+    #[test]
+    fn elements_sort_by_type_then_value() {
+        assert_sorted(vec![
+            vec![],
+            vec![Element::Null],
+            vec![Element::Bytes(vec![0])],
+            vec![Element::Bytes(vec![0, 0])],
+            vec![Element::Bytes(vec![1])],
+            vec![Element::String("a".to_owned())],
+            vec![Element::String("a".to_owned()), Element::Null],
+            vec![Element::String("b".to_owned())],
+            vec![Element::Tuple(vec![Element::Null])],
+            vec![Element::Tuple(vec![Element::String("a".to_owned())])],
+            vec![int(i64::MAX)],
+            vec![Element::Double(f64::NEG_INFINITY)],
+            vec![Element::Bool(false)],
+            vec![Element::Bool(true)],
+        ]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let tuple = vec![
+            Element::Null,
+            Element::Bytes(vec![0, 1, 0xff, 0]),
+            Element::String("a\0b".to_owned()),
+            Element::Tuple(vec![Element::Null, Element::Tuple(vec![]), int(-7)]),
+            int(0),
+            int(-300),
+            parse_big_int("123456789012345678901234567890").unwrap(),
+            Element::Double(-0.0),
+            Element::Double(1.5),
+            Element::Bool(true),
+        ];
+        let packed = pack(&tuple);
+        let unpacked = unpack_elements(&packed).unwrap();
+        assert_eq!(format!("{:?}", unpacked), format!("{:?}", tuple));
+        assert_eq!(pack(&unpacked), packed);
+    }
+
+    #[test]
+    fn big_ints_format_back_to_decimal() {
+        for decimal in &["0", "-1", "255", "-65536", "123456789012345678901234567890"] {
+            match parse_big_int(decimal).unwrap() {
+                Element::Int {
+                    negative,
+                    magnitude,
+                } => assert_eq!(format_big_int(negative, &magnitude), *decimal),
+                element => panic!("{:?} is not an integer", element),
+            }
+        }
+        assert!(parse_big_int("").is_none());
+        assert!(parse_big_int("1.5").is_none());
+    }
+
+    #[test]
+    fn truncated_tuples_are_rejected() {
+        let packed = pack(&[Element::String("abc".to_owned()), int(1000)]);
+        assert!(unpack_elements(&packed[..packed.len() - 1]).is_err());
+        assert!(unpack_elements(&[0x3f]).is_err());
+    }
+}
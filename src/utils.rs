@@ -1,205 +1,246 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::future::Future;
 use std::ops::Bound;
-use std::{sync::Arc, u32};
+use std::time::{Duration, Instant};
+use std::u32;
 
 use neon::prelude::*;
 use neon::{
     context::{Context, TaskContext},
     prelude::Handle,
-    result::JsResultExt,
-    types::{JsArray, JsString, JsValue},
+    types::{JsArray, JsBuffer, JsString, JsValue},
 };
 use tikv_client::{Key, KvPair};
 
 use tikv_client::TimestampExt;
 
 use crate::{
-    error::CustomError, error::CLIENT_ERRORS, RawClient, Snapshot, Transaction, TransactionClient,
+    backend::Checksum, codec::Codec, error::CustomError, error::CLIENT_ERRORS, failpoint,
+    limits::LimitExceeded, metrics, slowlog, trace, RawClient, Snapshot, Transaction,
+    TransactionClient,
 };
 use lazy_static::lazy_static;
-use tokio::{runtime::Runtime, sync::Mutex};
+use opentelemetry::trace::FutureExt as _;
+use tokio::runtime::Runtime;
 
 lazy_static! {
     pub(crate) static ref RUNTIME: Runtime = Runtime::new().unwrap();
 }
 
-pub fn bytes_to_js_string<'a>(cx: &mut TaskContext<'a>, bytes: Vec<u8>) -> Handle<'a, JsValue> {
-    let content = std::str::from_utf8(&bytes).unwrap().to_owned();
-    cx.string(content).upcast()
+/// Values of the default codec, and keys shown in slow logs, are returned as strings when they
+/// are valid UTF-8 and as Buffers otherwise. Keys read are returned with `Codec::key`.
+pub fn bytes_to_js_value<'a, C: Context<'a>>(cx: &mut C, bytes: Vec<u8>) -> JsResult<'a, JsValue> {
+    match String::from_utf8(bytes) {
+        Ok(content) => Ok(cx.string(content).upcast()),
+        Err(err) => Ok(bytes_to_js_buffer(cx, err.as_bytes())?.upcast()),
+    }
 }
 
-// pub fn bytes_to_js_string<'a>(cx: &mut TaskContext<'a>, bytes: Vec<u8>) -> Handle<'a, JsValue> {
-//     let content = std::str::from_utf8(&bytes).unwrap().to_owned();
-//     cx.string(content).upcast()
-// }
-
-pub trait ToJS: 'static + Send {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue>;
+pub fn bytes_to_js_buffer<'a, C: Context<'a>>(cx: &mut C, bytes: &[u8]) -> JsResult<'a, JsBuffer> {
+    let mut buffer = JsBuffer::new(cx, bytes.len() as u32)?;
+    cx.borrow_mut(&mut buffer, |data| {
+        data.as_mut_slice::<u8>().copy_from_slice(bytes)
+    });
+    Ok(buffer)
 }
 
-impl ToJS for () {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.undefined().upcast()
+/// Read a key given as a string or a Buffer.
+pub fn js_value_to_bytes<'a, C: Context<'a>>(
+    cx: &mut C,
+    value: Handle<JsValue>,
+) -> NeonResult<Vec<u8>> {
+    if let Ok(value) = value.downcast::<JsString, _>(cx) {
+        return Ok(value.value(cx).into_bytes());
+    }
+    if let Ok(value) = value.downcast::<JsBuffer, _>(cx) {
+        return Ok(cx.borrow(&value, |data| data.as_slice::<u8>().to_vec()));
     }
+    cx.throw_type_error("Keys must be strings or Buffers")
 }
 
-impl ToJS for Vec<Key> {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        rust_keys_to_js_array(cx, self).upcast()
-    }
+pub fn argument_to_bytes(cx: &mut FunctionContext, i: i32) -> NeonResult<Vec<u8>> {
+    let value = cx.argument::<JsValue>(i)?;
+    js_value_to_bytes(cx, value)
 }
 
-impl ToJS for Key {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        bytes_to_js_string(cx, self.into())
+/// Read a non-negative number of milliseconds named `name`, rejecting NaN, infinities and
+/// durations too long for `Duration` with a `RangeError`.
+pub fn js_millis_to_duration<'a, C: Context<'a>>(
+    cx: &mut C,
+    name: &str,
+    ms: f64,
+) -> NeonResult<Duration> {
+    let secs = ms / 1000.0;
+    // `u64::MAX as f64` rounds up to 2^64, which is already out of range.
+    if secs.is_nan() || secs < 0.0 || secs >= u64::MAX as f64 {
+        return cx.throw_range_error(format!("Invalid {}: {}", name, ms));
     }
+    Ok(Duration::from_secs_f64(secs))
 }
 
-impl ToJS for tikv_client::Value {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        bytes_to_js_string(cx, self.into())
-    }
+pub trait ToJS: 'static + Send {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue>;
 }
 
-impl ToJS for Vec<KvPair> {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        rust_pairs_to_js_array(cx, self).upcast()
+impl ToJS for () {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.undefined().upcast())
     }
 }
 
-impl ToJS for tikv_client::RawClient {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.boxed(RawClient {
-            inner: Arc::new(self),
-        })
-        .upcast()
+impl ToJS for RawClient {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.boxed(self).upcast())
     }
 }
 
-impl ToJS for tikv_client::TransactionClient {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.boxed(TransactionClient {
-            inner: Arc::new(self),
-        })
-        .upcast()
+impl ToJS for TransactionClient {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.boxed(self).upcast())
     }
 }
 
-impl ToJS for tikv_client::Transaction {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.boxed(Transaction {
-            inner: Arc::new(Mutex::new(self)),
-        })
-        .upcast()
+impl ToJS for Transaction {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.boxed(self).upcast())
     }
 }
 
-impl ToJS for tikv_client::Snapshot {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.boxed(Snapshot {
-            inner: Arc::new(Mutex::new(self)),
-        })
-        .upcast()
+impl ToJS for Snapshot {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.boxed(self).upcast())
     }
 }
 
 impl<T: ToJS> ToJS for Option<T> {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
         match self {
-            None => cx.undefined().upcast(),
+            None => Ok(cx.undefined().upcast()),
             Some(t) => t.to_js_value(cx),
         }
     }
 }
 
 impl ToJS for tikv_client::Timestamp {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.number(self.version() as f64).upcast()
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.number(self.version() as f64).upcast())
+    }
+}
+
+/// The CRC64 is a BigInt, as it doesn't fit in a number.
+impl ToJS for Checksum {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        let checksum = cx.empty_object();
+        let big_int = cx.global().get(cx, "BigInt")?;
+        let big_int = big_int.downcast_or_throw::<JsFunction, _>(cx)?;
+        let crc64 = cx.string(self.crc64_xor.to_string());
+        let this = cx.undefined();
+        let crc64 = big_int.call(cx, this, vec![crc64])?;
+        checksum.set(cx, "crc64", crc64)?;
+        let total_kvs = cx.number(self.total_kvs as f64);
+        checksum.set(cx, "total_kvs", total_kvs)?;
+        let total_bytes = cx.number(self.total_bytes as f64);
+        checksum.set(cx, "total_bytes", total_bytes)?;
+        Ok(checksum.upcast())
     }
 }
 
 impl ToJS for bool {
-    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> Handle<'a, JsValue> {
-        cx.boolean(self).upcast()
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.boolean(self).upcast())
+    }
+}
+
+impl ToJS for usize {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        Ok(cx.number(self as f64).upcast())
+    }
+}
+
+impl ToJS for Option<u64> {
+    fn to_js_value<'a>(self, cx: &mut TaskContext<'a>) -> JsResult<'a, JsValue> {
+        match self {
+            Some(number) => Ok(cx.number(number as f64).upcast()),
+            None => Ok(cx.undefined().upcast()),
+        }
     }
 }
 
 pub fn rust_pairs_to_js_array<'a>(
     cx: &mut TaskContext<'a>,
     values: Vec<KvPair>,
-) -> Handle<'a, JsArray> {
+    codec: &Codec,
+) -> JsResult<'a, JsArray> {
     let js_array = JsArray::new(cx, values.len() as u32);
-    for (i, obj) in values.iter().enumerate() {
+    for (i, obj) in values.into_iter().enumerate() {
         let pair = JsArray::new(cx, 2);
-        let v1 = cx.string(
-            std::str::from_utf8(&Vec::from(obj.0.clone()))
-                .unwrap()
-                .to_owned(),
-        );
-        let v2 = cx.string(std::str::from_utf8(&obj.1).unwrap().to_owned());
-        pair.set(cx, 0, v1).unwrap();
-        pair.set(cx, 1, v2).unwrap();
-        js_array.set(cx, i as u32, pair).unwrap();
+        let v1 = codec.key(cx, Vec::from(obj.0))?;
+        let v2 = codec.decode(cx, obj.1)?;
+        pair.set(cx, 0, v1)?;
+        pair.set(cx, 1, v2)?;
+        js_array.set(cx, i as u32, pair)?;
     }
-    js_array
+    Ok(js_array)
 }
 
-pub fn rust_keys_to_js_array<'a>(cx: &mut TaskContext<'a>, keys: Vec<Key>) -> Handle<'a, JsArray> {
+pub fn rust_keys_to_js_array<'a>(
+    cx: &mut TaskContext<'a>,
+    keys: Vec<Key>,
+    codec: &Codec,
+) -> JsResult<'a, JsArray> {
     let js_array = JsArray::new(cx, keys.len() as u32);
     for (i, obj) in keys.into_iter().enumerate() {
-        let v1 = obj.to_js_value(cx);
-        js_array.set(cx, i as u32, v1).unwrap();
+        let v1 = codec.key(cx, obj.into())?;
+        js_array.set(cx, i as u32, v1)?;
     }
-    js_array
+    Ok(js_array)
 }
 
 pub fn js_array_to_rust_keys<'a>(
     cx: &mut FunctionContext<'a>,
     array: Handle<JsArray>,
-) -> Vec<String> {
-    let array = array.to_vec(cx).unwrap(); // TODO: #21 remove unwrap here
-    array
-        .into_iter()
-        .map(|k| {
-            k.downcast::<JsString, _>(cx)
-                .or_throw(cx)
-                .unwrap()
-                .value(cx)
-        })
-        .collect::<Vec<String>>()
+) -> NeonResult<Vec<Key>> {
+    let mut keys = vec![];
+    for key in array.to_vec(cx)? {
+        keys.push(js_value_to_bytes(cx, key)?.into());
+    }
+    Ok(keys)
 }
 
+/// Read an array of `[start, end]` pairs as ranges including their start and excluding their end.
+pub fn js_array_to_rust_ranges<'a>(
+    cx: &mut FunctionContext<'a>,
+    array: Handle<JsArray>,
+) -> NeonResult<Vec<tikv_client::BoundRange>> {
+    let mut ranges = vec![];
+    for range in array.to_vec(cx)? {
+        let range = range.downcast_or_throw::<JsArray, _>(cx)?;
+        let start = range.get(cx, 0)?;
+        let start = js_value_to_bytes(cx, start)?;
+        let end = range.get(cx, 1)?;
+        let end = js_value_to_bytes(cx, end)?;
+        ranges.push(to_bound_range(Some(start), Some(end), true, false));
+    }
+    Ok(ranges)
+}
+
+/// Read an array of `[key, value]` pairs, encoding the values with `codec`.
 pub fn js_array_to_rust_pairs<'a>(
     cx: &mut FunctionContext<'a>,
     array: Handle<JsArray>,
-) -> impl IntoIterator<Item = impl Into<KvPair>> {
-    let array = array.to_vec(cx).unwrap(); // TODO: #21 remove unwrap here
+    codec: &Codec,
+) -> NeonResult<Vec<KvPair>> {
     let mut pairs = vec![];
-    for k in array.into_iter() {
-        let pair_result = k.downcast::<JsArray, _>(cx).or_throw(cx);
-        match pair_result {
-            Ok(pair) => {
-                let args: Vec<String> = vec![0_u32, 1_u32]
-                    .into_iter()
-                    .map(|i| {
-                        pair.get(cx, i as u32)
-                            .unwrap()
-                            .downcast::<JsString, _>(cx)
-                            .or_throw(cx)
-                            .unwrap() // TODO: #21 remove unwrap here
-                            .value(cx)
-                    })
-                    .collect();
-                pairs.push(KvPair::new(
-                    args.get(0).unwrap().to_owned(),
-                    args.get(1).unwrap().to_owned(),
-                ));
-            }
-            Err(err) => println!("{}", err.to_string()),
-        }
+    for pair in array.to_vec(cx)? {
+        let pair = pair.downcast_or_throw::<JsArray, _>(cx)?;
+        let key = pair.get(cx, 0)?;
+        let key = js_value_to_bytes(cx, key)?;
+        let value = pair.get(cx, 1)?;
+        let value = codec.encode(cx, value)?;
+        pairs.push(KvPair::new(key, value));
     }
-    pairs
+    Ok(pairs)
 }
 
 pub fn to_bound_range(
@@ -229,6 +270,90 @@ pub fn to_bound_range(
     tikv_client::BoundRange::from((start_bound, end_bound))
 }
 
+/// Describes a request for failpoints, metrics, tracing and the slow log.
+pub struct Request {
+    /// Name of the exported function serving the request.
+    pub method: &'static str,
+    pub cf: Option<String>,
+    pub target: Option<Target>,
+    /// The W3C `traceparent` of the caller, if any.
+    pub trace_context: Option<String>,
+}
+
+/// The keys a request reads or writes.
+#[derive(Clone, Debug)]
+pub enum Target {
+    Key(Vec<u8>),
+    Keys(usize),
+    Ranges(usize),
+    Range(Option<Vec<u8>>, Option<Vec<u8>>),
+}
+
+impl Request {
+    pub fn new(method: &'static str) -> Request {
+        Request {
+            method,
+            cf: None,
+            target: None,
+            trace_context: trace::take_trace_context(),
+        }
+    }
+
+    pub fn with_cf(method: &'static str, cf: String) -> Request {
+        Request {
+            cf: Some(cf),
+            ..Request::new(method)
+        }
+    }
+
+    pub fn key(mut self, key: impl AsRef<[u8]>) -> Request {
+        self.target = Some(Target::Key(key.as_ref().to_vec()));
+        self
+    }
+
+    pub fn keys(mut self, count: usize) -> Request {
+        self.target = Some(Target::Keys(count));
+        self
+    }
+
+    pub fn ranges(mut self, count: usize) -> Request {
+        self.target = Some(Target::Ranges(count));
+        self
+    }
+
+    pub fn range(mut self, start: Option<&[u8]>, end: Option<&[u8]>) -> Request {
+        self.target = Some(Target::Range(
+            start.map(<[u8]>::to_vec),
+            end.map(<[u8]>::to_vec),
+        ));
+        self
+    }
+}
+
+pub fn spawn_request<T, F>(
+    request: Request,
+    queue: EventQueue,
+    callback: Root<JsFunction>,
+    future: F,
+) where
+    T: ToJS,
+    F: Future<Output = Result<T, tikv_client::Error>> + Send + 'static,
+{
+    RUNTIME.spawn(async move {
+        let context = trace::start_span(&request);
+        let start = Instant::now();
+        let result = match failpoint::eval(request.method).await {
+            Ok(()) => future.with_context(context.clone()).await,
+            Err(err) => Err(err),
+        };
+        let elapsed = start.elapsed();
+        metrics::observe(&request, elapsed, &result);
+        slowlog::observe(&request, elapsed, &result);
+        trace::end_span(&context, &result);
+        send_result(queue, callback, result);
+    });
+}
+
 pub fn send_result<T: ToJS>(
     // TODO: #18 do I have to use static lifetime here?
     queue: EventQueue,
@@ -236,11 +361,13 @@ pub fn send_result<T: ToJS>(
     result: Result<T, tikv_client::Error>,
 ) {
     queue.send(move |mut cx| {
-        let result = result.map(|op| op.to_js_value(&mut cx));
+        // Converting values may call into JS, e.g. to decode them, which can throw.
+        let result = result.map(|op| cx.try_catch(|cx| op.to_js_value(cx)));
         let callback = callback.into_inner(&mut cx);
         let this = cx.undefined();
         let args: Vec<Handle<JsValue>> = match result {
-            Ok(values) => vec![cx.null().upcast(), values],
+            Ok(Ok(values)) => vec![cx.null().upcast(), values],
+            Ok(Err(err)) => vec![err, cx.undefined().upcast()],
             Err(err) => match err {
                 err @ tikv_client::Error::OperationAfterCommitError => vec![
                     CLIENT_ERRORS
@@ -296,6 +423,14 @@ pub fn send_result<T: ToJS>(
                         ]
                     }
                 }
+                err if LimitExceeded::find(&err).is_some() => vec![
+                    CLIENT_ERRORS
+                        .transaction_limit_error
+                        .throw(&mut cx, vec![err.to_string()])
+                        .unwrap()
+                        .upcast(),
+                    cx.undefined().upcast(),
+                ],
                 _ => vec![
                     cx.error(err.to_string()).unwrap().upcast(),
                     cx.undefined().upcast(),
@@ -0,0 +1,51 @@
+const assert = require("assert");
+const { WriteConflictError, AlreadyExistError } = require("../dist/error");
+const tikv = require("../dist/asynchronous");
+
+async function raw() {
+  const client = await new tikv.RawClient("mock://test-raw");
+  await client.put("k1", "v1", "default");
+  assert.equal(await client.get("k1", "default"), "v1");
+  assert.equal(await client.get("k1", "write"), undefined);
+
+  await client.put_with_ttl("session", "s", 60, "default");
+  assert.equal(await client.get_key_ttl("session", "default"), 60);
+  assert.equal(await client.get_key_ttl("k1", "default"), 0);
+  assert.equal(await client.get_key_ttl("missing", "default"), undefined);
+
+  await client.delete("k1", "default");
+  assert.equal(await client.get("k1", "default"), undefined);
+}
+
+async function transactions() {
+  const client = await new tikv.TransactionClient("mock://test-txn");
+  const txn1 = await client.begin(false);
+  const txn2 = await client.begin(false);
+  await txn1.put("k1", "v1");
+  await txn2.put("k1", "v2");
+  await txn1.commit();
+  await assert.rejects(txn2.commit(), WriteConflictError);
+
+  const insert = await client.begin(false);
+  await insert.insert("k1", "v3");
+  await assert.rejects(insert.commit(), AlreadyExistError);
+
+  const locking = await client.begin(true);
+  const blocked = await client.begin(true);
+  await locking.get_for_update("k2");
+  await assert.rejects(blocked.put("k2", "v"), WriteConflictError);
+  await locking.put("k2", "v2");
+  await locking.commit();
+
+  const snapshot = await client.snapshot(await client.current_timestamp(), false);
+  assert.equal(await snapshot.get("k1"), "v1");
+  assert.equal(await snapshot.get("k2"), "v2");
+}
+
+(async () => {
+  await raw();
+  await transactions();
+})().catch((err) => {
+  console.error(err);
+  process.exit(1);
+});
@@ -3,6 +3,86 @@
 "use strict";
 
 const { promisify } = require("util");
+const { EventEmitter } = require("events");
+
+export type Key = string | Buffer;
+export type Codec = "string" | "buffer" | "json" | "msgpack" | { encode(value: any): Key; decode(value: Buffer): any };
+export type KeyType = "string" | "buffer";
+export type CompressionOptions = {
+  algorithm: "zstd" | "lz4" | "snappy";
+  level?: number;
+  min_size?: number;
+  dictionary?: Buffer;
+};
+export type EncryptionOptions = {
+  key_id: string;
+  key_provider: (key_id: string) => Buffer;
+  allow_plaintext?: boolean;
+};
+export type CoalescingOptions = {
+  window_ms?: number;
+  max_batch?: number;
+};
+export type CacheOptions = {
+  capacity?: number;
+  ttl_ms?: number;
+};
+export type TransactionLimits = {
+  max_keys?: number;
+  max_bytes?: number;
+  max_value_size?: number;
+  max_duration_ms?: number;
+};
+export type Checksum = {
+  crc64: bigint;
+  total_kvs: number;
+  total_bytes: number;
+};
+export type CoprocessorResponse = {
+  data: Buffer;
+  ranges: [Key, Key][];
+};
+export type Peer = {
+  id: number;
+  store_id: number;
+  is_learner: boolean;
+};
+export type Region = {
+  id: number;
+  start_key: Key;
+  end_key: Key;
+  epoch: { conf_ver: number; version: number };
+  leader_store_id: number | null;
+  peers: Peer[];
+};
+export type Store = {
+  id: number;
+  address: string;
+  state: string;
+};
+export type LockInfo = {
+  key: Key;
+  primary: Key;
+  start_ts: number;
+  ttl: number;
+};
+export type GcOptions = {
+  interval_ms?: number;
+  life_time_ms?: number;
+};
+export type GcRun = {
+  safepoint: number | null;
+  oldest_active_ts: number | null;
+  updated: boolean;
+  error?: string;
+  message?: string;
+  elapsed_ms: number;
+};
+export type PendingMutation = {
+  type: "put" | "insert" | "delete" | "lock";
+  key: Key;
+  value?: any;
+};
 // @ts-ignore
 const inner = require("../../index.node");
 const {
@@ -11,31 +91,51 @@ const {
   WriteConflictError,
   AlreadyExistError,
   DeadlockError,
+  TransactionLimitError,
 } = require("../error");
 inner.init(
   OperationAfterCommitError,
   UndertminedError,
   WriteConflictError,
   AlreadyExistError,
-  DeadlockError
+  DeadlockError,
+  TransactionLimitError
 );
 
 const connect_async = promisify(inner.raw_connect);
 const get_async = promisify(inner.raw_get);
 const put_async = promisify(inner.raw_put);
+const put_with_ttl_async = promisify(inner.raw_put_with_ttl);
+const get_key_ttl_async = promisify(inner.raw_get_key_ttl);
 const delete_async = promisify(inner.raw_delete);
 const batch_get_async = promisify(inner.raw_batch_get);
 const batch_put_async = promisify(inner.raw_batch_put);
 const batch_delete_async = promisify(inner.raw_batch_delete);
 const delete_range_async = promisify(inner.raw_delete_range);
+const checksum_async = promisify(inner.raw_checksum);
+const coprocessor_async = promisify(inner.raw_coprocessor);
+const locate_key_async = promisify(inner.raw_locate_key);
+const regions_async = promisify(inner.raw_regions);
+const stores_async = promisify(inner.raw_stores);
 const scan_async = promisify(inner.raw_scan);
 const scan_keys_async = promisify(inner.raw_scan_keys);
+const batch_scan_async = promisify(inner.raw_batch_scan);
+const batch_scan_keys_async = promisify(inner.raw_batch_scan_keys);
+const train_dictionary_async = promisify(inner.raw_train_dictionary);
 
 const txn_connect_async = promisify(inner.txn_connect);
 const txn_begin_async = promisify(inner.txn_begin);
+const txn_begin_read_only_async = promisify(inner.txn_begin_read_only);
 const txn_snapshot_async = promisify(inner.txn_snapshot);
+const txn_snapshot_stale_async = promisify(inner.txn_snapshot_stale);
+const txn_snapshot_at_time_async = promisify(inner.txn_snapshot_at_time);
 const txn_current_timestamp_async = promisify(inner.txn_current_timestamp);
 const txn_gc_async = promisify(inner.txn_gc);
+const txn_scan_locks_async = promisify(inner.txn_scan_locks);
+const txn_cleanup_locks_async = promisify(inner.txn_cleanup_locks);
+const txn_locate_key_async = promisify(inner.txn_locate_key);
+const txn_regions_async = promisify(inner.txn_regions);
+const txn_stores_async = promisify(inner.txn_stores);
 const txn_get_async = promisify(inner.txn_get);
 const txn_get_for_update_async = promisify(inner.txn_get_for_update);
 const txn_key_exists_async = promisify(inner.txn_key_exists);
@@ -49,6 +149,14 @@ const txn_lock_keys_async = promisify(inner.txn_lock_keys);
 const txn_put_async = promisify(inner.txn_put);
 const txn_insert_async = promisify(inner.txn_insert);
 const txn_delete_async = promisify(inner.txn_delete);
+const txn_put_if_equals_async = promisify(inner.txn_put_if_equals);
+const txn_put_if_absent_async = promisify(inner.txn_put_if_absent);
+const txn_delete_if_equals_async = promisify(inner.txn_delete_if_equals);
+const txn_savepoint_async = promisify(inner.txn_savepoint);
+const txn_rollback_to_async = promisify(inner.txn_rollback_to);
+const txn_pending_mutations_async = promisify(inner.txn_pending_mutations);
+const txn_mutation_count_async = promisify(inner.txn_mutation_count);
+const txn_mutation_bytes_async = promisify(inner.txn_mutation_bytes);
 const txn_commit_async = promisify(inner.txn_commit);
 
 const snapshot_get_async = promisify(inner.snapshot_get);
@@ -57,6 +165,15 @@ const snapshot_batch_get_async = promisify(inner.snapshot_batch_get);
 const snapshot_scan_async = promisify(inner.snapshot_scan);
 const snapshot_scan_keys_async = promisify(inner.snapshot_scan_keys);
 
+function traced<T>(traceparent: string | undefined, request: () => T): T {
+  inner.set_trace_context(traceparent);
+  try {
+    return request();
+  } finally {
+    inner.set_trace_context(undefined);
+  }
+}
+
 export class RawClient {
   boxed: any;
   constructor(pd_endpoint: string) {
@@ -67,84 +184,225 @@ export class RawClient {
     })();
   }
 
-  get(key: string, cf: string) {
-    return get_async.call(this.boxed, key, cf);
+  subspace(prefix: Key): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_subspace.call(this.boxed, prefix);
+    return client;
+  }
+
+  with_codec(codec: Codec): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_codec.call(this.boxed, codec);
+    return client;
+  }
+
+  with_key_type(key_type: KeyType): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_key_type.call(this.boxed, key_type);
+    return client;
+  }
+
+  with_compression(options: CompressionOptions): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_compression.call(this.boxed, options);
+    return client;
+  }
+
+  with_encryption(options: EncryptionOptions): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_encryption.call(this.boxed, options);
+    return client;
   }
 
-  put(key: string, value: string, cf: string) {
-    return put_async.call(this.boxed, key, value, cf);
+  with_coalescing(options: CoalescingOptions = {}): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_coalescing.call(this.boxed, options);
+    return client;
+  }
+
+  with_cache(options: CacheOptions = {}): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_cache.call(this.boxed, options);
+    return client;
+  }
+
+  train_dictionary(keys: Key[], max_size: number, cf: string, traceparent?: string) {
+    return traced(traceparent, () => train_dictionary_async.call(this.boxed, keys, max_size, cf));
+  }
+
+  get(key: Key, cf: string, traceparent?: string) {
+    return traced(traceparent, () => get_async.call(this.boxed, key, cf));
+  }
+
+  put(key: Key, value: any, cf: string, traceparent?: string) {
+    return traced(traceparent, () => put_async.call(this.boxed, key, value, cf));
+  }
+
+  put_with_ttl(
+    key: Key,
+    value: any,
+    ttl_secs: number,
+    cf: string,
+    traceparent?: string
+  ) {
+    return traced(traceparent, () =>
+      put_with_ttl_async.call(this.boxed, key, value, ttl_secs, cf)
+    );
   }
 
-  delete(key: string, cf: string) {
-    return delete_async.call(this.boxed, key, cf);
+  get_key_ttl(key: Key, cf: string, traceparent?: string) {
+    return traced(traceparent, () => get_key_ttl_async.call(this.boxed, key, cf));
   }
 
-  batch_get(keys: string[], cf: string) {
-    return batch_get_async.call(this.boxed, keys, cf);
+  delete(key: Key, cf: string, traceparent?: string) {
+    return traced(traceparent, () => delete_async.call(this.boxed, key, cf));
   }
 
-  batch_put(kv_pairs: string[], cf: string) {
-    return batch_put_async.call(this.boxed, kv_pairs, cf);
+  batch_get(keys: Key[], cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_get_async.call(this.boxed, keys, cf));
   }
 
-  batch_delete(keys: any, cf: any) {
-    return batch_delete_async.call(this.boxed, keys, cf);
+  batch_put(kv_pairs: string[], cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_put_async.call(this.boxed, kv_pairs, cf));
+  }
+
+  batch_delete(keys: Key[], cf: any, traceparent?: string) {
+    return traced(traceparent, () => batch_delete_async.call(this.boxed, keys, cf));
   }
 
   scan(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     limit: number,
     include_start: boolean,
     include_end: boolean,
-    cf: string
+    cf: string,
+    traceparent?: string
   ) {
-    return scan_async.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end,
-      cf
+    return traced(traceparent, () =>
+      scan_async.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end,
+        cf
+      )
     );
   }
 
   scan_keys(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     limit: number,
     include_start: boolean,
     include_end: boolean,
-    cf: string
+    cf: string,
+    traceparent?: string
   ) {
-    return scan_keys_async.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end,
-      cf
+    return traced(traceparent, () =>
+      scan_keys_async.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end,
+        cf
+      )
+    );
+  }
+
+  batch_scan(
+    ranges: [Key, Key][],
+    each_limit: number,
+    cf: string,
+    traceparent?: string
+  ) {
+    return traced(traceparent, () =>
+      batch_scan_async.call(this.boxed, ranges, each_limit, cf)
+    );
+  }
+
+  batch_scan_keys(
+    ranges: [Key, Key][],
+    each_limit: number,
+    cf: string,
+    traceparent?: string
+  ) {
+    return traced(traceparent, () =>
+      batch_scan_keys_async.call(this.boxed, ranges, each_limit, cf)
     );
   }
 
   delete_range(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     include_start: boolean,
     include_end: boolean,
-    cf: string
+    cf: string,
+    traceparent?: string
   ) {
-    return delete_range_async.call(
-      this.boxed,
-      start,
-      end,
-      include_start,
-      include_end,
-      cf
+    return traced(traceparent, () =>
+      delete_range_async.call(
+        this.boxed,
+        start,
+        end,
+        include_start,
+        include_end,
+        cf
+      )
     );
   }
+
+  checksum(
+    start: Key,
+    end: Key,
+    include_start: boolean,
+    include_end: boolean,
+    cf: string,
+    traceparent?: string
+  ): Promise<Checksum> {
+    return traced(traceparent, () =>
+      checksum_async.call(
+        this.boxed,
+        start,
+        end,
+        include_start,
+        include_end,
+        cf
+      )
+    );
+  }
+
+  coprocessor(
+    name: string,
+    version: string,
+    ranges: [Key, Key][],
+    request: Buffer,
+    traceparent?: string
+  ): Promise<CoprocessorResponse[]> {
+    return traced(traceparent, () =>
+      coprocessor_async.call(this.boxed, name, version, ranges, request)
+    );
+  }
+
+  locate_key(key: Key, traceparent?: string): Promise<Region> {
+    return traced(traceparent, () =>
+      locate_key_async.call(this.boxed, key)
+    );
+  }
+
+  regions(start: Key, end: Key, traceparent?: string): Promise<Region[]> {
+    return traced(traceparent, () =>
+      regions_async.call(this.boxed, start, end)
+    );
+  }
+
+  stores(traceparent?: string): Promise<Store[]> {
+    return traced(traceparent, () => stores_async.call(this.boxed));
+  }
 }
 
 export class Transaction {
@@ -153,78 +411,147 @@ export class Transaction {
     this.boxed = boxed;
   }
 
-  get(key: string) {
-    return txn_get_async.call(this.boxed, key);
+  subspace(prefix: Key): Transaction {
+    return new Transaction(inner.txn_subspace.call(this.boxed, prefix));
+  }
+
+  with_codec(codec: Codec): Transaction {
+    return new Transaction(inner.txn_with_codec.call(this.boxed, codec));
+  }
+
+  with_key_type(key_type: KeyType): Transaction {
+    return new Transaction(inner.txn_with_key_type.call(this.boxed, key_type));
+  }
+
+  get(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_get_async.call(this.boxed, key));
+  }
+
+  get_for_update(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_get_for_update_async.call(this.boxed, key));
+  }
+
+  put(key: Key, value: any, traceparent?: string) {
+    return traced(traceparent, () => txn_put_async.call(this.boxed, key, value));
+  }
+
+  insert(key: Key, value: any, traceparent?: string) {
+    return traced(traceparent, () => txn_insert_async.call(this.boxed, key, value));
+  }
+
+  delete(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_delete_async.call(this.boxed, key));
+  }
+
+  put_if_equals(
+    key: Key,
+    expected: any,
+    value: any,
+    traceparent?: string
+  ): Promise<boolean> {
+    return traced(traceparent, () =>
+      txn_put_if_equals_async.call(this.boxed, key, expected, value)
+    );
+  }
+
+  put_if_absent(key: Key, value: any, traceparent?: string): Promise<boolean> {
+    return traced(traceparent, () =>
+      txn_put_if_absent_async.call(this.boxed, key, value)
+    );
+  }
+
+  delete_if_equals(
+    key: Key,
+    expected: any,
+    traceparent?: string
+  ): Promise<boolean> {
+    return traced(traceparent, () =>
+      txn_delete_if_equals_async.call(this.boxed, key, expected)
+    );
   }
 
-  get_for_update(key: string) {
-    return txn_get_for_update_async.call(this.boxed, key);
+  savepoint(name: string, traceparent?: string) {
+    return traced(traceparent, () => txn_savepoint_async.call(this.boxed, name));
   }
 
-  put(key: string, value: string) {
-    return txn_put_async.call(this.boxed, key, value);
+  rollback_to(name: string, traceparent?: string) {
+    return traced(traceparent, () =>
+      txn_rollback_to_async.call(this.boxed, name)
+    );
   }
 
-  insert(key: string, value: string) {
-    return txn_insert_async.call(this.boxed, key, value);
+  pending_mutations(traceparent?: string): Promise<PendingMutation[]> {
+    return traced(traceparent, () =>
+      txn_pending_mutations_async.call(this.boxed)
+    );
   }
 
-  delete(key: string) {
-    return txn_delete_async.call(this.boxed, key);
+  mutation_count(traceparent?: string): Promise<number> {
+    return traced(traceparent, () => txn_mutation_count_async.call(this.boxed));
   }
 
-  commit() {
-    return txn_commit_async.call(this.boxed);
+  mutation_bytes(traceparent?: string): Promise<number> {
+    return traced(traceparent, () => txn_mutation_bytes_async.call(this.boxed));
   }
 
-  key_exists(key: string) {
-    return txn_key_exists_async.call(this.boxed, key);
+  commit(traceparent?: string) {
+    return traced(traceparent, () => txn_commit_async.call(this.boxed));
   }
 
-  batch_get(keys: string[]) {
-    return txn_batch_get_async.call(this.boxed, keys);
+  key_exists(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_key_exists_async.call(this.boxed, key));
   }
 
-  batch_get_for_update(keys: string[]) {
-    return txn_batch_get_for_update_async.call(this.boxed, keys);
+  batch_get(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => txn_batch_get_async.call(this.boxed, keys));
+  }
+
+  batch_get_for_update(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => txn_batch_get_for_update_async.call(this.boxed, keys));
   }
 
   scan(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     limit: number,
     include_start: boolean,
-    include_end: boolean
+    include_end: boolean,
+    traceparent?: string
   ) {
-    return txn_scan_async.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+    return traced(traceparent, () =>
+      txn_scan_async.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 
   scan_keys(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     limit: number,
     include_start: boolean,
-    include_end: boolean
+    include_end: boolean,
+    traceparent?: string
   ) {
-    return txn_scan_keys_async.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+    return traced(traceparent, () =>
+      txn_scan_keys_async.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 
-  lock_keys(keys: string[]) {
-    return txn_lock_keys_async.call(this.boxed, keys);
+  lock_keys(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => txn_lock_keys_async.call(this.boxed, keys));
   }
 }
 
@@ -234,48 +561,77 @@ export class Snapshot {
     this.boxed = boxed;
   }
 
-  get(key: string) {
-    return snapshot_get_async.call(this.boxed, key);
+  subspace(prefix: Key): Snapshot {
+    return new Snapshot(inner.snapshot_subspace.call(this.boxed, prefix));
   }
-  key_exists(key: string) {
-    return snapshot_key_exists_async.call(this.boxed, key);
+
+  with_codec(codec: Codec): Snapshot {
+    return new Snapshot(inner.snapshot_with_codec.call(this.boxed, codec));
   }
-  batch_get(keys: string[]) {
-    return snapshot_batch_get_async.call(this.boxed, keys);
+
+  with_key_type(key_type: KeyType): Snapshot {
+    return new Snapshot(inner.snapshot_with_key_type.call(this.boxed, key_type));
+  }
+
+  with_cache(capacity?: number): Snapshot {
+    return new Snapshot(inner.snapshot_with_cache.call(this.boxed, capacity));
+  }
+
+  get(key: Key, traceparent?: string) {
+    return traced(traceparent, () => snapshot_get_async.call(this.boxed, key));
+  }
+  key_exists(key: Key, traceparent?: string) {
+    return traced(traceparent, () => snapshot_key_exists_async.call(this.boxed, key));
+  }
+  batch_get(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => snapshot_batch_get_async.call(this.boxed, keys));
   }
   scan(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     limit: number,
     include_start: boolean,
-    include_end: boolean
+    include_end: boolean,
+    traceparent?: string
   ) {
-    return snapshot_scan_async.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+    return traced(traceparent, () =>
+      snapshot_scan_async.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
   scan_keys(
-    start: string,
-    end: string,
+    start: Key,
+    end: Key,
     limit: number,
     include_start: boolean,
-    include_end: boolean
+    include_end: boolean,
+    traceparent?: string
   ) {
-    return snapshot_scan_keys_async.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+    return traced(traceparent, () =>
+      snapshot_scan_keys_async.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 }
+export class GcScheduler extends EventEmitter {
+  boxed: any;
+
+  stop() {
+    inner.gc_scheduler_stop.call(this.boxed);
+  }
+}
 export class TransactionClient {
   boxed: any;
   constructor(pd_endpoint: string) {
@@ -286,29 +642,246 @@ export class TransactionClient {
     })();
   }
 
-  begin(pessimistic: boolean) {
+  with_codec(codec: Codec): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_codec.call(this.boxed, codec);
+    return client;
+  }
+
+  with_key_type(key_type: KeyType): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_key_type.call(this.boxed, key_type);
+    return client;
+  }
+
+  with_compression(options: CompressionOptions): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_compression.call(this.boxed, options);
+    return client;
+  }
+
+  with_encryption(options: EncryptionOptions): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_encryption.call(this.boxed, options);
+    return client;
+  }
+
+  with_coalescing(options: CoalescingOptions = {}): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_coalescing.call(this.boxed, options);
+    return client;
+  }
+
+  with_limits(limits: TransactionLimits): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_limits.call(this.boxed, limits);
+    return client;
+  }
+
+  begin(pessimistic: boolean, traceparent?: string) {
+    return (async () => {
+      const boxed = await traced(traceparent, () =>
+        txn_begin_async.call(this.boxed, pessimistic)
+      );
+      return new Transaction(boxed);
+    })();
+  }
+
+  begin_read_only(traceparent?: string) {
     return (async () => {
-      const boxed = await txn_begin_async.call(this.boxed, pessimistic);
+      const boxed = await traced(traceparent, () =>
+        txn_begin_read_only_async.call(this.boxed)
+      );
       return new Transaction(boxed);
     })();
   }
 
-  snapshot(timestamp: number, pessimistic: boolean) {
+  snapshot(
+    timestamp: number,
+    pessimistic: boolean,
+    traceparent?: string,
+    follower_read?: boolean
+  ) {
     return (async () => {
-      const boxed = await txn_snapshot_async.call(
-        this.boxed,
-        timestamp,
-        pessimistic
+      const boxed = await traced(traceparent, () =>
+        txn_snapshot_async.call(
+          this.boxed,
+          timestamp,
+          pessimistic,
+          follower_read === true
+        )
+      );
+      return new Snapshot(boxed);
+    })();
+  }
+
+  snapshot_stale(
+    seconds: number,
+    pessimistic: boolean,
+    traceparent?: string,
+    follower_read?: boolean
+  ) {
+    return (async () => {
+      const boxed = await traced(traceparent, () =>
+        txn_snapshot_stale_async.call(
+          this.boxed,
+          seconds,
+          pessimistic,
+          follower_read === true
+        )
+      );
+      return new Snapshot(boxed);
+    })();
+  }
+
+  snapshot_at(
+    date: Date,
+    pessimistic: boolean,
+    traceparent?: string,
+    follower_read?: boolean
+  ) {
+    return (async () => {
+      const boxed = await traced(traceparent, () =>
+        txn_snapshot_at_time_async.call(
+          this.boxed,
+          date.getTime(),
+          pessimistic,
+          follower_read === true
+        )
       );
       return new Snapshot(boxed);
     })();
   }
 
-  current_timestamp() {
-    return txn_current_timestamp_async.call(this.boxed);
+  current_timestamp(traceparent?: string) {
+    return traced(traceparent, () => txn_current_timestamp_async.call(this.boxed));
+  }
+
+  gc(safepoint: number, traceparent?: string) {
+    return traced(traceparent, () => txn_gc_async.call(this.boxed, safepoint));
+  }
+
+  start_gc(options: GcOptions = {}): GcScheduler {
+    const scheduler = new GcScheduler();
+    scheduler.boxed = inner.txn_start_gc.call(
+      this.boxed,
+      options,
+      (run: GcRun) => scheduler.emit("run", run)
+    );
+    return scheduler;
+  }
+
+  scan_locks(
+    start: Key,
+    end: Key,
+    limit: number,
+    traceparent?: string
+  ): Promise<LockInfo[]> {
+    return traced(traceparent, () =>
+      txn_scan_locks_async.call(this.boxed, start, end, limit)
+    );
+  }
+
+  cleanup_locks(
+    start: Key,
+    end: Key,
+    safepoint: number,
+    dry_run: boolean = false,
+    traceparent?: string
+  ): Promise<LockInfo[]> {
+    return traced(traceparent, () =>
+      txn_cleanup_locks_async.call(this.boxed, start, end, safepoint, dry_run)
+    );
+  }
+
+  locate_key(key: Key, traceparent?: string): Promise<Region> {
+    return traced(traceparent, () =>
+      txn_locate_key_async.call(this.boxed, key)
+    );
+  }
+
+  regions(start: Key, end: Key, traceparent?: string): Promise<Region[]> {
+    return traced(traceparent, () =>
+      txn_regions_async.call(this.boxed, start, end)
+    );
+  }
+
+  stores(traceparent?: string): Promise<Store[]> {
+    return traced(traceparent, () => txn_stores_async.call(this.boxed));
+  }
+}
+
+export function set_logger(
+  logger:
+    | ((record: {
+        level: string;
+        target: string;
+        message: string;
+        fields: { [key: string]: string };
+      }) => void)
+    | null,
+  level: string,
+  max_per_second?: number
+) {
+  inner.set_logger(logger, level, max_per_second);
+}
+
+export function get_metrics(): string {
+  return inner.get_metrics();
+}
+
+export function configure_failpoint(
+  name: string,
+  options: { error?: string; delay_ms?: number; count?: number }
+) {
+  inner.failpoint_configure(name, options);
+}
+
+export function remove_failpoint(name: string) {
+  inner.failpoint_remove(name);
+}
+
+export function configure_tracing(options: {
+  exporter: "otlp" | "file" | "none";
+  endpoint?: string;
+  path?: string;
+  service_name?: string;
+}) {
+  inner.configure_tracing(options);
+}
+
+export const slow_log = new EventEmitter();
+
+export function set_slow_log_threshold(threshold_ms: number | null) {
+  if (threshold_ms === null) {
+    inner.set_slow_log(null);
+  } else {
+    inner.set_slow_log(
+      (record: any) => slow_log.emit("slow", record),
+      threshold_ms
+    );
+  }
+}
+
+export type TupleElement =
+  | null
+  | boolean
+  | number
+  | bigint
+  | string
+  | Buffer
+  | TupleElement[];
+
+export class Tuple {
+  static pack(tuple: TupleElement[]): Buffer {
+    return inner.tuple_pack(tuple);
+  }
+
+  static unpack(key: Key): TupleElement[] {
+    return inner.tuple_unpack(key);
   }
 
-  gc(safepoint: number) {
-    return txn_gc_async.call(this.boxed, safepoint);
+  static range(prefix: TupleElement[]): [Buffer, Buffer] {
+    return inner.tuple_range(prefix);
   }
 }
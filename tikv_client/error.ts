@@ -51,3 +51,13 @@ export class DeadlockError extends Error {
     super(message);
   }
 }
+
+export class TransactionLimitError extends Error {
+  /**
+   * @class TransactionLimitError
+   * @param { string } message - the error message
+   */
+  constructor(message: string) {
+    super(message);
+  }
+}
@@ -4,31 +4,206 @@
 
 // @ts-ignore
 const inner = require("../index.node");
-import { OperationAfterCommitError, UndertminedError, WriteConflictError, AlreadyExistError, DeadlockError } from "./error";
+import {
+  OperationAfterCommitError,
+  UndertminedError,
+  WriteConflictError,
+  AlreadyExistError,
+  DeadlockError,
+  TransactionLimitError,
+} from "./error";
 inner.init(
   OperationAfterCommitError,
   UndertminedError,
   WriteConflictError,
   AlreadyExistError,
-  DeadlockError
+  DeadlockError,
+  TransactionLimitError
 );
 
+import { EventEmitter } from "events";
+
+/** Keys are passed as strings or Buffers, and returned as strings unless read with `with_key_type("buffer")`. */
+export type Key = string | Buffer;
+
+/**
+ * How values are converted to and from the bytes stored in TiKV: `"string"` (the default) stores
+ * strings as UTF-8, `"buffer"` stores Buffers as they are, `"json"` and `"msgpack"` serialize any
+ * JSON-like value, and a custom codec gives a pair of functions.
+ */
+export type Codec = "string" | "buffer" | "json" | "msgpack" | { encode(value: any): Key; decode(value: Buffer): any };
+
+/**
+ * How keys read are returned: as strings (the default), failing on keys which are not valid
+ * UTF-8, or as Buffers.
+ */
+export type KeyType = "string" | "buffer";
+
+/**
+ * Values of at least `min_size` bytes (64 by default) are compressed with `algorithm`. `level`
+ * applies to zstd, which can also use a `dictionary` from `RawClient.train_dictionary`.
+ */
+export type CompressionOptions = {
+  algorithm: "zstd" | "lz4" | "snappy";
+  level?: number;
+  min_size?: number;
+  dictionary?: Buffer;
+};
+
+/**
+ * Each value is encrypted with a data key of its own, wrapped by the key named `key_id`.
+ * `key_provider` returns the 32 byte key for a key ID, and is asked for older keys when reading
+ * values encrypted before a rotation. Reading a value which isn't encrypted fails, unless
+ * `allow_plaintext` is set while migrating existing data to encryption.
+ */
+export type EncryptionOptions = {
+  key_id: string;
+  key_provider: (key_id: string) => Buffer;
+  allow_plaintext?: boolean;
+};
+
+/**
+ * Gets issued within `window_ms` (1 by default) of the first one are sent as a single batch get
+ * of at most `max_batch` (1024 by default) distinct keys.
+ */
+export type CoalescingOptions = {
+  window_ms?: number;
+  max_batch?: number;
+};
+
+/**
+ * Up to `capacity` (10000 by default) values are cached. Without `ttl_ms` they are kept until
+ * evicted or written through the caching client.
+ */
+export type CacheOptions = {
+  capacity?: number;
+  ttl_ms?: number;
+};
+
+/**
+ * Limits on each transaction: the keys it writes, the total size of their keys and values, the
+ * size of any value, and the time since it began. Sizes are in bytes, as encoded by the codec.
+ * Unset limits are not checked.
+ */
+export type TransactionLimits = {
+  max_keys?: number;
+  max_bytes?: number;
+  max_value_size?: number;
+  max_duration_ms?: number;
+};
+
+/** The XOR of the CRC64 of each key followed by its value, and the number and size of the pairs. */
+export type Checksum = {
+  crc64: bigint;
+  total_kvs: number;
+  total_bytes: number;
+};
+
+/** The response of the coprocessor of a region, and the parts of the requested ranges in it. */
+export type CoprocessorResponse = {
+  data: Buffer;
+  ranges: [Key, Key][];
+};
+
+/** A replica of a region, on the store with `store_id`. */
+export type Peer = {
+  id: number;
+  store_id: number;
+  is_learner: boolean;
+};
+
+/**
+ * A region holding the keys from `start_key` up to `end_key`, which is empty for the last
+ * region. The epoch changes when the region splits, merges or moves.
+ */
+export type Region = {
+  id: number;
+  start_key: Key;
+  end_key: Key;
+  epoch: { conf_ver: number; version: number };
+  leader_store_id: number | null;
+  peers: Peer[];
+};
+
+/** A TiKV store and its state, like "Up", "Offline" or "Tombstone". */
+export type Store = {
+  id: number;
+  address: string;
+  state: string;
+};
+
+/** A lock left by the transaction started at `start_ts`, expiring `ttl` milliseconds after it. */
+export type LockInfo = {
+  key: Key;
+  primary: Key;
+  start_ts: number;
+  ttl: number;
+};
+
+/**
+ * GC runs every `interval_ms` (10 minutes by default), keeping the versions of the last
+ * `life_time_ms` (10 minutes by default).
+ */
+export type GcOptions = {
+  interval_ms?: number;
+  life_time_ms?: number;
+};
+
+/**
+ * The outcome of a scheduled GC run. `safepoint` is null if no timestamp could be had, and
+ * `oldest_active_ts` null if no transaction or snapshot of this process was active. `updated` is false if PD
+ * kept a newer safepoint, or if the run failed with an `error` kind and `message`.
+ */
+export type GcRun = {
+  safepoint: number | null;
+  oldest_active_ts: number | null;
+  updated: boolean;
+  error?: string;
+  message?: string;
+  elapsed_ms: number;
+};
+
+/** A write a transaction will send at commit; `value` is set for puts and inserts. */
+export type PendingMutation = {
+  type: "put" | "insert" | "delete" | "lock";
+  key: Key;
+  value?: any;
+};
+
 var deasync = require("deasync");
 const raw_connect_sync = deasync(inner.raw_connect);
 const get_sync = deasync(inner.raw_get);
 const put_sync = deasync(inner.raw_put);
+const put_with_ttl_sync = deasync(inner.raw_put_with_ttl);
+const get_key_ttl_sync = deasync(inner.raw_get_key_ttl);
 const delete_sync = deasync(inner.raw_delete);
 const batch_get_sync = deasync(inner.raw_batch_get);
 const batch_put_sync = deasync(inner.raw_batch_put);
 const batch_delete_sync = deasync(inner.raw_batch_delete);
 const delete_range_sync = deasync(inner.raw_delete_range);
+const checksum_sync = deasync(inner.raw_checksum);
+const coprocessor_sync = deasync(inner.raw_coprocessor);
+const locate_key_sync = deasync(inner.raw_locate_key);
+const regions_sync = deasync(inner.raw_regions);
+const stores_sync = deasync(inner.raw_stores);
 const scan_sync = deasync(inner.raw_scan);
 const scan_keys_sync = deasync(inner.raw_scan_keys);
+const batch_scan_sync = deasync(inner.raw_batch_scan);
+const batch_scan_keys_sync = deasync(inner.raw_batch_scan_keys);
+const train_dictionary_sync = deasync(inner.raw_train_dictionary);
 const txn_connect_sync = deasync(inner.txn_connect);
 const txn_begin_sync = deasync(inner.txn_begin);
+const txn_begin_read_only_sync = deasync(inner.txn_begin_read_only);
 const txn_snapshot_sync = deasync(inner.txn_snapshot);
+const txn_snapshot_stale_sync = deasync(inner.txn_snapshot_stale);
+const txn_snapshot_at_time_sync = deasync(inner.txn_snapshot_at_time);
 const txn_current_timestamp_sync = deasync(inner.txn_current_timestamp);
 const txn_gc_sync = deasync(inner.txn_gc);
+const txn_scan_locks_sync = deasync(inner.txn_scan_locks);
+const txn_cleanup_locks_sync = deasync(inner.txn_cleanup_locks);
+const txn_locate_key_sync = deasync(inner.txn_locate_key);
+const txn_regions_sync = deasync(inner.txn_regions);
+const txn_stores_sync = deasync(inner.txn_stores);
 const txn_get_sync = deasync(inner.txn_get);
 const txn_get_for_update_sync = deasync(inner.txn_get_for_update);
 const txn_key_exists_sync = deasync(inner.txn_key_exists);
@@ -40,6 +215,14 @@ const txn_lock_keys_sync = deasync(inner.txn_lock_keys);
 const txn_put_sync = deasync(inner.txn_put);
 const txn_insert_sync = deasync(inner.txn_insert);
 const txn_delete_sync = deasync(inner.txn_delete);
+const txn_put_if_equals_sync = deasync(inner.txn_put_if_equals);
+const txn_put_if_absent_sync = deasync(inner.txn_put_if_absent);
+const txn_delete_if_equals_sync = deasync(inner.txn_delete_if_equals);
+const txn_savepoint_sync = deasync(inner.txn_savepoint);
+const txn_rollback_to_sync = deasync(inner.txn_rollback_to);
+const txn_pending_mutations_sync = deasync(inner.txn_pending_mutations);
+const txn_mutation_count_sync = deasync(inner.txn_mutation_count);
+const txn_mutation_bytes_sync = deasync(inner.txn_mutation_bytes);
 const txn_commit_sync = deasync(inner.txn_commit);
 const snapshot_get_sync = deasync(inner.snapshot_get);
 const snapshot_key_exists_sync = deasync(inner.snapshot_key_exists);
@@ -47,144 +230,408 @@ const snapshot_batch_get_sync = deasync(inner.snapshot_batch_get);
 const snapshot_scan_sync = deasync(inner.snapshot_scan);
 const snapshot_scan_keys_sync = deasync(inner.snapshot_scan_keys);
 
+// Hand the trace context to the request created by `request`, which runs the native function.
+function traced<T>(traceparent: string | undefined, request: () => T): T {
+  inner.set_trace_context(traceparent);
+  try {
+    return request();
+  } finally {
+    inner.set_trace_context(undefined);
+  }
+}
+
 export class RawClient {
   boxed: any;
   /**
    * Construct a raw client.
    * @class RawClient
-   * @param { string } pd_endpoint - PD endpoint
+   * @param { string } pd_endpoint - PD endpoint, or `mock://<name>` for an in-process mock store
    * @example new tikv.RawClient("127.0.0.1:2379")
+   * @example new tikv.RawClient("mock://")
    */
   constructor(pd_endpoint: string) {
     this.boxed = raw_connect_sync(pd_endpoint);
   }
 
+  /**
+   * A client for the keys starting with `prefix`. Keys and ranges passed to it are relative to
+   * the prefix, which is stripped from the keys it returns, so it never sees other keys.
+   * @param { Key } prefix - key prefix
+   * @example const users = client.subspace("users/"); users.put("alice", "value", "default")
+   * @returns { RawClient }
+   */
+  subspace(prefix: Key): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_subspace.call(this.boxed, prefix);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection which converts values with `codec`.
+   * @param { Codec } codec - value codec
+   * @example const json = client.with_codec("json"); json.put("key", { a: 1 }, "default")
+   * @returns { RawClient }
+   */
+  with_codec(codec: Codec): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_codec.call(this.boxed, codec);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection which returns the keys it reads as `key_type`.
+   * @param { KeyType } key_type - key type
+   * @example client.with_key_type("buffer").scan_keys("a", "b", 10, true, false, "default")
+   * @returns { RawClient }
+   */
+  with_key_type(key_type: KeyType): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_key_type.call(this.boxed, key_type);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection which compresses the values it writes. It reads both
+   * compressed and uncompressed values, so it can be turned on for existing data.
+   * @param { CompressionOptions } options - compression options
+   * @example const compressed = client.with_compression({ algorithm: "zstd", min_size: 256 })
+   * @returns { RawClient }
+   */
+  with_compression(options: CompressionOptions): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_compression.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection which encrypts the values it writes with AES-256-GCM,
+   * and decrypts the values it reads. Keys are not encrypted, but authenticated as this client
+   * sees them: values written by the encrypted client of a subspace are only readable through
+   * one derived the same way, so prefer `with_encryption(...).subspace(prefix)`.
+   * @param { EncryptionOptions } options - encryption options
+   * @example const secrets = client.with_encryption({ key_id: "2021-06", key_provider: (id) => keys[id] })
+   * @returns { RawClient }
+   */
+  with_encryption(options: EncryptionOptions): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_encryption.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * Derive a client which coalesces gets issued close together into batch gets, each caller
+   * receiving the value of its own key. Synchronous gets never overlap, so this pays off with
+   * the asynchronous client.
+   * @param { CoalescingOptions } options - coalescing options
+   * @example const loader = client.with_coalescing({ window_ms: 2 })
+   * @returns { RawClient }
+   */
+  with_coalescing(options: CoalescingOptions = {}): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_coalescing.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * Derive a client which caches the values `get` and `batch_get` read, by column family and key.
+   * Writes through the derived client invalidate its cache; writes by other clients are seen once
+   * cached values expire.
+   * @param { CacheOptions } options - cache options
+   * @example const config = client.with_cache({ capacity: 1000, ttl_ms: 5000 })
+   * @returns { RawClient }
+   */
+  with_cache(options: CacheOptions = {}): RawClient {
+    const client = Object.create(RawClient.prototype);
+    client.boxed = inner.raw_with_cache.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * Train a zstd dictionary from the values of a sample of keys, for `with_compression`.
+   * @param { Key[] } keys - sample keys
+   * @param { number } max_size - maximum size of the dictionary in bytes
+   * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example const dictionary = client.train_dictionary(["doc/1", "doc/2", "doc/3"], 16384, "default")
+   * @returns { Buffer }
+   */
+  train_dictionary(keys: Key[], max_size: number, cf: string, traceparent?: string): Buffer {
+    return traced(traceparent, () => train_dictionary_sync.call(this.boxed, keys, max_size, cf));
+  }
+
   /**
    * Get a raw key.
-   * @param { string } key - raw key
+   * @param { Key } key - raw key
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.get("key", "default")
    */
-  get(key: string, cf: string) {
-    return get_sync.call(this.boxed, key, cf);
+  get(key: Key, cf: string, traceparent?: string) {
+    return traced(traceparent, () => get_sync.call(this.boxed, key, cf));
   }
 
   /**
    * Put a raw key.
-   * @param { string } key - raw key
-   * @param { string } value - raw value
+   * @param { Key } key - raw key
+   * @param { any } value - raw value, converted with the codec of the client
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.put("key", "value", "default")
    */
-  put(key: string, value: string, cf: string) {
-    return put_sync.call(this.boxed, key, value, cf);
+  put(key: Key, value: any, cf: string, traceparent?: string) {
+    return traced(traceparent, () => put_sync.call(this.boxed, key, value, cf));
+  }
+
+  /**
+   * Put a raw key which expires after a time to live. TiKV must have TTLs enabled.
+   * @param { Key } key - raw key
+   * @param { any } value - raw value, converted with the codec of the client
+   * @param { number } ttl_secs - seconds until the key is deleted, 0 for never
+   * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example client.put_with_ttl("session", "value", 60, "default")
+   */
+  put_with_ttl(key: Key, value: any, ttl_secs: number, cf: string, traceparent?: string) {
+    return traced(traceparent, () =>
+      put_with_ttl_sync.call(this.boxed, key, value, ttl_secs, cf)
+    );
+  }
+
+  /**
+   * Get the seconds left before a raw key expires.
+   * @param { Key } key - raw key
+   * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example client.get_key_ttl("session", "default") //=> 60
+   * @returns { number | undefined } 0 if it never expires, undefined if the key doesn't exist
+   */
+  get_key_ttl(key: Key, cf: string, traceparent?: string): number | undefined {
+    return traced(traceparent, () => get_key_ttl_sync.call(this.boxed, key, cf));
   }
 
   /**
    * Delete a raw key.
-   * @param { string } key - raw key
+   * @param { Key } key - raw key
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.delete("key", "default")
    * @returns { boolean }
    */
-  delete(key: string, cf: string): boolean {
-    return delete_sync.call(this.boxed, key, cf);
+  delete(key: Key, cf: string, traceparent?: string): boolean {
+    return traced(traceparent, () => delete_sync.call(this.boxed, key, cf));
   }
 
   /**
    * Batch get raw keys.
-   * @param { string[] } keys - raw keys
+   * @param { Key[] } keys - raw keys
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.batch_get(["key1", "key2"], "default")
    */
-  batch_get(keys: string[], cf: string) {
-    return batch_get_sync.call(this.boxed, keys, cf);
+  batch_get(keys: Key[], cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_get_sync.call(this.boxed, keys, cf));
   }
 
   /**
    * Batch put raw keys.
-   * @param { string[] } keys - raw keys
+   * @param { Key[] } keys - raw keys
    * @param { string[] } values - raw values
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.batch_put(["key1", "key2"], ["value1", "value2"], "default")
    */
-  batch_put(keys: string[], cf: string) {
-    return batch_put_sync.call(this.boxed, keys, cf);
+  batch_put(keys: Key[], cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_put_sync.call(this.boxed, keys, cf));
   }
 
   /**
    * Batch delete raw keys.
-   * @param { string[] } keys - raw keys
+   * @param { Key[] } keys - raw keys
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.batch_delete(["key1", "key2"], "default")
    */
-  batch_delete(keys: string[], cf: string) {
-    return batch_delete_sync.call(this.boxed, keys, cf);
+  batch_delete(keys: Key[], cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_delete_sync.call(this.boxed, keys, cf));
   }
 
   /**
    * Create a new 'scan' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { number } limit - limit
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.scan("k1", "k5", 10, true, true, "default");
    */
-  scan(start: string, end: string, limit: number, include_start: boolean, include_end: boolean, cf: string) {
-    return scan_sync.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end,
-      cf
+  scan(start: Key, end: Key, limit: number, include_start: boolean, include_end: boolean, cf: string, traceparent?: string) {
+    return traced(traceparent, () =>
+      scan_sync.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end,
+        cf
+      )
     );
   }
 
   /**
    * Create a new 'scan_keys' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { number } limit - limit
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.scan_keys("k1", "k5", 10, true, true, "default");
    */
-  scan_keys(start: string, end: string, limit: number, include_start: boolean, include_end: boolean, cf: string) {
-    return scan_keys_sync.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end,
-      cf
+  scan_keys(start: Key, end: Key, limit: number, include_start: boolean, include_end: boolean, cf: string, traceparent?: string) {
+    return traced(traceparent, () =>
+      scan_keys_sync.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end,
+        cf
+      )
     );
   }
 
+  /**
+   * Scan several ranges in one request. Each range includes its start and excludes its end.
+   * @param { [Key, Key][] } ranges - start and end keys of the ranges
+   * @param { number } each_limit - limit of each range
+   * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example client.batch_scan([["a", "b"], ["x", "y"]], 10, "default");
+   * //=> [[["a1", "v1"]], [["x1", "v2"], ["x2", "v3"]]]
+   * @returns { Array[] } the pairs of each range
+   */
+  batch_scan(ranges: [Key, Key][], each_limit: number, cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_scan_sync.call(this.boxed, ranges, each_limit, cf));
+  }
+
+  /**
+   * Scan the keys of several ranges in one request. Each range includes its start and excludes
+   * its end.
+   * @param { [Key, Key][] } ranges - start and end keys of the ranges
+   * @param { number } each_limit - limit of each range
+   * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example client.batch_scan_keys([["a", "b"], ["x", "y"]], 10, "default");
+   * //=> [["a1"], ["x1", "x2"]]
+   * @returns { Key[][] } the keys of each range
+   */
+  batch_scan_keys(ranges: [Key, Key][], each_limit: number, cf: string, traceparent?: string) {
+    return traced(traceparent, () => batch_scan_keys_sync.call(this.boxed, ranges, each_limit, cf));
+  }
+
   /**
    * Create a new 'delete_range' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
    * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example client.delete_range("k1", "k5", true, true, "default");
    */
-  delete_range(start: string, end: string, include_start: boolean, include_end: boolean, cf: string) {
-    return delete_range_sync.call(
-      this.boxed,
-      start,
-      end,
-      include_start,
-      include_end,
-      cf
+  delete_range(start: Key, end: Key, include_start: boolean, include_end: boolean, cf: string, traceparent?: string) {
+    return traced(traceparent, () =>
+      delete_range_sync.call(
+        this.boxed,
+        start,
+        end,
+        include_start,
+        include_end,
+        cf
+      )
+    );
+  }
+
+  /**
+   * Checksum the pairs in a range, to check that two ranges hold the same data.
+   * @param { Key } start - start key
+   * @param { Key } end - end key
+   * @param { boolean } include_start - include start key
+   * @param { boolean } include_end - include end key
+   * @param { string } cf - configuration
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example client.checksum("k1", "k5", true, false, "default");
+   * //=> { crc64: 14631796525467209337n, total_kvs: 4, total_bytes: 48 }
+   * @returns { Checksum }
+   */
+  checksum(start: Key, end: Key, include_start: boolean, include_end: boolean, cf: string, traceparent?: string): Checksum {
+    return traced(traceparent, () =>
+      checksum_sync.call(
+        this.boxed,
+        start,
+        end,
+        include_start,
+        include_end,
+        cf
+      )
     );
   }
+
+  /**
+   * Send a request to a raw coprocessor plugin on every region covering the ranges. Each range
+   * includes its start and excludes its end.
+   * @param { string } name - name of the coprocessor plugin
+   * @param { string } version - version requirement of the plugin, like "^1.0"
+   * @param { [Key, Key][] } ranges - start and end keys of the ranges
+   * @param { Buffer } request - request sent to every region
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example client.coprocessor("example-plugin", "^1.0", [["a", "z"]], Buffer.from("count"));
+   * //=> [{ data: <Buffer ...>, ranges: [["a", "m"]] }, { data: <Buffer ...>, ranges: [["m", "z"]] }]
+   * @returns { CoprocessorResponse[] }
+   */
+  coprocessor(name: string, version: string, ranges: [Key, Key][], request: Buffer, traceparent?: string): CoprocessorResponse[] {
+    return traced(traceparent, () => coprocessor_sync.call(this.boxed, name, version, ranges, request));
+  }
+
+  /**
+   * Locate the region holding a key.
+   * @param { Key } key - key to locate
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example
+   * const client = new tikv.RawClient("127.0.0.1:2379");
+   * client.locate_key("k1");
+   * //=> { id: 2, start_key: "", end_key: "t", epoch: { conf_ver: 5, version: 3 }, leader_store_id: 1, peers: [...] }
+   * @returns { Region }
+   */
+  locate_key(key: Key, traceparent?: string): Region {
+    return traced(traceparent, () => locate_key_sync.call(this.boxed, key));
+  }
+
+  /**
+   * List the regions overlapping a range, in order. The range includes its start and excludes
+   * its end; an empty end means the end of the key space.
+   * @param { Key } start - start key
+   * @param { Key } end - end key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { Region[] }
+   */
+  regions(start: Key, end: Key, traceparent?: string): Region[] {
+    return traced(traceparent, () => regions_sync.call(this.boxed, start, end));
+  }
+
+  /**
+   * List the stores of the cluster and their addresses.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { Store[] }
+   */
+  stores(traceparent?: string): Store[] {
+    return traced(traceparent, () => stores_sync.call(this.boxed));
+  }
 }
 
 export class Transaction {
@@ -199,100 +646,245 @@ export class Transaction {
     this.boxed = boxed;
   }
 
+  /**
+   * A view of this transaction restricted to the keys starting with `prefix`. Keys and ranges
+   * passed to it are relative to the prefix, which is stripped from the keys it returns.
+   * Committing it commits this transaction.
+   * @param { Key } prefix - key prefix
+   * @example txn.subspace("users/").put("alice", "value")
+   * @returns { Transaction }
+   */
+  subspace(prefix: Key): Transaction {
+    return new Transaction(inner.txn_subspace.call(this.boxed, prefix));
+  }
+
+  /**
+   * A view of this transaction which converts values with `codec`.
+   * @param { Codec } codec - value codec
+   * @example txn.with_codec("msgpack").put("key", [1, 2, 3])
+   * @returns { Transaction }
+   */
+  with_codec(codec: Codec): Transaction {
+    return new Transaction(inner.txn_with_codec.call(this.boxed, codec));
+  }
+
+  /**
+   * A view of this transaction which returns the keys it reads as `key_type`.
+   * @param { KeyType } key_type - key type
+   * @example txn.with_key_type("buffer").scan_keys("a", "b", 10, true, false)
+   * @returns { Transaction }
+   */
+  with_key_type(key_type: KeyType): Transaction {
+    return new Transaction(inner.txn_with_key_type.call(this.boxed, key_type));
+  }
+
   /**
    * Create a new 'get' request.
-   * @param { string } key - key
+   * @param { Key } key - key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.get("key")
    */
-  get(key: string) {
-    return txn_get_sync.call(this.boxed, key);
+  get(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_get_sync.call(this.boxed, key));
   }
 
   /**
    * Create a `get for update` request.
-   * @param { string } key - key
+   * @param { Key } key - key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.get_for_update("key")
    * txn.commit()
    */
-  get_for_update(key: string) {
-    return txn_get_for_update_sync.call(this.boxed, key);
+  get_for_update(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_get_for_update_sync.call(this.boxed, key));
   }
 
   /**
    * Create a new 'put' request.
-   * @param { string } key - key
-   * @param { string } value - value
+   * @param { Key } key - key
+   * @param { any } value - value, converted with the codec of the transaction
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.put("key", "value")
    * txn.commit()
    */
-  put(key: string, value: string) {
-    return txn_put_sync.call(this.boxed, key, value);
+  put(key: Key, value: any, traceparent?: string) {
+    return traced(traceparent, () => txn_put_sync.call(this.boxed, key, value));
   }
 
   /**
    * Create a new 'insert' request.
-   * @param { string } key - key
-   * @param { string } value - value
+   * @param { Key } key - key
+   * @param { any } value - value, converted with the codec of the transaction
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.insert("key", "value")
    * txn.commit()
    */
-  insert(key: string, value: string) {
-    return txn_insert_sync.call(this.boxed, key, value);
+  insert(key: Key, value: any, traceparent?: string) {
+    return traced(traceparent, () => txn_insert_sync.call(this.boxed, key, value));
   }
 
   /**
    * Create a new 'delete' request.
-   * @param { string } key - key
+   * @param { Key } key - key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.delete("key")
    * txn.commit()
    */
-  delete(key: string) {
-    return txn_delete_sync.call(this.boxed, key);
+  delete(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_delete_sync.call(this.boxed, key));
+  }
+
+  /**
+   * Put a value if the key holds the expected one, reading it with `get_for_update` so that a
+   * pessimistic transaction locks the key. JSON and MessagePack values are compared as decoded,
+   * whatever the order of object fields. Values of other codecs, custom ones included, are
+   * compared byte by byte as encoded.
+   * @param { Key } key - key
+   * @param { any } expected - expected value
+   * @param { any } value - value
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { boolean } whether the value was put
+   * @example
+   * const txn = client.begin(true);
+   * txn.put_if_equals("balance", "100", "80"); //=> true
+   * txn.commit();
+   */
+  put_if_equals(key: Key, expected: any, value: any, traceparent?: string): boolean {
+    return traced(traceparent, () =>
+      txn_put_if_equals_sync.call(this.boxed, key, expected, value)
+    );
+  }
+
+  /**
+   * Put a value if the key holds none, reading it with `get_for_update` so that a pessimistic
+   * transaction locks the key.
+   * @param { Key } key - key
+   * @param { any } value - value
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { boolean } whether the value was put
+   */
+  put_if_absent(key: Key, value: any, traceparent?: string): boolean {
+    return traced(traceparent, () => txn_put_if_absent_sync.call(this.boxed, key, value));
+  }
+
+  /**
+   * Delete a key if it holds the expected value, reading it with `get_for_update` so that a
+   * pessimistic transaction locks the key. Values are compared as by `put_if_equals`.
+   * @param { Key } key - key
+   * @param { any } expected - expected value
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { boolean } whether the key was deleted
+   */
+  delete_if_equals(key: Key, expected: any, traceparent?: string): boolean {
+    return traced(traceparent, () => txn_delete_if_equals_sync.call(this.boxed, key, expected));
+  }
+
+  /**
+   * Set a savepoint, to undo the writes issued after it with `rollback_to`. Setting a savepoint
+   * with the name of an existing one moves it. Writes after a savepoint are kept by the client and
+   * sent at commit, so a pessimistic transaction locks their keys only then.
+   * @param { string } name - name of the savepoint
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example
+   * txn.put("a", "1");
+   * txn.savepoint("before_b");
+   * txn.put("b", "2");
+   * txn.rollback_to("before_b");
+   * txn.commit(); // writes only "a"
+   */
+  savepoint(name: string, traceparent?: string) {
+    return traced(traceparent, () => txn_savepoint_sync.call(this.boxed, name));
+  }
+
+  /**
+   * Discard the puts, inserts, deletes and key locks issued since a savepoint, and the savepoints
+   * set after it. The savepoint itself is kept.
+   * @param { string } name - name of the savepoint
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   */
+  rollback_to(name: string, traceparent?: string) {
+    return traced(traceparent, () => txn_rollback_to_sync.call(this.boxed, name));
+  }
+
+  /**
+   * List the writes the transaction will send at commit, one per key in key order: the last put,
+   * insert or delete of each key, or a lock for keys only locked.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { PendingMutation[] }
+   * @example
+   * txn.put("a", "1");
+   * txn.delete("b");
+   * txn.pending_mutations(); // [{ type: "put", key: "a", value: "1" }, { type: "delete", key: "b" }]
+   */
+  pending_mutations(traceparent?: string): PendingMutation[] {
+    return traced(traceparent, () => txn_pending_mutations_sync.call(this.boxed));
+  }
+
+  /**
+   * Count the keys the transaction will write or lock at commit.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { number }
+   */
+  mutation_count(traceparent?: string): number {
+    return traced(traceparent, () => txn_mutation_count_sync.call(this.boxed));
+  }
+
+  /**
+   * Sum the sizes of the keys and values the transaction will write at commit, as encoded by the
+   * codec and before compression or encryption.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { number }
+   */
+  mutation_bytes(traceparent?: string): number {
+    return traced(traceparent, () => txn_mutation_bytes_sync.call(this.boxed));
   }
 
   /**
    * Create a new 'commit' request.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * //... Do some actions.
    * txn.commit()
    */
-  commit() {
-    return txn_commit_sync.call(this.boxed);
+  commit(traceparent?: string) {
+    return traced(traceparent, () => txn_commit_sync.call(this.boxed));
   }
 
   /**
    * Check whether a key exists.
-   * @param { string } key - key
+   * @param { Key } key - key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.exists("key")
    * txn.commit()
    */
-  key_exists(key: string) {
-    return txn_key_exists_sync.call(this.boxed, key);
+  key_exists(key: Key, traceparent?: string) {
+    return traced(traceparent, () => txn_key_exists_sync.call(this.boxed, key));
   }
 
   /**
    * Create a new 'batch get' request.
-   * @param { string[] } keys - keys
+   * @param { Key[] } keys - keys
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
@@ -300,13 +892,14 @@ export class Transaction {
    * txn.commit()
    * //=> [{key: "key1", value: "value1"}, {key: "key2", value: "value2"}]
    */
-  batch_get(keys: string[]) {
-    return txn_batch_get_sync.call(this.boxed, keys);
+  batch_get(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => txn_batch_get_sync.call(this.boxed, keys));
   }
 
   /**
    * Create a new 'batch get for update' request.
-   * @param { string[] } keys - keys
+   * @param { Key[] } keys - keys
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
@@ -314,41 +907,45 @@ export class Transaction {
    * txn.commit()
    * //=> [{key: "key1", value: "value1"}, {key: "key2", value: "value2"}]
    */
-  batch_get_for_update(keys: string[]) {
-    return txn_batch_get_for_update_sync.call(this.boxed, keys);
+  batch_get_for_update(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => txn_batch_get_for_update_sync.call(this.boxed, keys));
   }
 
   /**
    * Create a new 'scan' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { number } limit - limit
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.scan("start", "end", 10, true, true)
    * txn.commit()
    */
-  scan(start: string, end: string, limit: number, include_start: boolean, include_end: boolean) {
-    return txn_scan_sync.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+  scan(start: Key, end: Key, limit: number, include_start: boolean, include_end: boolean, traceparent?: string) {
+    return traced(traceparent, () =>
+      txn_scan_sync.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 
   /**
    * Create a new 'scan keys' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { number } limit - limit
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
@@ -356,28 +953,31 @@ export class Transaction {
    * txn.commit()
    * //=> ["key1", "key2"]
    */
-  scan_keys(start: string, end: string, limit: number, include_start: boolean, include_end: boolean) {
-    return txn_scan_keys_sync.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+  scan_keys(start: Key, end: Key, limit: number, include_start: boolean, include_end: boolean, traceparent?: string) {
+    return traced(traceparent, () =>
+      txn_scan_keys_sync.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 
   /**
    * Create a new 'lock keys' request.
-   * @param { string[] } keys - keys
+   * @param { Key[] } keys - keys
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.lock_keys(["key1", "key2"])
    * txn.commit()
    */
-  lock_keys(keys: string[]) {
-    return txn_lock_keys_sync.call(this.boxed, keys);
+  lock_keys(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => txn_lock_keys_sync.call(this.boxed, keys));
   }
 }
 
@@ -390,151 +990,586 @@ export class Snapshot {
     this.boxed = boxed;
   }
 
+  /**
+   * A view of this snapshot restricted to the keys starting with `prefix`. Keys and ranges
+   * passed to it are relative to the prefix, which is stripped from the keys it returns.
+   * @param { Key } prefix - key prefix
+   * @example snapshot.subspace("users/").get("alice")
+   * @returns { Snapshot }
+   */
+  subspace(prefix: Key): Snapshot {
+    return new Snapshot(inner.snapshot_subspace.call(this.boxed, prefix));
+  }
+
+  /**
+   * A view of this snapshot which converts values with `codec`.
+   * @param { Codec } codec - value codec
+   * @example snapshot.with_codec("json").get("key")
+   * @returns { Snapshot }
+   */
+  with_codec(codec: Codec): Snapshot {
+    return new Snapshot(inner.snapshot_with_codec.call(this.boxed, codec));
+  }
+
+  /**
+   * A view of this snapshot which returns the keys it reads as `key_type`.
+   * @param { KeyType } key_type - key type
+   * @example snapshot.with_key_type("buffer").scan_keys("a", "b", 10, true, false)
+   * @returns { Snapshot }
+   */
+  with_key_type(key_type: KeyType): Snapshot {
+    return new Snapshot(inner.snapshot_with_key_type.call(this.boxed, key_type));
+  }
+
+  /**
+   * A view of this snapshot which remembers the values it reads, so reading them again doesn't go
+   * to TiKV. Values never change at the timestamp of a snapshot.
+   * @param { number } [capacity] - maximum number of values remembered, 10000 by default
+   * @example const cached = snapshot.with_cache()
+   * @returns { Snapshot }
+   */
+  with_cache(capacity?: number): Snapshot {
+    return new Snapshot(inner.snapshot_with_cache.call(this.boxed, capacity));
+  }
+
   /**
    * Create a new 'get' request.
-   * @param { string } key - key
+   * @param { Key } key - key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const snapshot = client.snapshot(client.current_timestamp(), true);
    * snapshot.get("key")
    * //=> {key: "key", value: "value"}
    */
-  get(key: string) {
-    return snapshot_get_sync.call(this.boxed, key);
+  get(key: Key, traceparent?: string) {
+    return traced(traceparent, () => snapshot_get_sync.call(this.boxed, key));
   }
 
   /**
    * Create a new 'key exists' request.
-   * @param { string } key - key
+   * @param { Key } key - key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const snapshot = client.snapshot(client.current_timestamp(), true);
    * snapshot.key_exists("key")
    * //=> true
    */
-  key_exists(key: string) {
-    return snapshot_key_exists_sync.call(this.boxed, key);
+  key_exists(key: Key, traceparent?: string) {
+    return traced(traceparent, () => snapshot_key_exists_sync.call(this.boxed, key));
   }
 
   /**
    * Create a new 'batch get' request.
-   * @param { string[] } keys - keys
+   * @param { Key[] } keys - keys
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const snapshot = client.snapshot(client.current_timestamp(), true);
    * snapshot.batch_get(["key1", "key2"])
    * //=> [{key: "key1", value: "value1"}, {key: "key2", value: "value2"}]
    */
-  batch_get(keys: any) {
-    return snapshot_batch_get_sync.call(this.boxed, keys);
+  batch_get(keys: Key[], traceparent?: string) {
+    return traced(traceparent, () => snapshot_batch_get_sync.call(this.boxed, keys));
   }
 
   /**
    * Create a new 'scan' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { number } limit - limit
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const snapshot = client.snapshot(client.current_timestamp(), true);
    * snapshot.scan("start", "end", 10, true, true)
    * //=> [{key: "key1", value: "value1"}, {key: "key2", value: "value2"}]
    */
-  scan(start: string, end: string, limit: number, include_start: boolean, include_end: boolean) {
-    return snapshot_scan_sync.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+  scan(start: Key, end: Key, limit: number, include_start: boolean, include_end: boolean, traceparent?: string) {
+    return traced(traceparent, () =>
+      snapshot_scan_sync.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 
   /**
    * Create a new 'scan keys' request.
-   * @param { string } start - start key
-   * @param { string } end - end key
+   * @param { Key } start - start key
+   * @param { Key } end - end key
    * @param { number } limit - limit
    * @param { boolean } include_start - include start key
    * @param { boolean } include_end - include end key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const snapshot = client.snapshot(client.current_timestamp(), true);
    * snapshot.scan_keys("start", "end", 10, true, true)
    * //=> ["key1", "key2"]
    */
-  scan_keys(start: string, end: string, limit: number, include_start: boolean, include_end: boolean) {
-    return snapshot_scan_keys_sync.call(
-      this.boxed,
-      start,
-      end,
-      limit,
-      include_start,
-      include_end
+  scan_keys(start: Key, end: Key, limit: number, include_start: boolean, include_end: boolean, traceparent?: string) {
+    return traced(traceparent, () =>
+      snapshot_scan_keys_sync.call(
+        this.boxed,
+        start,
+        end,
+        limit,
+        include_start,
+        include_end
+      )
     );
   }
 }
 
+/**
+ * Runs GC in the background until stopped, emitting a "run" event with a `GcRun` after each run.
+ * It stops as well once garbage collected, so keep a reference to it.
+ */
+export class GcScheduler extends EventEmitter {
+  boxed: any;
+
+  /**
+   * Stop running GC. A run in progress still completes.
+   */
+  stop() {
+    inner.gc_scheduler_stop.call(this.boxed);
+  }
+}
+
 export class TransactionClient {
   boxed: any;
   /**
    * @class TransactionClient
-   * @param { string } pd_endpoint - PD endpoint
+   * @param { string } pd_endpoint - PD endpoint, or `mock://<name>` for an in-process mock store
    * @example const client = new tikv.TransactionClient("127.0.0.1:2379");
+   * @example const client = new tikv.TransactionClient("mock://");
    */
   constructor(pd_endpoint: string) {
     this.boxed = txn_connect_sync(pd_endpoint);
   }
 
+  /**
+   * A client sharing this connection whose transactions and snapshots convert values with `codec`.
+   * @param { Codec } codec - value codec
+   * @example const client = new tikv.TransactionClient("127.0.0.1:2379").with_codec("json");
+   * @returns { TransactionClient }
+   */
+  with_codec(codec: Codec): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_codec.call(this.boxed, codec);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection whose transactions and snapshots return the keys they read,
+   * and whose lock and region listings return their keys, as `key_type`.
+   * @param { KeyType } key_type - key type
+   * @example const client = new tikv.TransactionClient("127.0.0.1:2379").with_key_type("buffer");
+   * @returns { TransactionClient }
+   */
+  with_key_type(key_type: KeyType): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_key_type.call(this.boxed, key_type);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection whose transactions and snapshots compress the values
+   * they write, and read both compressed and uncompressed values.
+   * @param { CompressionOptions } options - compression options
+   * @example const client = new tikv.TransactionClient("127.0.0.1:2379").with_compression({ algorithm: "lz4" });
+   * @returns { TransactionClient }
+   */
+  with_compression(options: CompressionOptions): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_compression.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * A client sharing this connection whose transactions and snapshots encrypt the values
+   * they write with AES-256-GCM, and decrypt the values they read. Keys are not encrypted.
+   * @param { EncryptionOptions } options - encryption options
+   * @example const client = new tikv.TransactionClient("127.0.0.1:2379").with_encryption({ key_id: "2021-06", key_provider: (id) => keys[id] });
+   * @returns { TransactionClient }
+   */
+  with_encryption(options: EncryptionOptions): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_encryption.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * Derive a client whose transactions coalesce gets issued close together into batch gets.
+   * @param { CoalescingOptions } options - coalescing options
+   * @example const client = new tikv.TransactionClient("127.0.0.1:2379").with_coalescing({ window_ms: 2 });
+   * @returns { TransactionClient }
+   */
+  with_coalescing(options: CoalescingOptions = {}): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_coalescing.call(this.boxed, options);
+    return client;
+  }
+
+  /**
+   * Derive a client whose transactions are held to limits. A put, insert or delete which would
+   * exceed one throws a `TransactionLimitError` and is not applied, instead of the commit failing.
+   * Limits which are negative or not finite throw a `RangeError`.
+   * @param { TransactionLimits } limits - transaction limits
+   * @example const client = new tikv.TransactionClient("127.0.0.1:2379").with_limits({ max_keys: 10000, max_duration_ms: 30000 });
+   * @returns { TransactionClient }
+   */
+  with_limits(limits: TransactionLimits): TransactionClient {
+    const client = Object.create(TransactionClient.prototype);
+    client.boxed = inner.txn_client_with_limits.call(this.boxed, limits);
+    return client;
+  }
+
   /**
    * Create a new 'begin' request.
    * @param { boolean } pessimistic - pessimistic
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const txn = client.begin(true);
    * txn.commit()
    */
-  begin(pessimistic: boolean) {
-    return new Transaction(txn_begin_sync.call(this.boxed, pessimistic));
+  begin(pessimistic: boolean, traceparent?: string) {
+    return new Transaction(traced(traceparent, () => txn_begin_sync.call(this.boxed, pessimistic)));
+  }
+
+  /**
+   * Begin a transaction which only reads. Writes and locks fail at once without reaching TiKV, and
+   * committing it only ends it.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example
+   * const client = new tikv.TransactionClient("127.0.0.1:2379");
+   * const txn = client.begin_read_only();
+   * txn.get("key");
+   * txn.commit()
+   */
+  begin_read_only(traceparent?: string) {
+    return new Transaction(traced(traceparent, () => txn_begin_read_only_sync.call(this.boxed)));
   }
 
   /**
    * Create a new Snapshot
    * @param { number } timestamp - timestamp
    * @param { boolean } pessimistic - pessimistic
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @param { boolean } [follower_read] - read from the followers of each region
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const snapshot = client.snapshot(client.current_timestamp(), true);
    */
-  snapshot(timestamp: number, pessimistic: boolean) {
+  snapshot(timestamp: number, pessimistic: boolean, traceparent?: string, follower_read?: boolean) {
     return new Snapshot(
-      txn_snapshot_sync.call(this.boxed, timestamp, pessimistic)
+      traced(traceparent, () =>
+        txn_snapshot_sync.call(this.boxed, timestamp, pessimistic, follower_read === true)
+      )
+    );
+  }
+
+  /**
+   * Create a snapshot of the data as it was some seconds ago, by the clock of PD. Use it for reads
+   * which may be slightly stale, like analytics.
+   * @param { number } seconds - how far in the past to read
+   * @param { boolean } pessimistic - pessimistic
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @param { boolean } [follower_read] - read from the followers of each region
+   * @example
+   * const client = new tikv.TransactionClient("127.0.0.1:2379");
+   * const snapshot = client.snapshot_stale(10, false);
+   * @returns { Snapshot }
+   */
+  snapshot_stale(seconds: number, pessimistic: boolean, traceparent?: string, follower_read?: boolean) {
+    return new Snapshot(
+      traced(traceparent, () =>
+        txn_snapshot_stale_sync.call(this.boxed, seconds, pessimistic, follower_read === true)
+      )
+    );
+  }
+
+  /**
+   * Create a snapshot of the data as it was at a point in time, which must not be after the
+   * current time of PD.
+   * @param { Date } date - time to read at
+   * @param { boolean } pessimistic - pessimistic
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @param { boolean } [follower_read] - read from the followers of each region
+   * @example
+   * const client = new tikv.TransactionClient("127.0.0.1:2379");
+   * const snapshot = client.snapshot_at(new Date("2021-06-01T00:00:00Z"), false);
+   * @returns { Snapshot }
+   */
+  snapshot_at(date: Date, pessimistic: boolean, traceparent?: string, follower_read?: boolean) {
+    return new Snapshot(
+      traced(traceparent, () =>
+        txn_snapshot_at_time_sync.call(this.boxed, date.getTime(), pessimistic, follower_read === true)
+      )
     );
   }
 
   /**
    * Retrieve the current [`Timestamp`].
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * const timestamp = client.current_timestamp();
    * //=> 1588888888
    */
-  current_timestamp() {
-    return txn_current_timestamp_sync.call(this.boxed);
+  current_timestamp(traceparent?: string) {
+    return traced(traceparent, () => txn_current_timestamp_sync.call(this.boxed));
   }
 
   /**
    * Request garbage collection (GC) of the TiKV cluster.
    * @param { number } safepoint - safe point
+   * @param { string } [traceparent] - W3C trace context to parent the request span
    * @example
    * const client = new tikv.TransactionClient("127.0.0.1:2379");
    * client.gc(1588888888);
    * //=> true
    */
-  gc(safepoint: number) {
-    return txn_gc_sync.call(this.boxed, safepoint);
+  gc(safepoint: number, traceparent?: string) {
+    return traced(traceparent, () => txn_gc_sync.call(this.boxed, safepoint));
+  }
+
+  /**
+   * Run GC at once and then every interval, with the safepoint set to the current timestamp less
+   * the life time. The safepoint never passes the start of a transaction of this process not yet
+   * committed, nor the timestamp of a snapshot of this process not yet garbage collected.
+   * @param { GcOptions } [options] - GC options
+   * @returns { GcScheduler }
+   * @example
+   * const gc = client.start_gc({ interval_ms: 600000, life_time_ms: 600000 });
+   * gc.on("run", (run) => console.log(run.safepoint, run.updated));
+   * gc.stop();
+   */
+  start_gc(options: GcOptions = {}): GcScheduler {
+    const scheduler = new GcScheduler();
+    scheduler.boxed = inner.txn_start_gc.call(this.boxed, options, (run: GcRun) =>
+      scheduler.emit("run", run)
+    );
+    return scheduler;
+  }
+
+  /**
+   * List the locks in a range held by transactions started up to now, in key order.
+   * @param { Key } start - start key, inclusive
+   * @param { Key } end - end key, exclusive, or "" for the end of the key space
+   * @param { number } limit - maximum number of locks
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { LockInfo[] }
+   * @example
+   * client.scan_locks("", "", 100);
+   * //=> [{ key: "k1", primary: "k1", start_ts: 426867018473635841, ttl: 3000 }]
+   */
+  scan_locks(start: Key, end: Key, limit: number, traceparent?: string): LockInfo[] {
+    return traced(traceparent, () => txn_scan_locks_sync.call(this.boxed, start, end, limit));
+  }
+
+  /**
+   * Resolve the expired locks in a range held by transactions started at or before a safe point.
+   * Each transaction is committed or rolled back by its primary lock, the way a reader meeting its
+   * locks would. Locks still within their TTL are left alone.
+   * @param { Key } start - start key, inclusive
+   * @param { Key } end - end key, exclusive, or "" for the end of the key space
+   * @param { number } safepoint - safe point
+   * @param { boolean } [dry_run] - only list the locks which would be resolved
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { LockInfo[] } the locks resolved
+   * @example
+   * client.cleanup_locks("", "", client.current_timestamp(), true);
+   */
+  cleanup_locks(
+    start: Key,
+    end: Key,
+    safepoint: number,
+    dry_run: boolean = false,
+    traceparent?: string
+  ): LockInfo[] {
+    return traced(traceparent, () =>
+      txn_cleanup_locks_sync.call(this.boxed, start, end, safepoint, dry_run)
+    );
+  }
+
+  /**
+   * Locate the region holding a key.
+   * @param { Key } key - key to locate
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @example
+   * const client = new tikv.TransactionClient("127.0.0.1:2379");
+   * client.locate_key("k1");
+   * //=> { id: 2, start_key: "", end_key: "t", epoch: { conf_ver: 5, version: 3 }, leader_store_id: 1, peers: [...] }
+   * @returns { Region }
+   */
+  locate_key(key: Key, traceparent?: string): Region {
+    return traced(traceparent, () => txn_locate_key_sync.call(this.boxed, key));
+  }
+
+  /**
+   * List the regions overlapping a range, in order. The range includes its start and excludes
+   * its end; an empty end means the end of the key space.
+   * @param { Key } start - start key
+   * @param { Key } end - end key
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { Region[] }
+   */
+  regions(start: Key, end: Key, traceparent?: string): Region[] {
+    return traced(traceparent, () => txn_regions_sync.call(this.boxed, start, end));
+  }
+
+  /**
+   * List the stores of the cluster and their addresses.
+   * @param { string } [traceparent] - W3C trace context to parent the request span
+   * @returns { Store[] }
+   */
+  stores(traceparent?: string): Store[] {
+    return traced(traceparent, () => txn_stores_sync.call(this.boxed));
+  }
+}
+
+/**
+ * Forward log records of the client, including those of the underlying TiKV client, to a JS
+ * function, e.g. to route them into pino. Clients connected before it is installed forward too.
+ * Records beyond `max_per_second` are dropped; the next record delivered carries the number of
+ * dropped records in `fields.dropped_records`.
+ * @param { Function | null } logger - called with `{ level, target, message, fields }`, or null to stop logging
+ * @param { string } level - one of "off", "error", "warn", "info", "debug", "trace"
+ * @param { number } [max_per_second=100] - maximum number of records delivered per second
+ * @example
+ * const log = pino();
+ * tikv.set_logger((record) => log[record.level]({ target: record.target, ...record.fields }, record.message), "info");
+ */
+export function set_logger(
+  logger: ((record: { level: string; target: string; message: string; fields: { [key: string]: string } }) => void) | null,
+  level: string,
+  max_per_second?: number
+) {
+  inner.set_logger(logger, level, max_per_second);
+}
+
+/**
+ * Render request counters and latency histograms, by method, column family and error kind,
+ * in the Prometheus text exposition format.
+ * @example
+ * app.get("/metrics", (req, res) => res.type("text/plain").send(tikv.get_metrics()));
+ * @returns { string }
+ */
+export function get_metrics(): string {
+  return inner.get_metrics();
+}
+
+/**
+ * Make requests of an exported function fail or stall, for testing retry logic.
+ * Only available when built with the `failpoints` feature (`npm run build-failpoints`).
+ * @param { string } name - name of the native function, e.g. "txn_commit" or "raw_get"
+ * @param { Object } options - `error` to fail with (e.g. "WriteConflict"), `delay_ms` to stall for,
+ * `count` of requests to affect (all requests when omitted)
+ * @example tikv.configure_failpoint("txn_commit", { error: "WriteConflict", count: 2 });
+ * @example tikv.configure_failpoint("raw_get", { delay_ms: 200 });
+ */
+export function configure_failpoint(
+  name: string,
+  options: { error?: string; delay_ms?: number; count?: number }
+) {
+  inner.failpoint_configure(name, options);
+}
+
+/**
+ * Remove a failpoint set by `configure_failpoint`.
+ * @param { string } name - name of the native function
+ * @example tikv.remove_failpoint("txn_commit");
+ */
+export function remove_failpoint(name: string) {
+  inner.failpoint_remove(name);
+}
+
+/**
+ * Export a span for every request, parented to the `traceparent` passed to the operation.
+ * Spans go to an OTLP collector over gRPC, or are written as JSON lines to a local file,
+ * which is handy in tests. `"none"` flushes and stops exporting.
+ * @param { Object } options - `exporter` ("otlp", "file" or "none"), `endpoint` of the OTLP
+ * collector (defaults to "http://localhost:4317"), `path` of the file, and `service_name`
+ * @example tikv.configure_tracing({ exporter: "otlp", endpoint: "http://otel-collector:4317", service_name: "api" });
+ * @example tikv.configure_tracing({ exporter: "file", path: "/tmp/spans.jsonl" });
+ */
+export function configure_tracing(options: {
+  exporter: "otlp" | "file" | "none";
+  endpoint?: string;
+  path?: string;
+  service_name?: string;
+}) {
+  inner.configure_tracing(options);
+}
+
+/**
+ * Emits a "slow" event for every request slower than the threshold set with
+ * `set_slow_log_threshold`. The record has the `method`, the `cf` of raw requests, the `key`,
+ * `key_count` or `start`/`end` it touched, `elapsed_ms`, and the `error` kind if it failed.
+ * @example tikv.slow_log.on("slow", (record) => log.warn(record, "slow TiKV request"));
+ */
+export const slow_log = new EventEmitter();
+
+/**
+ * Report requests taking at least `threshold_ms` milliseconds on `slow_log`.
+ * @param { number | null } threshold_ms - threshold, or null to stop reporting
+ * @example tikv.set_slow_log_threshold(100);
+ */
+export function set_slow_log_threshold(threshold_ms: number | null) {
+  if (threshold_ms === null) {
+    inner.set_slow_log(null);
+  } else {
+    inner.set_slow_log((record: any) => slow_log.emit("slow", record), threshold_ms);
+  }
+}
+
+export type TupleElement = null | boolean | number | bigint | string | Buffer | TupleElement[];
+
+/**
+ * Order-preserving encoding of tuples into keys, compatible with the FoundationDB tuple layer.
+ * Packed keys sort like their tuples: element by element, numbers and BigInts numerically,
+ * and strings and Buffers bytewise. Numbers are encoded as doubles and BigInts as integers,
+ * which sort before all doubles, so use one or the other for the same element of a tuple.
+ */
+export class Tuple {
+  /**
+   * Encode a tuple into a key.
+   * @param { TupleElement[] } tuple - elements
+   * @example client.put(tikv.Tuple.pack(["users", 42]), "alice", "default")
+   * @returns { Buffer }
+   */
+  static pack(tuple: TupleElement[]): Buffer {
+    return inner.tuple_pack(tuple);
+  }
+
+  /**
+   * Decode a key created by `pack`. Doubles are returned as numbers and integers as BigInts.
+   * @param { Key } key - packed key
+   * @example tikv.Tuple.unpack(key) //=> ["users", 42]
+   * @returns { TupleElement[] }
+   */
+  static unpack(key: Key): TupleElement[] {
+    return inner.tuple_unpack(key);
+  }
+
+  /**
+   * The bounds of the keys of all tuples extending `prefix`, to scan or delete with
+   * `include_start` true and `include_end` false.
+   * @param { TupleElement[] } prefix - leading elements
+   * @example client.scan(...tikv.Tuple.range(["users"]), 100, true, false, "default")
+   * @returns { Buffer[] } start and end
+   */
+  static range(prefix: TupleElement[]): [Buffer, Buffer] {
+    return inner.tuple_range(prefix);
   }
 }